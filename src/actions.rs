@@ -0,0 +1,204 @@
+use std::env;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use failure::Error;
+use crate::failures::split_location;
+use crate::report::{Report, State, SuggestionKind};
+
+/// GitHub only shows this many error annotations of a step, the rest are only in the summary
+pub const MAX_ANNOTATIONS: usize = 10;
+
+/// Whether to talk to GitHub Actions: annotations for the failures, and a job summary
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// When GITHUB_ACTIONS says we run there
+    Auto,
+    Always,
+    Never,
+}
+
+impl std::str::FromStr for Mode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Mode, String> {
+        match mode {
+            "auto" => Ok(Mode::Auto),
+            "always" => Ok(Mode::Always),
+            "never" => Ok(Mode::Never),
+            _ => Err(format!("unknown mode {}", mode)),
+        }
+    }
+}
+
+impl Mode {
+    pub fn enabled(self) -> bool {
+        match self {
+            Mode::Auto => env::var("GITHUB_ACTIONS").is_ok_and(|actions| actions == "true"),
+            Mode::Always => true,
+            Mode::Never => false,
+        }
+    }
+}
+
+/// Workflow commands which put the failures on the lines of the diff they're on, as many as
+/// GitHub shows, and a notice about the rest
+pub fn annotations(report: &Report) -> Vec<String> {
+    let mut annotations: Vec<_> = report.failures.iter().take(MAX_ANNOTATIONS).map(|failure| {
+        let mut properties = vec![];
+        if let Some((file, line, column)) = failure.failure.locations.first().and_then(|location| split_location(location)) {
+            properties.push(format!("file={}", escape_property(file)));
+            properties.push(format!("line={}", line));
+            properties.push(format!("col={}", column));
+        }
+        properties.push(format!("title={}", escape_property(&format!("Broken link: {}", failure.link))));
+        let mut message = failure.failure.message.clone();
+        if let Some(suggestion) = &failure.failure.suggestion {
+            message += &format!(", did you mean {}?", suggestion);
+        }
+        format!("::error {}::{}", properties.join(","), escape_data(&message))
+    }).collect();
+    if report.failures.len() > MAX_ANNOTATIONS {
+        let rest = report.failures.len() - MAX_ANNOTATIONS;
+        annotations.push(format!("::notice::{} more broken links, see the job summary", rest));
+    }
+    annotations
+}
+
+/// The run as markdown for the job page: the counts, then every failure and suggestion
+pub fn summary(report: &Report) -> String {
+    let counts = &report.summary;
+    let mut summary = String::from("## Link check\n\n| | |\n|---|---|\n");
+    summary += &format!("| Urls | {} in {} links |\n", counts.urls, counts.links);
+    summary += &format!("| Working | {} |\n", counts.working);
+    summary += &format!("| Failed | {} ({} permanent) |\n", counts.failed, counts.permanent);
+    let others = [
+        ("Temporarily unavailable", counts.unavailable),
+        ("Unverifiable", counts.unverifiable),
+        ("Need a login", counts.login_required),
+        ("Suspect", counts.suspect),
+        ("Not checked before the deadline", counts.unchecked),
+        ("Ignored", counts.ignored_links),
+    ];
+    for (what, count) in others.iter().filter(|(_, count)| *count > 0) {
+        summary += &format!("| {} | {} |\n", what, count);
+    }
+    summary += &format!("| Took | {:.1}s |\n", counts.duration_ms as f64 / 1000.0);
+    if !report.failures.is_empty() {
+        summary += &format!("\n### {} broken links\n\n| Link | Error | Where |\n|---|---|---|\n", report.failures.len());
+        for failure in &report.failures {
+            let mut error = failure.failure.message.clone();
+            if let Some(suggestion) = &failure.failure.suggestion {
+                error += &format!(", did you mean {}?", suggestion);
+            }
+            if let Some(snapshot) = failure.archived {
+                error += &format!(" (archived copy: {})", snapshot.url);
+            }
+            summary += &format!("| {} | {} | {} |\n", cell(failure.link), cell(&error), cell(&failure.failure.locations.join(", ")));
+        }
+    }
+    let suspect: Vec<_> = report.urls.iter().filter(|url| url.state == State::Suspect).collect();
+    if !suspect.is_empty() {
+        summary += &format!("\n### {} suspect links\n\n| Link | Why | Where |\n|---|---|---|\n", suspect.len());
+        for url in suspect {
+            summary += &format!("| {} | {} | {} |\n", cell(url.url), cell(url.reason.unwrap_or_default()), cell(&url.locations.join(", ")));
+        }
+    }
+    let better: Vec<_> = report.suggestions.iter()
+        .filter(|suggestion| matches!(suggestion.kind, SuggestionKind::BetterUrl | SuggestionKind::MovedToHttps))
+        .collect();
+    if !better.is_empty() {
+        summary += &format!("\n### {} links with a better url\n\n| Link | Better |\n|---|---|\n", better.len());
+        for suggestion in better {
+            summary += &format!("| {} | {} |\n", cell(suggestion.link), cell(suggestion.replacement));
+        }
+    }
+    summary
+}
+
+/// Adds the summary to the job page, after whatever earlier steps wrote there
+pub fn append_summary(path: &Path, report: &Report) -> Result<(), Error> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(summary(report).as_bytes())?;
+    Ok(())
+}
+
+/// The message of a workflow command can't have line breaks, or `%` which escapes them
+fn escape_data(data: &str) -> String {
+    data.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+/// ... and the properties can't have the `,` and `:` which separate them either
+fn escape_property(property: &str) -> String {
+    escape_data(property).replace(':', "%3A").replace(',', "%2C")
+}
+
+/// Text which can go in a cell of a markdown table
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::failures::{Failure, Kind};
+    use crate::report::Fixture;
+
+    #[test]
+    fn annotations_up_to_the_limit() {
+        let mut fixture = Fixture::new();
+        for i in 0..12 {
+            let url = format!("https://{:02}.example/a,b", i);
+            let mut failure = Failure::new(Kind::Http, format!("[404] {}", url));
+            failure.locations = vec![format!("README.md:{}:3", i + 10)];
+            fixture.results.failed.insert(url, failure);
+        }
+        let usage = fixture.results.failed.get_mut("README.md#usage").unwrap();
+        usage.message = "[no such heading] #usage 100%".to_string();
+        // Failures without a location only get the title
+        usage.locations.clear();
+        let annotations = annotations(&fixture.report());
+        assert_eq!(annotations.len(), MAX_ANNOTATIONS + 1);
+        assert_eq!(annotations[0], "::error title=Broken link%3A README.md#usage::[no such heading] #usage 100%25, did you mean #usages?");
+        assert_eq!(annotations[1], "::error file=README.md,line=10,col=3,title=Broken link%3A https%3A//00.example/a%2Cb::[404] https://00.example/a,b");
+        assert_eq!(annotations[MAX_ANNOTATIONS], "::notice::4 more broken links, see the job summary");
+    }
+
+    #[test]
+    fn job_summary() {
+        let mut fixture = Fixture::new();
+        let mut failure = Failure::new(Kind::Http, "[404] https://gone.example/a|b".to_string());
+        failure.locations = vec!["README.md:5:5".to_string()];
+        fixture.results.failed.insert("https://gone.example/a|b".to_string(), failure);
+        fixture.results.working.insert("http://moved.example/".to_string());
+        fixture.results.moved_to_https.insert("http://moved.example/".to_string(), "https://moved.example/".to_string());
+        fixture.locations.insert("https://gone.example/a|b".to_string(), vec!["README.md:5:5".to_string()]);
+        fixture.locations.insert("http://moved.example/".to_string(), vec!["README.md:6:3".to_string()]);
+        assert_eq!(summary(&fixture.report()), "\
+## Link check
+
+| | |
+|---|---|
+| Urls | 5 in 6 links |
+| Working | 2 |
+| Failed | 3 (1 permanent) |
+| Unverifiable | 1 |
+| Took | 1.5s |
+
+### 3 broken links
+
+| Link | Error | Where |
+|---|---|---|
+| README.md#usage | [no such heading] #usage, did you mean #usages? | README.md:7:3 |
+| https://b.example/ | [404] https://b.example/ | README.md:3:1 |
+| https://gone.example/a\\|b | [404] https://gone.example/a\\|b | README.md:5:5 |
+
+### 2 links with a better url
+
+| Link | Better |
+|---|---|
+| https://a.example/ | https://a.example/home |
+| http://moved.example/ | https://moved.example/ |
+");
+    }
+}
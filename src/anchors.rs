@@ -0,0 +1,159 @@
+use lazy_static::lazy_static;
+use pulldown_cmark::{Event, Parser, Tag};
+use regex::Regex;
+use scraper::{Html, Selector};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The anchor GitHub gives a heading, the way github-slugger does it: lowercase, without anything
+/// but letters, numbers, marks, connectors, dashes and spaces (so emoji go too), and a dash for
+/// every space. Markdown formatting has to be gone already.
+pub fn slugify_github(heading: &str) -> String {
+    lazy_static! {
+        static ref REMOVED: Regex = Regex::new(r"[^\p{L}\p{M}\p{N}\p{Pc}\- ]").unwrap();
+    }
+    REMOVED.replace_all(&heading.trim().to_lowercase(), "").replace(' ', "-")
+}
+
+/// A heading, with the anchor GitHub gives it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heading {
+    pub offset: usize,
+    pub level: u32,
+    pub title: String,
+    pub anchor: String,
+}
+
+/// Everything a fragment in a document can point to
+#[derive(Debug, Default)]
+pub struct Anchors {
+    anchors: BTreeSet<String>,
+    /// In the order they're in the document, for the outline and the table of contents
+    headings: Vec<Heading>,
+    /// How often each slug came up, since repeated headings get -1, -2, ... appended
+    occurrences: BTreeMap<String, usize>,
+}
+
+impl Anchors {
+    /// The headings of a markdown document, plus the ids and names in its html
+    pub fn from_markdown(source: &str) -> Anchors {
+        let mut anchors = Anchors::default();
+        let mut heading: Option<Heading> = None;
+        for (event, range) in Parser::new(source).into_offset_iter() {
+            match event {
+                Event::Start(Tag::Heading(level)) => heading = Some(Heading {offset: range.start, level, title: String::new(), anchor: String::new()}),
+                Event::End(Tag::Heading(_)) => {
+                    if let Some(mut heading) = heading.take() {
+                        heading.title = heading.title.trim().to_string();
+                        heading.anchor = anchors.add_heading(&heading.title);
+                        anchors.headings.push(heading);
+                    }
+                }
+                // Only the text counts, not the formatting around it
+                Event::Text(text) | Event::Code(text) if heading.is_some() => heading.as_mut().unwrap().title.push_str(&text),
+                Event::Html(html) => {
+                    let fragment = Html::parse_fragment(&html);
+                    for element in fragment.select(&Selector::parse("[id], a[name]").unwrap()) {
+                        for anchor in element.value().id().into_iter().chain(element.value().attr("name")) {
+                            anchors.add(anchor);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        anchors
+    }
+
+    /// Adds a heading, returning the anchor it gets
+    fn add_heading(&mut self, heading: &str) -> String {
+        let slug = slugify_github(heading);
+        let mut anchor = slug.clone();
+        // A heading can also end in a number to begin with, so keep counting until it's unique
+        while self.occurrences.contains_key(&anchor) {
+            let count = self.occurrences.get_mut(&slug).unwrap();
+            *count += 1;
+            anchor = format!("{}-{}", slug, count);
+        }
+        self.occurrences.insert(anchor.clone(), 0);
+        self.anchors.insert(anchor.clone());
+        anchor
+    }
+
+    pub fn headings(&self) -> &[Heading] {
+        &self.headings
+    }
+
+    /// An explicit anchor, like `<a name="...">` or an `id`
+    pub fn add(&mut self, anchor: &str) {
+        self.anchors.insert(anchor.to_string());
+    }
+
+    /// Whether a (decoded) fragment finds something. Headings are lowercase, but GitHub finds
+    /// them whatever the case of the link.
+    pub fn contains(&self, fragment: &str) -> bool {
+        self.anchors.contains(fragment) || self.anchors.contains(&fragment.to_lowercase())
+    }
+
+    /// The anchor which looks the most like this one, if any is close enough to be a typo
+    pub fn nearest(&self, fragment: &str) -> Option<&str> {
+        let fragment = fragment.to_lowercase();
+        self.anchors.iter()
+            .map(|candidate| (strsim::levenshtein(&fragment, candidate), candidate))
+            .filter(|(distance, candidate)| *distance <= candidate.chars().count().max(fragment.chars().count()) / 2)
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, candidate)| candidate.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slugs() {
+        let cases = [
+            ("Command-line", "command-line"),
+            ("Web programming", "web-programming"),
+            ("C/C++ bindings", "cc-bindings"),
+            ("C++ & Rust", "c--rust"),
+            ("What's new?", "whats-new"),
+            ("snake_case_name", "snake_case_name"),
+            ("Émojis 🎉 and accents", "émojis--and-accents"),
+            ("🦀 Crabs", "-crabs"),
+            ("Ünïcödé", "ünïcödé"),
+            ("日本語のドキュメント", "日本語のドキュメント"),
+            ("e\u{301}tude", "e\u{301}tude"),
+            ("Version 1.0 (2020)", "version-10-2020"),
+            ("  Padded  ", "padded"),
+        ];
+        for (heading, slug) in &cases {
+            assert_eq!(slugify_github(heading), *slug, "{}", heading);
+        }
+    }
+
+    #[test]
+    fn markdown_headings() {
+        let anchors = Anchors::from_markdown(r#"
+# `code` heading
+## **Bold** and *emphasis*
+## ``nested `code` span``
+## [Linked](https://example.com) heading
+## Intro
+## Intro
+## Intro-1
+<a name="old-name"></a>
+<h2 id="html-heading">Html heading</h2>
+"#);
+        for anchor in &["code-heading", "bold-and-emphasis", "nested-code-span", "linked-heading",
+                        "intro", "intro-1", "intro-1-1", "old-name", "html-heading"] {
+            assert!(anchors.contains(anchor), "{}", anchor);
+        }
+        assert!(anchors.contains("Code-Heading"));
+        assert!(!anchors.contains("intro-2"));
+        let headings: Vec<_> = anchors.headings().iter().map(|heading| (heading.level, heading.title.as_str(), heading.anchor.as_str())).collect();
+        assert_eq!(headings[..2], [(1, "code heading", "code-heading"), (2, "Bold and emphasis", "bold-and-emphasis")]);
+        assert_eq!(headings[5..], [(2, "Intro", "intro-1"), (2, "Intro-1", "intro-1-1")]);
+        assert_eq!(anchors.nearest("codeheading"), Some("code-heading"));
+        assert_eq!(anchors.nearest("databases"), None);
+    }
+}
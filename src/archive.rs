@@ -0,0 +1,147 @@
+use async_std::task;
+use log::{debug, info, warn};
+use serde::{Serialize, Deserialize};
+use std::time;
+use url::Url;
+use super::{Checker, Fallbacks, get_body, send};
+
+const AVAILABILITY_API: &str = "https://archive.org/wayback/available";
+
+const SAVE_PAGE_NOW: &str = "https://web.archive.org/save/";
+
+/// Save Page Now is slow and easily overwhelmed, so we wait this long between two saves
+const SAVE_DELAY: time::Duration = time::Duration::from_secs(5);
+
+/// Pages with a snapshot younger than this aren't saved again
+const RECENT: time::Duration = time::Duration::from_secs(30 * 24 * 60 * 60);
+
+/// The answer is a few hundred bytes of json
+const API_BYTES: usize = 16 * 1024;
+
+/// The copy of a page the Wayback Machine has closest to now
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub url: String,
+    /// YYYYMMDDhhmmss
+    pub timestamp: String,
+}
+
+impl Snapshot {
+    /// The day it was taken, as YYYY-MM-DD
+    pub fn date(&self) -> String {
+        match (self.timestamp.get(..4), self.timestamp.get(4..6), self.timestamp.get(6..8)) {
+            (Some(year), Some(month), Some(day)) => format!("{}-{}-{}", year, month, day),
+            _ => self.timestamp.clone(),
+        }
+    }
+
+    /// When it was taken, the timestamp is in UTC
+    fn time(&self) -> Option<time::SystemTime> {
+        let format = ::time::macros::format_description!("[year][month][day][hour][minute][second]");
+        let taken = ::time::PrimitiveDateTime::parse(&self.timestamp, format).ok()?;
+        Some(taken.assume_utc().into())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Availability {
+    #[serde(default)]
+    archived_snapshots: ArchivedSnapshots,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ArchivedSnapshots {
+    closest: Option<Closest>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Closest {
+    #[serde(default)]
+    available: bool,
+    #[serde(default)]
+    status: String,
+    url: String,
+    timestamp: String,
+}
+
+/// Reads the availability API's answer. Snapshots of error pages don't help anyone.
+fn parse_availability(body: &[u8]) -> Option<Snapshot> {
+    let availability: Availability = serde_json::from_slice(body).ok()?;
+    let closest = availability.archived_snapshots.closest?;
+    if !closest.available || !closest.status.starts_with('2') {
+        return None;
+    }
+    Some(Snapshot { url: closest.url, timestamp: closest.timestamp })
+}
+
+/// Asks the Wayback Machine for its closest snapshot of a url
+pub async fn closest_snapshot(checker: &Checker, url: &str) -> Option<Snapshot> {
+    let mut api = Url::parse(AVAILABILITY_API).unwrap();
+    api.query_pairs_mut().append_pair("url", url);
+    match get_body(checker, api.as_str(), &Fallbacks::default(), API_BYTES).await {
+        Ok(body) => parse_availability(&body),
+        Err(err) => {
+            debug!("Can't ask the Wayback Machine about {}: {}", url, err);
+            None
+        }
+    }
+}
+
+/// Has the Wayback Machine take a snapshot of the page
+async fn save(checker: &Checker, url: &str) -> Result<(), String> {
+    let save_url = format!("{}{}", SAVE_PAGE_NOW, url);
+    let host = checker.host(&save_url);
+    let _handle = checker.handles.get().await;
+    let _permit = host.permit(&checker.opt, &save_url).await;
+    let resp = send(checker, &save_url, &Fallbacks { get: true, ..Fallbacks::default() }).await;
+    host.finished();
+    match resp {
+        // It redirects to the snapshot once it is done
+        Ok(resp) if resp.status().is_success() || resp.status().is_redirection() => Ok(()),
+        Ok(resp) => Err(resp.status().to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Saves the working urls which don't have a recent snapshot yet, one at a time. Whatever goes
+/// wrong is only logged, it says nothing about the urls themselves.
+pub async fn save_all(checker: &Checker, urls: &[String]) {
+    let mut saved = 0;
+    for url in urls {
+        let snapshot = closest_snapshot(checker, url).await;
+        if let Some(taken) = snapshot.as_ref().and_then(Snapshot::time) {
+            if taken + RECENT > time::SystemTime::now() {
+                debug!("{} was archived recently, on {}", url, snapshot.unwrap().date());
+                continue;
+            }
+        }
+        if saved > 0 {
+            task::sleep(SAVE_DELAY).await;
+        }
+        match save(checker, url).await {
+            Ok(()) => {
+                debug!("Archived {}", url);
+                saved += 1;
+            }
+            Err(err) => warn!("Can't archive {}: {}", url, err),
+        }
+    }
+    info!("Archived {} of {} working urls", saved, urls.len());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn availability() {
+        let snapshot = parse_availability(br#"{"url": "example.com", "archived_snapshots": {"closest": {"status": "200", "available": true, "url": "http://web.archive.org/web/20130919044612/http://example.com/", "timestamp": "20130919044612"}}}"#).unwrap();
+        assert_eq!(snapshot.url, "http://web.archive.org/web/20130919044612/http://example.com/");
+        assert_eq!(snapshot.date(), "2013-09-19");
+        assert_eq!(snapshot.time().map(httpdate::fmt_http_date), Some("Thu, 19 Sep 2013 04:46:12 GMT".to_string()));
+        assert_eq!(Snapshot {timestamp: "2013091904461".to_string(), ..snapshot.clone()}.time(), None);
+        assert_eq!(parse_availability(br#"{"url": "example.invalid", "archived_snapshots": {}}"#), None);
+        assert_eq!(parse_availability(br#"{"archived_snapshots": {"closest": {"status": "404", "available": true, "url": "x", "timestamp": "2013"}}}"#), None);
+        assert_eq!(parse_availability(b"<html>Service Unavailable</html>"), None);
+    }
+}
@@ -0,0 +1,174 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time;
+use failure::{Error, format_err};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use crate::lint::EntryFormat;
+use crate::retry::Retry;
+use crate::sarif::Levels;
+
+/// Settings which are too fine-grained for the command line, loaded from a yaml file
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Request timeouts in seconds, keyed by domain glob, e.g. `"*.archive.org": 60`
+    pub timeouts: BTreeMap<String, u64>,
+
+    /// User agents, keyed by domain glob, for hosts which don't like the default one
+    pub user_agents: BTreeMap<String, String>,
+
+    /// Extra request headers, keyed by domain glob. These override the default headers
+    pub headers: BTreeMap<String, BTreeMap<String, String>>,
+
+    /// Domain globs where an invalid certificate is only a warning instead of a failure
+    pub insecure_hosts: Vec<String>,
+
+    /// Regexes for the urls of login pages, on top of the built-in ones for the big sites
+    pub login_pages: Vec<String>,
+
+    /// Phrases which give away an error page served with a 200, for `--detect-soft-404`
+    pub soft_404_phrases: Vec<String>,
+
+    /// Overrides for the retry policy, keyed by status ("404"), class ("5xx") or error kind
+    /// ("timeout"), e.g. `"5xx": {attempts: 3, backoff_ms: 500}`
+    pub retries: BTreeMap<String, Retry>,
+
+    /// Statuses which count as working, keyed by url glob, for sites which never answer us with
+    /// a 200, e.g. `"https://www.linkedin.com/*": [999]`
+    pub accepted_statuses: BTreeMap<String, Vec<u16>>,
+
+    /// Redirects to a different domain which aren't suspect, keyed by domain glob of where they
+    /// start, e.g. `"rustup.rs": ["rust-lang.org"]`
+    pub allowed_redirects: BTreeMap<String, Vec<String>>,
+
+    /// Url globs for urls in code blocks which aren't meant to work, for `--include-code-blocks`,
+    /// e.g. `"https://github.com/yourname/*"`
+    pub code_block_ignore: Vec<String>,
+
+    /// What `--lint-entries` lets entries get away with, e.g. `endings: []` for descriptions
+    /// without a full stop
+    pub entry_format: EntryFormat,
+
+    /// Headings whose entries `--lint-order` doesn't expect in alphabetical order, like ones
+    /// sorted by relevance. Can have `*` and `?`, and count for their subsections too
+    pub unsorted_sections: Vec<String>,
+
+    /// How `--output-format sarif` rates failures, suspect links and links with a better url,
+    /// as `error`, `warning`, `note` or `none`, e.g. `suspect: error`
+    pub sarif_levels: Levels,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, Error> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format_err!("Can't read {}: {}", path.display(), e))?;
+        let config: Config = serde_yaml::from_str(&contents)
+            .map_err(|e| format_err!("Can't parse {}: {}", path.display(), e))?;
+        for user_agent in config.user_agents.values() {
+            HeaderValue::from_str(user_agent)
+                .map_err(|_| format_err!("Invalid user agent {} in {}", user_agent, path.display()))?;
+        }
+        for (name, value) in config.headers.values().flatten() {
+            HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format_err!("Invalid header name {} in {}", name, path.display()))?;
+            HeaderValue::from_str(value)
+                .map_err(|_| format_err!("Invalid value for header {} in {}", name, path.display()))?;
+        }
+        for status in config.accepted_statuses.values().flatten() {
+            if !(100..1000).contains(status) {
+                return Err(format_err!("Invalid status {} in {}", status, path.display()));
+            }
+        }
+        if config.entry_format.separators.is_empty() || config.entry_format.separators.iter().any(|separator| separator.trim().is_empty()) {
+            return Err(format_err!("entry_format in {} needs separators which aren't blank", path.display()));
+        }
+        Ok(config)
+    }
+
+    /// Adds the configured headers for this host, replacing any defaults with the same name
+    pub fn apply_headers(&self, host: &str, headers: &mut HeaderMap) {
+        for (name, value) in lookup(&self.headers, host).into_iter().flatten() {
+            // Validated when loading
+            headers.insert(HeaderName::from_bytes(name.as_bytes()).unwrap(), HeaderValue::from_str(value).unwrap());
+        }
+    }
+
+    pub fn user_agent(&self, host: &str) -> Option<&str> {
+        lookup(&self.user_agents, host).map(String::as_str)
+    }
+
+    pub fn timeout(&self, host: &str) -> Option<time::Duration> {
+        lookup(&self.timeouts, host).map(|seconds| time::Duration::from_secs(*seconds))
+    }
+
+    pub fn tolerates_invalid_certs(&self, host: &str) -> bool {
+        self.insecure_hosts.iter().any(|pattern| glob_matches(pattern, host))
+    }
+
+    /// Whether this status means the url works, even though it isn't a success
+    pub fn accepts_status(&self, url: &str, status: u16) -> bool {
+        self.accepted_statuses.iter()
+            .any(|(pattern, statuses)| statuses.contains(&status) && glob_matches(pattern, url))
+    }
+
+    pub fn ignores_code_url(&self, url: &str) -> bool {
+        self.code_block_ignore.iter().any(|pattern| glob_matches(pattern, url))
+    }
+}
+
+/// Matches a domain glob, where `*` stands for any number of characters
+pub fn glob_matches(pattern: &str, host: &str) -> bool {
+    match pattern.find('*') {
+        None => pattern.eq_ignore_ascii_case(host),
+        Some(star) => {
+            let (prefix, rest) = (&pattern[..star], &pattern[star + 1..]);
+            if host.len() < prefix.len() || !host[..prefix.len()].eq_ignore_ascii_case(prefix) {
+                return false;
+            }
+            let host = &host[prefix.len()..];
+            (0..=host.len())
+                .filter(|i| host.is_char_boundary(*i))
+                .any(|i| glob_matches(rest, &host[i..]))
+        }
+    }
+}
+
+/// Finds the value for the longest glob matching the host, so more specific entries win
+pub fn lookup<'a, T>(map: &'a BTreeMap<String, T>, host: &str) -> Option<&'a T> {
+    map.iter()
+        .filter(|(pattern, _)| glob_matches(pattern, host))
+        .max_by_key(|(pattern, _)| pattern.len())
+        .map(|(_, value)| value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs() {
+        assert!(glob_matches("*.archive.org", "web.archive.org"));
+        assert!(glob_matches("*.archive.org", "WEB.Archive.org"));
+        assert!(!glob_matches("*.archive.org", "archive.org"));
+        assert!(glob_matches("archive.org", "archive.org"));
+        assert!(glob_matches("*", "example.com"));
+        assert!(glob_matches("docs.*.org", "docs.rust-lang.org"));
+        assert!(!glob_matches("docs.*.org", "docs.rust-lang.com"));
+    }
+
+    #[test]
+    fn longest_glob_wins() {
+        let config: Config = serde_yaml::from_str(r#"
+timeouts:
+  "*": 5
+  "*.org": 10
+  "*.archive.org": 60
+"#).unwrap();
+        assert_eq!(config.timeout("web.archive.org"), Some(time::Duration::from_secs(60)));
+        assert_eq!(config.timeout("rust-lang.org"), Some(time::Duration::from_secs(10)));
+        assert_eq!(config.timeout("example.com"), Some(time::Duration::from_secs(5)));
+        assert_eq!(Config::default().timeout("example.com"), None);
+    }
+}
@@ -0,0 +1,173 @@
+use lazy_static::lazy_static;
+use scraper::{Html, Selector};
+use serde::Deserialize;
+
+/// How much of a page we read to decide whether it is really there
+pub const SOFT_404_BYTES: usize = 64 * 1024;
+
+/// What sites say on error pages which they serve with a 200. More can be added with
+/// `soft_404_phrases` in the config.
+const SOFT_404_PHRASES: &[&str] = &[
+    "page not found",
+    "page could not be found",
+    "page you requested could not be found",
+    "page doesn't exist",
+    "page does not exist",
+    "there isn't a github pages site here",
+];
+
+/// Anything shorter than this can't be much of a project page
+const SHORT_BODY: usize = 512;
+
+/// Guesses whether a page which answered 200 is an error page, and if so why. Only a guess, so
+/// whatever this finds is reported as suspect rather than failed.
+pub fn soft_404(body: &[u8], phrases: &[String]) -> Option<String> {
+    if body.len() < SHORT_BODY {
+        return Some(format!("page is only {} bytes", body.len()));
+    }
+    let document = Html::parse_document(&String::from_utf8_lossy(body));
+    let title = document.select(&Selector::parse("title").unwrap())
+        .next()
+        .map(|title| title.text().collect::<String>())
+        .unwrap_or_default();
+    let lowercase = title.to_lowercase();
+    if lowercase.contains("404") || lowercase.contains("not found") {
+        return Some(format!("title is \"{}\"", title.trim()));
+    }
+    let text = document.root_element().text().collect::<String>().to_lowercase();
+    SOFT_404_PHRASES.iter().copied()
+        .chain(phrases.iter().map(String::as_str))
+        .find(|phrase| text.contains(&phrase.to_lowercase()))
+        .map(|phrase| format!("page says \"{}\"", phrase))
+}
+
+/// The text of a badge. Shields puts all of it in the title, otherwise we go by the text
+/// elements, which are usually doubled up for the shadow.
+pub fn badge_text(svg: &[u8]) -> Option<String> {
+    let document = Html::parse_document(&String::from_utf8_lossy(svg));
+    let title = document.select(&Selector::parse("title").unwrap())
+        .next()
+        .map(|title| title.text().collect::<String>().trim().to_string())
+        .filter(|title| !title.is_empty());
+    if title.is_some() {
+        return title;
+    }
+    let mut texts: Vec<String> = document.select(&Selector::parse("text").unwrap())
+        .map(|text| text.text().collect::<String>().trim().to_string())
+        .filter(|text| !text.is_empty())
+        .collect();
+    texts.dedup();
+    if texts.is_empty() { None } else { Some(texts.join(" ")) }
+}
+
+/// Whether a badge shows an error instead of a status, e.g. because the repository moved
+pub fn broken_badge(text: &str) -> bool {
+    let text = text.to_lowercase();
+    ["invalid", "not found", "unknown", "inaccessible"].iter().any(|error| text.contains(error))
+}
+
+/// Whether the page has the target of a fragment, as an id or a name. GitHub prefixes the ids in
+/// rendered markdown with `user-content-`, and scrolls to them whatever the case of the fragment.
+pub fn has_anchor(body: &[u8], anchor: &str) -> bool {
+    let document = Html::parse_document(&String::from_utf8_lossy(body));
+    let prefixed = format!("user-content-{}", anchor);
+    document.select(&Selector::parse("[id], [name]").unwrap())
+        .flat_map(|element| element.value().id().into_iter().chain(element.value().attr("name")))
+        .any(|target| target == anchor || target.eq_ignore_ascii_case(&prefixed))
+}
+
+/// Fragments which are meant for scripts rather than pointing at an element, like the line
+/// numbers on GitHub, client side routes or text fragments
+pub fn checkable_fragment(fragment: &str) -> bool {
+    lazy_static! {
+        static ref SCRIPTED: regex::Regex = regex::Regex::new(r"^(L\d+(C\d+)?(-L\d+(C\d+)?)?$|[!/]|:~:)").unwrap();
+    }
+    !fragment.is_empty() && !SCRIPTED.is_match(fragment)
+}
+
+#[derive(Debug, Deserialize)]
+struct ParkingSignature {
+    provider: String,
+    marker: String,
+}
+
+lazy_static! {
+    static ref PARKING_SIGNATURES: Vec<ParkingSignature> =
+        serde_yaml::from_str(include_str!("../data/parked-domains.yaml")).expect("Invalid data/parked-domains.yaml");
+}
+
+/// Recognizes the pages domain parking and resale services put up once a domain lapses. Returns
+/// who is parking it.
+pub fn parked(body: &[u8]) -> Option<String> {
+    let html = String::from_utf8_lossy(body).to_lowercase();
+    if let Some(signature) = PARKING_SIGNATURES.iter().find(|signature| html.contains(&signature.marker.to_lowercase())) {
+        return Some(signature.provider.clone());
+    }
+    // Parking pages we don't know are mostly ads in iframes, plus an offer to buy the domain
+    let document = Html::parse_document(&html);
+    let iframes = document.select(&Selector::parse("iframe").unwrap()).count();
+    let words = document.root_element().text().flat_map(str::split_whitespace).count();
+    let for_sale = ["domain is for sale", "buy this domain", "domain may be for sale"].iter().any(|offer| html.contains(offer));
+    if iframes > 0 && words < 150 && for_sale {
+        return Some("unknown parking service".to_string());
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(title: &str, body: &str) -> Vec<u8> {
+        // Padded so it isn't too short to be a real page
+        format!("<html><head><title>{}</title></head><body>{}<p>{}</p></body></html>", title, body, "padding ".repeat(100))
+            .into_bytes()
+    }
+
+    #[test]
+    fn soft_404s() {
+        assert_eq!(soft_404(&page("404 Not Found", ""), &[]), Some("title is \"404 Not Found\"".to_string()));
+        assert_eq!(soft_404(&page("Oops", "Sorry, this Page Does Not Exist."), &[]),
+                   Some("page says \"page does not exist\"".to_string()));
+        assert_eq!(soft_404(&page("Oops", "Nothing to see here"), &["nothing to see".to_string()]),
+                   Some("page says \"nothing to see\"".to_string()));
+        assert_eq!(soft_404(b"<html></html>", &[]), Some("page is only 13 bytes".to_string()));
+        assert_eq!(soft_404(&page("serde", "A framework for serializing"), &[]), None);
+    }
+
+    #[test]
+    fn badges() {
+        let shields = br#"<svg xmlns="http://www.w3.org/2000/svg" width="90" height="20" role="img" aria-label="crates.io: v1.0.3"><title>crates.io: v1.0.3</title><g><text x="325" y="150">crates.io</text></g></svg>"#;
+        assert_eq!(badge_text(shields), Some("crates.io: v1.0.3".to_string()));
+        let badgen = br##"<svg xmlns="http://www.w3.org/2000/svg"><g><text x="5" y="15" fill="#000">build</text><text x="5" y="14">build</text><text x="50" y="15" fill="#000">repo not found</text><text x="50" y="14">repo not found</text></g></svg>"##;
+        assert_eq!(badge_text(badgen), Some("build repo not found".to_string()));
+        assert_eq!(badge_text(b"<svg></svg>"), None);
+        assert!(broken_badge("build repo not found"));
+        assert!(broken_badge("license: Invalid"));
+        assert!(!broken_badge("build: passing"));
+    }
+
+    #[test]
+    fn anchors() {
+        let page = br##"<h2><a id="user-content-installation" class="anchor" href="#installation"></a>Installation</h2><a name="old-api"></a><div id="Usage"></div>"##;
+        assert!(has_anchor(page, "installation"));
+        assert!(has_anchor(page, "Installation"));
+        assert!(has_anchor(page, "old-api"));
+        assert!(has_anchor(page, "Usage"));
+        assert!(!has_anchor(page, "usage-examples"));
+        assert!(checkable_fragment("installation"));
+        for scripted in &["L10", "L10-L20", "L3C5-L4C2", "!/home", "/docs/intro", ":~:text=serde", ""] {
+            assert!(!checkable_fragment(scripted), "{}", scripted);
+        }
+    }
+
+    #[test]
+    fn parked_domains() {
+        assert!(!PARKING_SIGNATURES.is_empty());
+        assert_eq!(parked(br#"<script src="https://img1.wsimg.com/parking-lander/static/js/main.js"></script>"#),
+                   Some("GoDaddy".to_string()));
+        assert_eq!(parked(br#"<iframe src="/ads"></iframe><h1>This domain is for sale!</h1>"#),
+                   Some("unknown parking service".to_string()));
+        assert_eq!(parked(&page("rocket", "Rocket is a web framework. This domain is for sale, just kidding")), None);
+    }
+}
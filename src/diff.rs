@@ -0,0 +1,81 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::process::Command;
+use crate::urls;
+
+/// What changed in a file since some git ref
+#[derive(Debug, Default, PartialEq)]
+pub struct Changes {
+    /// The (1-based) lines which are new or different
+    added: BTreeSet<usize>,
+    /// The urls on the lines which were removed or changed
+    pub removed: BTreeSet<String>,
+}
+
+impl Changes {
+    /// Asks git what changed in the file since `base`, which can be anything `git diff` takes
+    pub fn since(base: &str, file: &Path) -> Result<Changes, String> {
+        let output = Command::new("git")
+            .args(["diff", "--unified=0", "--no-color", "--no-ext-diff", base, "--"])
+            .arg(file)
+            .output()
+            .map_err(|e| format!("can't run git: {}", e))?;
+        if !output.status.success() {
+            return Err(format!("git diff {} failed: {}", base, String::from_utf8_lossy(&output.stderr).trim()));
+        }
+        Ok(Changes::from_diff(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    /// Reads a diff without context lines, as from `git diff --unified=0`
+    fn from_diff(diff: &str) -> Changes {
+        lazy_static! {
+            static ref HUNK_REGEX: Regex = Regex::new(r"^@@ -\d+(?:,\d+)? \+(\d+)(?:,(\d+))? @@").unwrap();
+        }
+        let mut changes = Changes::default();
+        let mut in_hunk = false;
+        for line in diff.lines() {
+            if let Some(captures) = HUNK_REGEX.captures(line) {
+                in_hunk = true;
+                let start: usize = captures[1].parse().unwrap_or_default();
+                let count: usize = captures.get(2).map_or(1, |count| count.as_str().parse().unwrap_or_default());
+                changes.added.extend(start..start + count);
+            } else if let Some(removed) = line.strip_prefix('-').filter(|_| in_hunk) {
+                changes.removed.extend(urls::in_text(removed).into_iter().map(|(_, url)| url.to_string()));
+            }
+        }
+        changes
+    }
+
+    pub fn added(&self, line: usize) -> bool {
+        self.added.contains(&line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diffs() {
+        let changes = Changes::from_diff("\
+diff --git a/README.md b/README.md
+index 1234567..89abcde 100644
+--- a/README.md
++++ b/README.md
+@@ -3 +3 @@ Rust
+-* [old](https://old.example) — gone
++* [new](https://new.example) — here
+@@ -10,0 +11,2 @@ Rust
++* [a](https://a.example)
++* [b](https://b.example)
+@@ -20,2 +21,0 @@ Rust
+-* [c](https://c.example)
+-* see https://d.example.
+");
+        let added: Vec<_> = (1..30).filter(|line| changes.added(*line)).collect();
+        assert_eq!(added, vec![3, 11, 12]);
+        assert_eq!(changes.removed.into_iter().collect::<Vec<_>>(), vec!["https://c.example", "https://d.example", "https://old.example"]);
+    }
+}
@@ -0,0 +1,97 @@
+use futures::StreamExt;
+use hyper::client::connect::dns::Name;
+use log::debug;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time;
+
+/// A lookup which fails can be a SERVFAIL or a timeout that's gone the next time, so only a host
+/// which fails this often in a row counts as not existing
+const LOOKUP_ATTEMPTS: u32 = 3;
+
+/// Wait before the second lookup, doubled for every one after that
+const LOOKUP_BACKOFF: time::Duration = time::Duration::from_millis(500);
+
+/// The client's resolver: every host is looked up once for the whole run, so the retries of a
+/// url and the other urls on its host connect to what `resolve_all` found up front
+#[derive(Default, Clone)]
+pub struct Cache {
+    addresses: Arc<Mutex<BTreeMap<String, Vec<SocketAddr>>>>,
+}
+
+impl Cache {
+    async fn lookup(&self, host: &str) -> io::Result<Vec<SocketAddr>> {
+        if let Some(addresses) = self.addresses.lock().unwrap().get(host) {
+            return Ok(addresses.clone());
+        }
+        // The client puts in the port of the url
+        let addresses: Vec<_> = tokio::net::lookup_host((host, 0)).await?.collect();
+        self.addresses.lock().unwrap().insert(host.to_string(), addresses.clone());
+        Ok(addresses)
+    }
+
+    /// Looks the host up until it works, or it failed LOOKUP_ATTEMPTS times
+    async fn lookup_with_retries(&self, host: &str) -> io::Result<Vec<SocketAddr>> {
+        let mut backoff = LOOKUP_BACKOFF;
+        for _ in 1..LOOKUP_ATTEMPTS {
+            match self.lookup(host).await {
+                Ok(addresses) => return Ok(addresses),
+                Err(err) => debug!("Failed to resolve {}, trying again in {:?}: {}", host, backoff, err),
+            }
+            async_std::task::sleep(backoff).await;
+            backoff *= 2;
+        }
+        self.lookup(host).await
+    }
+
+    /// Resolves every host once before checking anything, concurrently, so that hosts which don't
+    /// exist fail right away instead of once per url. Returns the hosts which failed to resolve.
+    pub async fn resolve_all(&self, hosts: BTreeSet<String>, concurrency: usize) -> BTreeSet<String> {
+        futures::stream::iter(hosts)
+            .map(|host| async move {
+                let resolved = match self.lookup_with_retries(&host).await {
+                    Ok(_) => true,
+                    Err(err) => {
+                        debug!("Failed to resolve {}: {}", host, err);
+                        false
+                    }
+                };
+                (host, resolved)
+            })
+            .buffer_unordered(concurrency)
+            .filter_map(|(host, resolved)| async move { if resolved { None } else { Some(host) } })
+            .collect()
+            .await
+    }
+}
+
+impl Resolve for Cache {
+    fn resolve(&self, name: Name) -> Resolving {
+        let cache = self.clone();
+        Box::pin(async move {
+            let addresses: Addrs = Box::new(cache.lookup(name.as_str()).await?.into_iter());
+            Ok(addresses)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn looks_up_once() {
+        let cache = Cache::default();
+        assert_eq!(cache.resolve_all(vec!["localhost".to_string()].into_iter().collect(), 1).await, BTreeSet::new());
+        let addresses = cache.addresses.lock().unwrap()["localhost"].clone();
+        assert!(addresses.iter().all(|address| address.ip().is_loopback()), "{:?}", addresses);
+        // Whatever it resolves to now, the client gets what we found before
+        let pinned = vec!["127.0.0.2:0".parse().unwrap()];
+        cache.addresses.lock().unwrap().insert("localhost".to_string(), pinned.clone());
+        let resolved: Vec<_> = cache.resolve("localhost".parse().unwrap()).await.unwrap().collect();
+        assert_eq!(resolved, pinned);
+    }
+}
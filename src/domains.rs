@@ -0,0 +1,71 @@
+use std::net::IpAddr;
+use crate::config::{Config, glob_matches};
+
+/// Redirects between these (registrable) domains are expected, by domain glob. More can be added
+/// with `allowed_redirects` in the config.
+const ALLOWED_REDIRECTS: &[(&str, &str)] = &[
+    // Project pages which moved to their own domain
+    ("*.github.io", "*"),
+    ("*.gitlab.io", "*"),
+    ("youtu.be", "youtube.com"),
+    ("docs.rs", "rust-lang.org"),
+    // Url shorteners, which only exist to send us elsewhere
+    ("bit.ly", "*"),
+    ("goo.gl", "*"),
+    ("t.co", "*"),
+];
+
+/// The part of a host that can be registered, e.g. example.co.uk for www.example.co.uk. Hosting
+/// services like github.io count as suffixes, so every user has their own. The list of suffixes
+/// comes with the psl crate, so updating that keeps it current.
+pub fn registrable(host: &str) -> Option<String> {
+    if host.parse::<IpAddr>().is_ok() {
+        return None;
+    }
+    psl::domain_str(&host.to_ascii_lowercase()).map(str::to_string)
+}
+
+/// Whether a redirect leaves the site for a different one, which often means someone else owns
+/// the domain now
+pub fn crosses_domains(config: &Config, from: &str, to: &str) -> bool {
+    let (from, to) = match (registrable(from), registrable(to)) {
+        (Some(from), Some(to)) => (from, to),
+        // IP addresses and the like, where we can't tell
+        _ => return false,
+    };
+    if from == to {
+        return false;
+    }
+    let allowed = |source: &str, target: &str| glob_matches(source, &from) && glob_matches(target, &to);
+    !ALLOWED_REDIRECTS.iter().any(|(source, target)| allowed(source, target)) &&
+        !config.allowed_redirects.iter().any(|(source, targets)| targets.iter().any(|target| allowed(source, target)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registrable_domains() {
+        assert_eq!(registrable("www.rust-lang.org").as_deref(), Some("rust-lang.org"));
+        assert_eq!(registrable("www.example.co.uk").as_deref(), Some("example.co.uk"));
+        assert_eq!(registrable("serde-rs.github.io").as_deref(), Some("serde-rs.github.io"));
+        assert_eq!(registrable("127.0.0.1"), None);
+    }
+
+    #[test]
+    fn cross_domain_redirects() {
+        let config = Config::default();
+        assert!(!crosses_domains(&config, "rust-lang.org", "www.rust-lang.org"));
+        assert!(!crosses_domains(&config, "amethyst.github.io", "amethyst.rs"));
+        assert!(!crosses_domains(&config, "youtu.be", "www.youtube.com"));
+        assert!(crosses_domains(&config, "www.example-crate.org", "www.domain-for-sale.com"));
+        assert!(crosses_domains(&config, "tokio.rs", "tokio-rs.github.io"));
+
+        let config: Config = serde_yaml::from_str(r#"
+allowed_redirects:
+  "example-crate.org": ["*.com"]
+"#).unwrap();
+        assert!(!crosses_domains(&config, "www.example-crate.org", "www.domain-for-sale.com"));
+    }
+}
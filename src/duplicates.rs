@@ -0,0 +1,74 @@
+use crate::items::{Item, Piece};
+
+/// A link in an entry of a list
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryLink {
+    /// Which entry it's in, counting from 0 through the document. Entries of sub-lists count on
+    /// their own.
+    pub entry: usize,
+    pub url: String,
+    pub offset: usize,
+}
+
+/// The links in the entries of the lists in a document, except for badges: images, and links
+/// around nothing but an image
+pub fn entry_links(items: &[Item]) -> Vec<EntryLink> {
+    let mut links: Vec<_> = items.iter()
+        .flat_map(|item| item.pieces.iter().filter_map(move |piece| match piece {
+            // An image's alt text doesn't count, so a link needs some text of its own
+            Piece::Link {url, text, offset} if !text.trim().is_empty() => Some(EntryLink {entry: item.index, url: url.clone(), offset: *offset}),
+            _ => None,
+        }))
+        .collect();
+    links.sort_by_key(|link| link.offset);
+    links
+}
+
+/// The project a url is about, which other urls for it have too: without the scheme, `www.`,
+/// a trailing slash or `.git`, the query or the fragment. GitHub doesn't care about case either.
+pub fn project(url: &str) -> Option<String> {
+    let parsed = url::Url::parse(url).ok()?;
+    if !parsed.scheme().starts_with("http") {
+        return None;
+    }
+    let host = parsed.host_str()?.trim_start_matches("www.").to_string();
+    let path = parsed.path().trim_end_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path).trim_end_matches('/');
+    let path = if host == "github.com" { path.to_lowercase() } else { path.to_string() };
+    Some(format!("{}{}", host, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::items::items;
+
+    #[test]
+    fn entries() {
+        let source = "\
+[intro](https://intro.example)
+
+* [serde](https://github.com/serde-rs/serde) — Serialization [![crates.io](https://img.shields.io/crates/v/serde.svg)](https://crates.io/crates/serde) [docs](https://docs.rs/serde)
+  * [serde_json](https://github.com/serde-rs/json) — JSON
+* [Serde](https://github.com/Serde-rs/serde.git) [<img src=\"https://travis-ci.org/x.svg\">](https://travis-ci.org/x)
+";
+        let links: Vec<_> = entry_links(&items(source)).into_iter().map(|link| (link.entry, link.url)).collect();
+        assert_eq!(links, vec![
+            (0, "https://github.com/serde-rs/serde".to_string()),
+            (0, "https://docs.rs/serde".to_string()),
+            (1, "https://github.com/serde-rs/json".to_string()),
+            (2, "https://github.com/Serde-rs/serde.git".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn projects() {
+        let same = ["https://github.com/serde-rs/serde", "http://www.github.com/Serde-rs/Serde/", "https://github.com/serde-rs/serde.git",
+                    "https://github.com/serde-rs/serde#readme", "https://github.com/serde-rs/serde?tab=readme"];
+        for url in &same {
+            assert_eq!(project(url).as_deref(), Some("github.com/serde-rs/serde"), "{}", url);
+        }
+        assert_eq!(project("https://gitlab.com/Org/Repo/"), Some("gitlab.com/Org/Repo".to_string()));
+        assert_eq!(project("mailto:someone@example.com"), None);
+    }
+}
@@ -0,0 +1,231 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time;
+
+/// What kind of failure it is, to filter and count them by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    /// An error status, which is in `status`
+    Http,
+    RedirectLoop,
+    TooManyRedirects,
+    Dns,
+    Timeout,
+    ConnectionRefused,
+    ConnectionReset,
+    Tls,
+    RateLimited,
+    /// Skipped, because too many requests to the host failed already
+    HostUnreachable,
+    Unverifiable,
+    LoginRequired,
+    NotAnImage,
+    MissingAnchor,
+    MangledUrl,
+    BrokenBadge,
+    /// A link to a heading in the same file which isn't there
+    NoSuchHeading,
+    /// A link to a file in the repository which isn't there, or to a heading it doesn't have
+    NoSuchFile,
+    UndefinedReference,
+    /// Several entries linking to the same project, with `--fail-on-duplicates`
+    Duplicate,
+    /// A mailto:, tel: or data: link which is written wrong, or a javascript: link
+    InvalidLink,
+    Other,
+}
+
+/// What the terminal report groups failures by, most actionable first, so the links which are
+/// really gone don't drown in the ones which might work again next time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Category {
+    ClientError,
+    Dns,
+    /// Links to headings and files in the repository, and reference links
+    Local,
+    /// Pages which answer, but not with what the link is for, like an image or a section
+    Content,
+    Invalid,
+    Duplicate,
+    Redirect,
+    Tls,
+    ServerError,
+    Connection,
+    RateLimited,
+    Timeout,
+    /// Bot protection and logins, with --strict
+    Unverifiable,
+    Other,
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Category::ClientError => "4xx",
+            Category::Dns => "DNS",
+            Category::Local => "links within the repository",
+            Category::Content => "content",
+            Category::Invalid => "invalid links",
+            Category::Duplicate => "duplicates",
+            Category::Redirect => "redirects",
+            Category::Tls => "TLS",
+            Category::ServerError => "5xx",
+            Category::Connection => "connection",
+            Category::RateLimited => "rate limited",
+            Category::Timeout => "timeouts",
+            Category::Unverifiable => "unverifiable",
+            Category::Other => "other",
+        })
+    }
+}
+
+/// Why a link failed, the way the results keep it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Failure {
+    pub kind: Kind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    /// What the report says about it, like `[404] https://example.com/`
+    pub message: String,
+    /// Where the redirects went, if it failed at the end of them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redirect_chain: Vec<String>,
+    /// Where the link is, as path:line:column
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub locations: Vec<String>,
+    /// The sections the link is in, like `Applications > Emulators`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sections: Vec<String>,
+    /// What it probably should have been, like the heading a fragment was meant for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suggestion: Option<String>,
+    /// How many requests we made before giving up, 0 for links which aren't fetched
+    #[serde(default)]
+    pub attempts: u32,
+    /// When it failed, in seconds since the Unix epoch
+    #[serde(default)]
+    pub checked_at: u64,
+}
+
+impl Failure {
+    pub fn new(kind: Kind, message: String) -> Failure {
+        let checked_at = time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap_or_default().as_secs();
+        Failure {
+            kind,
+            status: None,
+            message,
+            redirect_chain: vec![],
+            locations: vec![],
+            sections: vec![],
+            suggestion: None,
+            attempts: 0,
+            checked_at,
+        }
+    }
+
+    pub fn category(&self) -> Category {
+        match self.kind {
+            Kind::Http | Kind::MangledUrl => match self.status {
+                Some(400..=499) => Category::ClientError,
+                Some(500..=599) => Category::ServerError,
+                _ => Category::Other,
+            },
+            Kind::Dns => Category::Dns,
+            Kind::NoSuchHeading | Kind::NoSuchFile | Kind::UndefinedReference => Category::Local,
+            Kind::NotAnImage | Kind::MissingAnchor | Kind::BrokenBadge => Category::Content,
+            Kind::InvalidLink => Category::Invalid,
+            Kind::Duplicate => Category::Duplicate,
+            Kind::RedirectLoop | Kind::TooManyRedirects => Category::Redirect,
+            Kind::Tls => Category::Tls,
+            Kind::ConnectionRefused | Kind::ConnectionReset | Kind::HostUnreachable => Category::Connection,
+            Kind::RateLimited => Category::RateLimited,
+            Kind::Timeout => Category::Timeout,
+            Kind::Unverifiable | Kind::LoginRequired => Category::Unverifiable,
+            Kind::Other => Category::Other,
+        }
+    }
+}
+
+/// One of a link's `locations`, split into the path, line and column
+pub fn split_location(location: &str) -> Option<(&str, usize, usize)> {
+    let mut parts = location.rsplitn(3, ':');
+    let column = parts.next()?.parse().ok()?;
+    let line = parts.next()?.parse().ok()?;
+    Some((parts.next()?, line, column))
+}
+
+/// The message, then where the link is and the fix, if there is one
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if !self.locations.is_empty() {
+            write!(f, " ({})", self.locations.join(", "))?;
+        }
+        if !self.sections.is_empty() {
+            write!(f, " in {}", self.sections.join("; "))?;
+        }
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, ", did you mean {}?", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the failures of older versions too, which only kept the message
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<String, Failure>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Stored {
+        Failure(Failure),
+        Message(String),
+    }
+    let stored: BTreeMap<String, Stored> = BTreeMap::deserialize(deserializer)?;
+    Ok(stored.into_iter().map(|(url, stored)| {
+        let failure = match stored {
+            Stored::Failure(failure) => failure,
+            Stored::Message(message) => Failure {checked_at: 0, ..Failure::new(Kind::Other, message)},
+        };
+        (url, failure)
+    }).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        let mut failure = Failure::new(Kind::NoSuchHeading, "[no such heading] #usage".to_string());
+        assert_eq!(failure.to_string(), "[no such heading] #usage");
+        failure.locations = vec!["README.md:3:1".to_string(), "README.md:9:5".to_string()];
+        failure.sections = vec!["Applications > Audio".to_string(), "Libraries".to_string()];
+        failure.suggestion = Some("#usages".to_string());
+        assert_eq!(failure.to_string(), "[no such heading] #usage (README.md:3:1, README.md:9:5) in Applications > Audio; Libraries, did you mean #usages?");
+    }
+
+    #[test]
+    fn old_and_new_formats() {
+        #[derive(Deserialize)]
+        struct Results {
+            #[serde(deserialize_with = "deserialize")]
+            failed: BTreeMap<String, Failure>,
+        }
+        let results: Results = serde_yaml::from_str(r#"
+failed:
+  "https://old.example/": "[404] https://old.example/ (README.md:1:1)"
+  "https://new.example/":
+    kind: http
+    status: 410
+    message: "[410 gone] https://new.example/"
+    attempts: 1
+    checked_at: 1600000000
+"#).unwrap();
+        let old = &results.failed["https://old.example/"];
+        assert_eq!((old.kind, old.status, old.to_string().as_str()), (Kind::Other, None, "[404] https://old.example/ (README.md:1:1)"));
+        let new = &results.failed["https://new.example/"];
+        assert_eq!((new.kind, new.status, new.attempts, new.checked_at), (Kind::Http, Some(410), 1, 1600000000));
+        assert!(serde_yaml::to_string(new).unwrap().contains("kind: http"));
+    }
+}
@@ -0,0 +1,341 @@
+use lazy_static::lazy_static;
+use log::{debug, info, warn};
+use regex::Regex;
+use reqwest::{StatusCode, header};
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::Ordering;
+use super::{Checker, CheckerError, Fallbacks, rate_limit_reset, read_body, send};
+
+const API: &str = "https://api.github.com";
+
+/// A repository is a few KB of json, anything longer got cut off somewhere
+const API_BYTES: usize = 64 * 1024;
+
+/// First path segments on github.com which aren't users or organizations
+const RESERVED: &[&str] = &[
+    "about", "apps", "collections", "explore", "features", "login", "marketplace", "orgs",
+    "search", "settings", "sponsors", "topics", "trending",
+];
+
+/// GitHub doesn't allow more repositories in a single GraphQL query
+const BATCH_SIZE: usize = 100;
+
+/// What the API tells us about a repository, the parts we care about anyway
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Repository {
+    pub full_name: String,
+    #[serde(default)]
+    pub archived: bool,
+    #[serde(default)]
+    pub default_branch: String,
+    /// Of the response, which we take from reqwest
+    #[serde(skip)]
+    pub http_version: String,
+}
+
+/// The owner and name of the repository, if the url is the repository itself. Anything deeper,
+/// like files or the wiki, still needs the page.
+pub fn repo_of(url: &str) -> Option<(String, String)> {
+    lazy_static! {
+        static ref REPO_REGEX: Regex = Regex::new(r"^https?://(www\.)?github\.com/(?P<org>[^/?#]+)/(?P<repo>[^/?#]+?)(\.git)?/?$").unwrap();
+    }
+    let captures = REPO_REGEX.captures(url)?;
+    let org = &captures["org"];
+    if RESERVED.contains(&org.to_ascii_lowercase().as_str()) {
+        return None;
+    }
+    Some((org.to_string(), captures["repo"].to_string()))
+}
+
+/// The repository a GitHub Actions url belongs to: the actions tab, a workflow or its badge.
+/// Renaming the workflow file breaks all of those, while the repository is still there.
+pub fn workflow_repository(url: &str) -> Option<String> {
+    lazy_static! {
+        static ref WORKFLOW_REGEX: Regex = Regex::new(r"^https?://(www\.)?github\.com/(?P<org>[^/?#]+)/(?P<repo>[^/?#]+)/(actions(/workflows/[^/?#]+(/badge\.svg)?)?|workflows/[^/?#]+/badge\.svg)/?([?#].*)?$").unwrap();
+    }
+    let captures = WORKFLOW_REGEX.captures(url)?;
+    Some(format!("https://github.com/{}/{}", &captures["org"], &captures["repo"]))
+}
+
+/// Where GitHub serves a markdown file it shows at this url as it is
+pub fn raw_markdown(url: &str) -> Option<String> {
+    lazy_static! {
+        static ref BLOB_REGEX: Regex = Regex::new(r"(?i)^https://github\.com/(?P<org>[^/?#]+)/(?P<repo>[^/?#]+)/blob/(?P<path>[^?#]+\.(md|markdown))([?#].*)?$").unwrap();
+    }
+    let captures = BLOB_REGEX.captures(url)?;
+    Some(format!("https://raw.githubusercontent.com/{}/{}/{}", &captures["org"], &captures["repo"], &captures["path"]))
+}
+
+/// Asks the API about a repository. Returns None if it couldn't tell us, e.g. because we ran out
+/// of requests or the repository moved, in which case the page has to be checked instead.
+pub async fn check_repo(checker: &Checker, org: &str, repo: &str) -> Option<Result<Repository, CheckerError>> {
+    if let Some(prefetched) = checker.github_repos.lock().unwrap().get(&key(org, repo)) {
+        return Some(prefetched.clone().ok_or(CheckerError::HttpError {status: StatusCode::NOT_FOUND, chain: vec![]}));
+    }
+    if !checker.github_api.load(Ordering::Relaxed) {
+        return None;
+    }
+    let url = format!("{}/repos/{}/{}", API, org, repo);
+    let host = checker.host(&url);
+    let _handle = checker.handles.get().await;
+    let _permit = host.permit(&checker.opt, &url).await;
+    let resp = send(checker, &url, &Fallbacks { get: true, ..Fallbacks::default() }).await;
+    host.finished();
+    let resp = match resp {
+        Ok(resp) => resp,
+        Err(err) => {
+            debug!("Can't reach the GitHub API for {}/{}: {}", org, repo, err);
+            return None;
+        }
+    };
+    match resp.status() {
+        StatusCode::OK => {}
+        // Private repositories are 404 too, which is just as dead to readers
+        StatusCode::NOT_FOUND => return Some(Err(CheckerError::HttpError {status: StatusCode::NOT_FOUND, chain: vec![]})),
+        status => {
+            // The page has its own, separate limit, so use that for the rest of the run
+            if rate_limit_reset(&resp).is_some() && checker.github_api.swap(false, Ordering::Relaxed) {
+                warn!("GitHub API rate limit reached, checking repository pages instead");
+            }
+            debug!("Got {} from the GitHub API for {}/{}, checking the page instead", status, org, repo);
+            return None;
+        }
+    }
+    let http_version = format!("{:?}", resp.version());
+    let body = read_body(checker, resp, API_BYTES).await.ok()?;
+    match serde_json::from_slice::<Repository>(&body) {
+        Ok(repository) => {
+            let repository = Repository { http_version, ..repository };
+            debug!("{} exists, default branch {}", repository.full_name, repository.default_branch);
+            Some(Ok(repository))
+        }
+        Err(err) => {
+            debug!("Unexpected answer from the GitHub API for {}/{}: {}", org, repo, err);
+            None
+        }
+    }
+}
+
+/// Repositories are case insensitive
+fn key(org: &str, repo: &str) -> String {
+    format!("{}/{}", org, repo).to_lowercase()
+}
+
+/// One GraphQL query for all of the repositories, each under an alias (r0, r1, ...) so the
+/// answers can be told apart
+fn query(repos: &[(String, String)]) -> String {
+    let fields: Vec<_> = repos.iter().enumerate()
+        // GraphQL strings are escaped the same way as json ones
+        .map(|(i, (org, repo))| format!("r{}: repository(owner: {}, name: {}) {{ nameWithOwner isArchived pushedAt defaultBranchRef {{ name }} }}",
+                                        i, serde_json::Value::from(org.as_str()), serde_json::Value::from(repo.as_str())))
+        .collect();
+    format!("query {{ {} }}", fields.join(" "))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphqlRepository {
+    name_with_owner: String,
+    is_archived: bool,
+    pushed_at: Option<String>,
+    default_branch_ref: Option<GraphqlRef>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphqlRef {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphqlError {
+    #[serde(rename = "type", default)]
+    kind: Option<String>,
+    #[serde(default)]
+    path: Vec<serde_json::Value>,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphqlResponse {
+    #[serde(default)]
+    data: Option<BTreeMap<String, Option<GraphqlRepository>>>,
+    #[serde(default)]
+    errors: Vec<GraphqlError>,
+}
+
+/// Reads the answer to `query`. Repositories which don't exist come back as null with a
+/// NOT_FOUND error, those are None. Anything else that went wrong for a repository leaves it out,
+/// so it is checked on its own later.
+fn parse_batch(repos: &[(String, String)], body: &[u8], http_version: &str) -> Result<Vec<(String, Option<Repository>)>, serde_json::Error> {
+    let response: GraphqlResponse = serde_json::from_slice(body)?;
+    let mut data = response.data.unwrap_or_default();
+    let not_found: BTreeSet<_> = response.errors.iter()
+        .filter(|error| error.kind.as_deref() == Some("NOT_FOUND"))
+        .filter_map(|error| error.path.first().and_then(|alias| alias.as_str()))
+        .collect();
+    for error in response.errors.iter().filter(|error| error.kind.as_deref() != Some("NOT_FOUND")) {
+        debug!("GraphQL error for {:?}: {}", error.path, error.message);
+    }
+    let mut outcomes = vec![];
+    for (i, (org, repo)) in repos.iter().enumerate() {
+        let alias = format!("r{}", i);
+        match data.remove(&alias).flatten() {
+            Some(found) => {
+                debug!("{} last pushed at {:?}", found.name_with_owner, found.pushed_at);
+                outcomes.push((key(org, repo), Some(Repository {
+                    full_name: found.name_with_owner,
+                    archived: found.is_archived,
+                    default_branch: found.default_branch_ref.map(|branch| branch.name).unwrap_or_default(),
+                    http_version: http_version.to_string(),
+                })));
+            }
+            None if not_found.contains(alias.as_str()) => outcomes.push((key(org, repo), None)),
+            None => {}
+        }
+    }
+    Ok(outcomes)
+}
+
+/// Looks up all repositories among the urls ahead of time, with one GraphQL query per hundred
+/// of them. `check_repo` answers from what this found. Only works with a token.
+pub async fn prefetch(checker: &Checker, urls: &[String]) {
+    let token = match checker.github_token {
+        Some(ref token) => token,
+        None => return,
+    };
+    let repos: BTreeMap<_, _> = urls.iter()
+        .filter_map(|url| repo_of(url))
+        .map(|(org, repo)| (key(&org, &repo), (org, repo)))
+        .collect();
+    let repos: Vec<_> = repos.into_values().collect();
+    let url = format!("{}/graphql", API);
+    let host = checker.host(&url);
+    for batch in repos.chunks(BATCH_SIZE) {
+        let _handle = checker.handles.get().await;
+        let _permit = host.permit(&checker.opt, &url).await;
+        let body = serde_json::json!({ "query": query(batch) }).to_string();
+        let resp = checker.client.post(&url)
+            .header(header::AUTHORIZATION, token.clone())
+            .header(header::USER_AGENT, checker.user_agent(&url))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .timeout(checker.timeout(&url))
+            .send()
+            .await;
+        host.finished();
+        let resp = match resp {
+            Ok(resp) if resp.status().is_success() => resp,
+            Ok(resp) => {
+                warn!("GitHub GraphQL query failed with {}, checking {} repositories one by one", resp.status(), batch.len());
+                continue;
+            }
+            Err(err) => {
+                warn!("GitHub GraphQL query failed ({}), checking {} repositories one by one", err, batch.len());
+                continue;
+            }
+        };
+        let http_version = format!("{:?}", resp.version());
+        let outcomes = match read_body(checker, resp, checker.opt.max_body_size).await.map(|body| parse_batch(batch, &body, &http_version)) {
+            Ok(Ok(outcomes)) => outcomes,
+            Ok(Err(err)) => {
+                warn!("Unexpected answer to GitHub GraphQL query: {}", err);
+                continue;
+            }
+            Err(err) => {
+                warn!("Can't read answer to GitHub GraphQL query: {}", err);
+                continue;
+            }
+        };
+        info!("Looked up {} of {} repositories through GraphQL", outcomes.len(), batch.len());
+        checker.github_repos.lock().unwrap().extend(outcomes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repository_urls() {
+        let repo = |org: &str, repo: &str| Some((org.to_string(), repo.to_string()));
+        assert_eq!(repo_of("https://github.com/rust-lang/rust"), repo("rust-lang", "rust"));
+        assert_eq!(repo_of("https://www.github.com/serde-rs/serde/"), repo("serde-rs", "serde"));
+        assert_eq!(repo_of("http://github.com/tokio-rs/tokio.git"), repo("tokio-rs", "tokio"));
+        assert_eq!(repo_of("https://github.com/rust-lang/rust/wiki"), None);
+        assert_eq!(repo_of("https://github.com/rust-lang/rust/blob/master/README.md"), None);
+        assert_eq!(repo_of("https://github.com/rust-lang/rust#readme"), None);
+        assert_eq!(repo_of("https://github.com/rust-lang"), None);
+        assert_eq!(repo_of("https://github.com/topics/rust"), None);
+        assert_eq!(repo_of("https://gitlab.com/rust-lang/rust"), None);
+    }
+
+    #[test]
+    fn workflow_urls() {
+        let repository = Some("https://github.com/rust-lang/rust".to_string());
+        for url in &[
+            "https://github.com/rust-lang/rust/actions",
+            "https://github.com/rust-lang/rust/actions?workflow=CI",
+            "https://github.com/rust-lang/rust/actions/workflows/ci.yml",
+            "https://github.com/rust-lang/rust/actions/workflows/ci.yml/badge.svg",
+            "https://github.com/rust-lang/rust/actions/workflows/ci.yml/badge.svg?branch=master",
+            "https://github.com/rust-lang/rust/workflows/CI/badge.svg",
+            "http://github.com/rust-lang/rust/workflows/Rust%20CI/badge.svg?event=push",
+        ] {
+            assert_eq!(workflow_repository(url), repository, "{}", url);
+        }
+        for url in &[
+            "https://github.com/rust-lang/rust",
+            "https://github.com/rust-lang/rust/blob/master/.github/workflows/ci.yml",
+            "https://github.com/rust-lang/rust/actions/runs/123",
+            "https://github.com/rust-lang/rust/workflows/CI",
+            "https://gitlab.com/rust-lang/rust/actions",
+        ] {
+            assert_eq!(workflow_repository(url), None, "{}", url);
+        }
+    }
+
+    #[test]
+    fn markdown_files() {
+        assert_eq!(raw_markdown("https://github.com/serde-rs/serde/blob/master/README.md#getting-help").as_deref(),
+                   Some("https://raw.githubusercontent.com/serde-rs/serde/master/README.md"));
+        assert_eq!(raw_markdown("https://github.com/rust-lang/rust/blob/master/src/doc/guide.markdown").as_deref(),
+                   Some("https://raw.githubusercontent.com/rust-lang/rust/master/src/doc/guide.markdown"));
+        assert_eq!(raw_markdown("https://github.com/rust-lang/rust/blob/master/src/lib.rs#L10"), None);
+        assert_eq!(raw_markdown("https://github.com/rust-lang/rust#readme"), None);
+    }
+
+    #[test]
+    fn repository() {
+        let repository: Repository = serde_json::from_str(r#"{"id": 724712, "full_name": "rust-lang/rust", "archived": true, "default_branch": "master", "owner": {"login": "rust-lang"}}"#).unwrap();
+        assert_eq!(repository.full_name, "rust-lang/rust");
+        assert!(repository.archived);
+    }
+
+    #[test]
+    fn graphql_batch() {
+        let repos = vec![("rust-lang".to_string(), "rust".to_string()), ("nobody".to_string(), "gone".to_string()),
+                         ("Old".to_string(), "name".to_string()), ("timeout".to_string(), "repo".to_string())];
+        assert_eq!(query(&repos[..1]), r#"query { r0: repository(owner: "rust-lang", name: "rust") { nameWithOwner isArchived pushedAt defaultBranchRef { name } } }"#);
+        let body = br#"{
+            "data": {
+                "r0": {"nameWithOwner": "rust-lang/rust", "isArchived": false, "pushedAt": "2020-01-01T00:00:00Z", "defaultBranchRef": {"name": "master"}},
+                "r1": null,
+                "r2": {"nameWithOwner": "new/name", "isArchived": true, "pushedAt": null, "defaultBranchRef": null},
+                "r3": null
+            },
+            "errors": [
+                {"type": "NOT_FOUND", "path": ["r1"], "message": "Could not resolve to a Repository with the name 'nobody/gone'."},
+                {"path": ["r3"], "message": "Something went wrong"}
+            ]
+        }"#;
+        let outcomes = parse_batch(&repos, body, "HTTP/2.0").unwrap();
+        assert_eq!(outcomes.len(), 3);
+        assert_eq!(outcomes[0].0, "rust-lang/rust");
+        assert_eq!(outcomes[0].1.as_ref().unwrap().default_branch, "master");
+        assert_eq!(outcomes[1], ("nobody/gone".to_string(), None));
+        assert_eq!(outcomes[2].0, "old/name");
+        assert_eq!(outcomes[2].1.as_ref().unwrap().full_name, "new/name");
+        assert!(outcomes[2].1.as_ref().unwrap().archived);
+    }
+}
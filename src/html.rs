@@ -0,0 +1,190 @@
+use scraper::{Html, Selector};
+use url::Url;
+
+/// A url in an html fragment
+#[derive(Debug, Clone, PartialEq)]
+pub struct Link {
+    pub url: String,
+    /// Whether it's shown as an image, so it has to be one
+    pub image: bool,
+    /// The element it's embedded with, for anything but links and images
+    pub embed: Option<&'static str>,
+}
+
+/// The links and image sources in a piece of html, each once, plus what's wrong with the
+/// attributes we couldn't make sense of. With a `<base href>`, relative urls are relative to
+/// that instead of the file.
+pub fn links(html: &str) -> (Vec<Link>, Vec<String>) {
+    let fragment = Html::parse_fragment(html);
+    let base = fragment.select(&Selector::parse("base[href]").unwrap()).next()
+        .and_then(|element| Url::parse(element.value().attr("href")?.trim()).ok());
+    let mut links = vec![];
+    let mut warnings = vec![];
+    let mut add = |url: &str, image: bool, embed: Option<&'static str>| {
+        // Browsers ignore spaces around a url in an attribute, but not a stray &nbsp;
+        let url = url.trim_matches(|c: char| c.is_ascii_whitespace());
+        let url = match &base {
+            Some(base) if Url::parse(url) == Err(url::ParseError::RelativeUrlWithoutBase) => {
+                base.join(url).map_or_else(|_| url.to_string(), String::from)
+            }
+            _ => url.to_string(),
+        };
+        let link = Link {url, image, embed};
+        if !link.url.is_empty() && !links.contains(&link) {
+            links.push(link);
+        }
+    };
+    // Lazy loading scripts keep the real source in data- attributes until the image is visible
+    for element in fragment.select(&Selector::parse("img, picture source").unwrap()) {
+        for attribute in &["src", "data-src"] {
+            if let Some(src) = element.value().attr(attribute).filter(|_| element.value().name() == "img") {
+                add(src, true, None);
+            }
+        }
+        for attribute in &["srcset", "data-srcset"] {
+            if let Some(srcset) = element.value().attr(attribute) {
+                match parse_srcset(srcset) {
+                    Ok(urls) => urls.into_iter().for_each(|url| add(url, true, None)),
+                    Err(reason) => warnings.push(format!("malformed {} \"{}\": {}", attribute, srcset, reason)),
+                }
+            }
+        }
+    }
+    // Demos and previews, where a video's poster is an image like any other
+    let embeds = [("iframe", "src"), ("video", "src"), ("video source", "src"), ("audio", "src"),
+                  ("audio source", "src"), ("object", "data"), ("embed", "src")];
+    for (selector, attribute) in &embeds {
+        for element in fragment.select(&Selector::parse(selector).unwrap()) {
+            if let Some(src) = element.value().attr(attribute) {
+                add(src, false, Some(selector.split(' ').next().unwrap()));
+            }
+        }
+    }
+    for element in fragment.select(&Selector::parse("video[poster]").unwrap()) {
+        add(element.value().attr("poster").unwrap(), true, Some("video"));
+    }
+    for element in fragment.select(&Selector::parse("a").unwrap()) {
+        if let Some(href) = element.value().attr("href") {
+            add(href, false, None);
+        }
+    }
+    (links, warnings)
+}
+
+/// The urls in a `srcset`, like `logo.png, logo-2x.png 2x`, without the width or density
+/// descriptors after them. Urls can have commas in them, just not at the end.
+fn parse_srcset(srcset: &str) -> Result<Vec<&str>, String> {
+    let mut urls = vec![];
+    let mut rest = srcset;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == ',');
+        if rest.is_empty() {
+            break;
+        }
+        let end = rest.find(|c: char| c.is_ascii_whitespace()).unwrap_or(rest.len());
+        let url = &rest[..end];
+        rest = &rest[end..];
+        // A comma right after the url ends the candidate, otherwise descriptors follow
+        let trimmed = url.trim_end_matches(',');
+        let descriptor = if trimmed.len() < url.len() {
+            ""
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            let descriptor = rest[..end].trim();
+            rest = &rest[end..];
+            descriptor
+        };
+        if !descriptor.is_empty() && !is_descriptor(descriptor) {
+            return Err(format!("\"{}\" isn't a width or pixel density", descriptor));
+        }
+        urls.push(trimmed);
+    }
+    if urls.is_empty() {
+        return Err("no urls".to_string());
+    }
+    Ok(urls)
+}
+
+/// Whether this is a width like `640w` or a pixel density like `1.5x`
+fn is_descriptor(descriptor: &str) -> bool {
+    match descriptor.strip_suffix('w') {
+        Some(width) => !width.is_empty() && width.chars().all(|c| c.is_ascii_digit()),
+        None => descriptor.strip_suffix('x').and_then(|density| density.parse::<f64>().ok()).is_some_and(|density| density > 0.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srcsets() {
+        assert_eq!(parse_srcset("logo.png"), Ok(vec!["logo.png"]));
+        assert_eq!(parse_srcset(" logo.png 1x,\n  logo-2x.png 2x , logo-640.png 640w"), Ok(vec!["logo.png", "logo-2x.png", "logo-640.png"]));
+        assert_eq!(parse_srcset("https://cdn.example/w_100,h_100/logo.png 1.5x,https://cdn.example/big.png,"),
+                   Ok(vec!["https://cdn.example/w_100,h_100/logo.png", "https://cdn.example/big.png"]));
+        assert_eq!(parse_srcset("logo.png 2x large"), Err("\"2x large\" isn't a width or pixel density".to_string()));
+        assert_eq!(parse_srcset("logo.png big"), Err("\"big\" isn't a width or pixel density".to_string()));
+        assert_eq!(parse_srcset(" , "), Err("no urls".to_string()));
+    }
+
+    #[test]
+    fn fragment_links() {
+        let (links, warnings) = links(r#"
+<a href=" https://example.com/ "><img src="https://img.example/a.png" srcset="https://img.example/a.png 1x, https://img.example/a@2x.png 2x"></a>
+<picture>
+  <source srcset="https://img.example/dark.png" media="(prefers-color-scheme: dark)">
+  <img class="lazy" data-src="https://img.example/lazy.png" data-srcset="https://img.example/lazy.png 640w, https://img.example/lazy-big.png 1280w">
+</picture>
+<img srcset="https://img.example/broken.png 2 x">
+"#);
+        let link = |url: &str, image| Link {url: url.to_string(), image, embed: None};
+        assert_eq!(links, vec![
+            link("https://img.example/a.png", true),
+            link("https://img.example/a@2x.png", true),
+            link("https://img.example/dark.png", true),
+            link("https://img.example/lazy.png", true),
+            link("https://img.example/lazy-big.png", true),
+            link("https://example.com/", false),
+        ]);
+        assert_eq!(warnings, vec!["malformed srcset \"https://img.example/broken.png 2 x\": \"2 x\" isn't a width or pixel density"]);
+    }
+
+    #[test]
+    fn embeds() {
+        let (links, _) = links(r#"
+<iframe width="560" src="https://www.youtube.com/embed/dQw4w9WgXcQ"></iframe>
+<video poster="https://img.example/poster.jpg" controls>
+  <source src="https://media.example/demo.webm" type="video/webm">
+  <source src="demo.mp4" type="video/mp4">
+</video>
+<audio src="https://media.example/theme.ogg"></audio>
+<object data="https://media.example/diagram.svg"></object>
+"#);
+        let embedded: Vec<_> = links.iter().map(|link| (link.url.as_str(), link.image, link.embed)).collect();
+        assert_eq!(embedded, vec![
+            ("https://www.youtube.com/embed/dQw4w9WgXcQ", false, Some("iframe")),
+            ("https://media.example/demo.webm", false, Some("video")),
+            ("demo.mp4", false, Some("video")),
+            ("https://media.example/theme.ogg", false, Some("audio")),
+            ("https://media.example/diagram.svg", false, Some("object")),
+            ("https://img.example/poster.jpg", true, Some("video")),
+        ]);
+    }
+
+    #[test]
+    fn base_href() {
+        let (links, _) = links(r#"
+<base href="https://example.com/docs/">
+<table>
+  <tr><td><a href="guide.html">Guide</a></td><td><img src="../logo.png"></td></tr>
+  <tr><td><a href="/about">About</a></td><td><a href="https://other.example/">Other</a></td></tr>
+</table>
+"#);
+        let urls: Vec<_> = links.iter().map(|link| link.url.as_str()).collect();
+        assert_eq!(urls, vec!["https://example.com/logo.png", "https://example.com/docs/guide.html",
+                              "https://example.com/about", "https://other.example/"]);
+        let (without_base, _) = super::links(r#"<a href="guide.html">Guide</a>"#);
+        assert_eq!(without_base[0].url, "guide.html");
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::BTreeMap;
+use crate::report::{Report, ReportedFailure, State, SuggestionKind, escape, suite};
+
+/// Everything the page needs is in the file, so it can be passed around on its own
+const STYLE: &str = "\
+body { font-family: sans-serif; margin: 2em auto; max-width: 80em; padding: 0 1em; color: #222; }
+.summary span { display: inline-block; margin-right: 1.5em; }
+.failed { color: #b00; }
+table { border-collapse: collapse; width: 100%; }
+th, td { text-align: left; vertical-align: top; padding: 0.3em 0.6em; border-bottom: 1px solid #ddd; }
+tr.section th { background: #f3f3f3; }
+td { word-break: break-all; }
+ol.chain { list-style: none; margin: 0.3em 0 0; padding: 0; font-size: 0.9em; }
+ol.chain li { display: inline; }
+ol.chain li + li::before { content: \" \\2192  \"; color: #888; }
+.suggestion { background: #fff3b0; padding: 0.1em 0.3em; }
+input[type=search] { width: 30em; max-width: 100%; padding: 0.3em; margin-bottom: 1em; }
+";
+
+/// Hides the failures which don't have what's typed into the filter
+const SCRIPT: &str = "\
+document.getElementById('filter').addEventListener('input', function () {
+  var needle = this.value.toLowerCase();
+  document.querySelectorAll('#failures tr.failure').forEach(function (row) {
+    row.hidden = needle !== '' && row.textContent.toLowerCase().indexOf(needle) < 0;
+  });
+});
+";
+
+/// A link, if it's a url, otherwise the text
+fn link(url: &str) -> String {
+    if url.starts_with("http://") || url.starts_with("https://") {
+        format!("<a href=\"{0}\">{0}</a>", escape(url))
+    } else {
+        escape(url)
+    }
+}
+
+/// Where the redirects went, from where they started
+fn chain(start: &str, hops: &[String]) -> String {
+    if hops.is_empty() {
+        return String::new();
+    }
+    let hops: String = std::iter::once(start).chain(hops.iter().map(String::as_str))
+        .map(|hop| format!("<li>{}</li>", link(hop)))
+        .collect();
+    format!("<ol class=\"chain\">{}</ol>", hops)
+}
+
+fn failure_row(failure: &ReportedFailure) -> String {
+    let mut error = escape(&failure.failure.message);
+    error += &chain(failure.link, &failure.failure.redirect_chain);
+    if let Some(suggestion) = &failure.failure.suggestion {
+        error += &format!("<div><span class=\"suggestion\">did you mean {}?</span></div>", link(suggestion));
+    }
+    if let Some(snapshot) = failure.archived {
+        error += &format!("<div><span class=\"suggestion\">archived copy from {}: {}</span></div>", snapshot.date(), link(&snapshot.url));
+    }
+    format!(
+        "<tr class=\"failure\"><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+        link(failure.link), error, escape(&failure.failure.locations.join(", ")),
+    )
+}
+
+/// The report as a page of its own: the counts, the failures by section with a filter, the links
+/// with a better url, and the working links folded away
+pub fn render(report: &Report) -> String {
+    let counts = &report.summary;
+    let mut page = String::from("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n");
+    page += &format!("<title>Link check: {} broken links</title>\n<style>\n{}</style>\n</head>\n<body>\n", counts.failed, STYLE);
+    page += "<h1>Link check</h1>\n<p class=\"summary\">";
+    page += &format!("<span>{} urls in {} links</span>", counts.urls, counts.links);
+    page += &format!("<span>{} working</span>", counts.working);
+    page += &format!("<span class=\"failed\">{} failed ({} permanent)</span>", counts.failed, counts.permanent);
+    let others = [
+        ("temporarily unavailable", counts.unavailable),
+        ("unverifiable", counts.unverifiable),
+        ("need a login", counts.login_required),
+        ("suspect", counts.suspect),
+        ("not checked before the deadline", counts.unchecked),
+        ("ignored", counts.ignored_links),
+    ];
+    for (what, count) in others.iter().filter(|(_, count)| *count > 0) {
+        page += &format!("<span>{} {}</span>", count, what);
+    }
+    page += &format!("<span>took {:.1}s</span></p>\n", counts.duration_ms as f64 / 1000.0);
+
+    if !report.failures.is_empty() {
+        page += &format!("<h2>{} broken links</h2>\n", report.failures.len());
+        page += "<input id=\"filter\" type=\"search\" placeholder=\"Filter by url, error or section\">\n";
+        page += "<table id=\"failures\">\n<thead><tr><th>Link</th><th>Error</th><th>Where</th></tr></thead>\n";
+        let mut sections: BTreeMap<&str, Vec<&ReportedFailure>> = BTreeMap::new();
+        for failure in &report.failures {
+            sections.entry(suite(&failure.failure.sections, &failure.failure.locations)).or_default().push(failure);
+        }
+        for (section, failures) in sections {
+            page += &format!("<tbody>\n<tr class=\"section\"><th colspan=\"3\">{}</th></tr>\n", escape(section));
+            for failure in failures {
+                page += &failure_row(failure);
+            }
+            page += "</tbody>\n";
+        }
+        page += "</table>\n";
+    }
+
+    let suspect: Vec<_> = report.urls.iter().filter(|url| url.state == State::Suspect).collect();
+    if !suspect.is_empty() {
+        page += &format!("<h2>{} suspect links</h2>\n<ul>\n", suspect.len());
+        for url in suspect {
+            page += &format!("<li>{}: {} ({})</li>\n", escape(url.reason.unwrap_or_default()), link(url.url), escape(&url.locations.join(", ")));
+        }
+        page += "</ul>\n";
+    }
+
+    let better: Vec<_> = report.suggestions.iter()
+        .filter(|suggestion| matches!(suggestion.kind, SuggestionKind::BetterUrl | SuggestionKind::MovedToHttps))
+        .collect();
+    if !better.is_empty() {
+        page += &format!("<h2>{} links with a better url</h2>\n<ul>\n", better.len());
+        for suggestion in better {
+            page += &format!("<li>{} should be <span class=\"suggestion\">{}</span></li>\n", link(suggestion.link), link(suggestion.replacement));
+        }
+        page += "</ul>\n";
+    }
+
+    let working: Vec<_> = report.urls.iter().filter(|url| url.state == State::Working).collect();
+    if !working.is_empty() {
+        page += &format!("<details>\n<summary>{} working links</summary>\n<ul>\n", working.len());
+        for url in working {
+            page += &format!("<li>{}{}</li>\n", link(url.url), chain(url.url, url.redirects));
+        }
+        page += "</ul>\n</details>\n";
+    }
+    if !report.failures.is_empty() {
+        page += &format!("<script>\n{}</script>\n", SCRIPT);
+    }
+    page += "</body>\n</html>\n";
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::Snapshot;
+    use crate::report::Fixture;
+
+    #[test]
+    fn page() {
+        let mut fixture = Fixture::new();
+        let failure = fixture.results.failed.get_mut("https://b.example/").unwrap();
+        failure.message = "[404] https://b.example/ -> https://b.example/<new>".to_string();
+        failure.sections = vec!["Applications > Audio".to_string()];
+        failure.redirect_chain = vec!["https://b.example/<new>".to_string()];
+        fixture.results.archived.insert("https://b.example/".to_string(), Snapshot {url: "https://web.archive.org/web/2019/https://b.example/".to_string(), timestamp: "20190102030405".to_string()});
+        let page = render(&fixture.report());
+        for expected in &[
+            "<title>Link check: 2 broken links</title>",
+            "<span class=\"failed\">2 failed (1 permanent)</span>",
+            "<span>took 1.5s</span>",
+            "<input id=\"filter\" type=\"search\"",
+            "<tr class=\"section\"><th colspan=\"3\">Applications &gt; Audio</th></tr>",
+            "<tr class=\"section\"><th colspan=\"3\">README.md</th></tr>",
+            "<td><a href=\"https://b.example/\">https://b.example/</a></td>",
+            "<ol class=\"chain\"><li><a href=\"https://b.example/\">https://b.example/</a></li><li><a href=\"https://b.example/&lt;new&gt;\">https://b.example/&lt;new&gt;</a></li></ol>",
+            "<span class=\"suggestion\">archived copy from 2019-01-02: <a href=\"https://web.archive.org/web/2019/https://b.example/\">",
+            "<td>README.md#usage</td><td>[no such heading] #usage<div><span class=\"suggestion\">did you mean #usages?</span></div></td><td>README.md:7:3</td>",
+            "<h2>1 links with a better url</h2>",
+            "<details>\n<summary>1 working links</summary>",
+            "document.getElementById('filter')",
+        ] {
+            assert!(page.contains(expected), "{} isn't in\n{}", expected, page);
+        }
+        // Self-contained
+        assert!(!page.contains("<link") && !page.contains("src="));
+    }
+}
@@ -0,0 +1,135 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::ops::Range;
+use crate::lines::Lines;
+use crate::references::code_blocks;
+
+/// The parts of a document where `<!-- link-check-disable -->` ... `<!-- link-check-enable -->`
+/// and `<!-- link-check-ignore-next -->` comments say not to check links, and the ones which are
+/// commented out altogether
+#[derive(Debug, Default)]
+pub struct Ignored {
+    disabled: Vec<Range<usize>>,
+    comments: Vec<Range<usize>>,
+    /// Where ignore-next markers are which haven't skipped a link yet
+    next: Vec<usize>,
+}
+
+impl Ignored {
+    /// Finds the markers, or the line of the first one without its counterpart
+    pub fn from_markdown(source: &str) -> Result<Ignored, String> {
+        lazy_static! {
+            static ref MARKER_REGEX: Regex = Regex::new(r"<!--\s*link-check-(disable|enable|ignore-next)\s*-->").unwrap();
+            static ref COMMENT_REGEX: Regex = Regex::new(r"(?s)<!--.*?(-->|$)").unwrap();
+        }
+        let lines = Lines::new(source);
+        let line = |offset| lines.line(offset);
+        let code = code_blocks(source);
+        // Html blocks come one line at a time, so a line of a comment looks like html of its own
+        let comments = COMMENT_REGEX.find_iter(source)
+            .filter(|comment| !code.iter().any(|range| range.contains(&comment.start())))
+            .map(|comment| comment.range())
+            .collect();
+        let mut ignored = Ignored {comments, ..Ignored::default()};
+        let mut disabled_at = None;
+        for captures in MARKER_REGEX.captures_iter(source) {
+            let marker = captures.get(0).unwrap();
+            if code.iter().any(|range| range.contains(&marker.start())) {
+                continue;
+            }
+            match (&captures[1], disabled_at) {
+                ("disable", None) => disabled_at = Some(marker.start()),
+                ("disable", Some(start)) => {
+                    return Err(format!("line {}: link-check-disable, but links are disabled since line {} already", line(marker.start()), line(start)));
+                }
+                ("enable", Some(start)) => {
+                    ignored.disabled.push(start..marker.end());
+                    disabled_at = None;
+                }
+                ("enable", None) => {
+                    return Err(format!("line {}: link-check-enable without a link-check-disable before it", line(marker.start())));
+                }
+                _ => ignored.next.push(marker.end()),
+            }
+        }
+        match disabled_at {
+            Some(start) => Err(format!("line {}: link-check-disable without a link-check-enable after it", line(start))),
+            None => Ok(ignored),
+        }
+    }
+
+    /// Whether the link at this offset is inside an html comment, so nobody sees it
+    pub fn commented_out(&self, offset: usize) -> bool {
+        self.comments.iter().any(|range| range.contains(&offset))
+    }
+
+    /// Whether to skip the link at this offset. Links have to be asked about in the order they
+    /// are in, so an ignore-next marker skips the one right after it.
+    pub fn ignores(&mut self, offset: usize) -> bool {
+        if self.disabled.iter().any(|range| range.contains(&offset)) {
+            return true;
+        }
+        match self.next.first() {
+            Some(marker) if *marker <= offset => {
+                self.next.remove(0);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ignored_regions() {
+        let source = "\
+[a](https://a.example)
+<!-- link-check-disable -->
+[b](https://b.example)
+<!-- link-check-enable -->
+<!-- link-check-ignore-next -->
+[c](https://c.example) [d](https://d.example)
+
+```
+<!-- link-check-disable -->
+```
+";
+        let mut ignored = Ignored::from_markdown(source).unwrap();
+        let offsets: Vec<_> = ["[a]", "[b]", "[c]", "[d]"].iter().map(|link| source.find(link).unwrap()).collect();
+        let skipped: Vec<_> = offsets.into_iter().map(|offset| ignored.ignores(offset)).collect();
+        assert_eq!(skipped, vec![false, true, true, false]);
+    }
+
+    #[test]
+    fn unbalanced_markers() {
+        let error = |source| Ignored::from_markdown(source).unwrap_err();
+        assert_eq!(error("a\n<!-- link-check-disable -->\nb"), "line 2: link-check-disable without a link-check-enable after it");
+        assert_eq!(error("<!-- link-check-enable -->"), "line 1: link-check-enable without a link-check-disable before it");
+        assert_eq!(error("<!-- link-check-disable -->\n<!-- link-check-disable -->\n<!-- link-check-enable -->"),
+                   "line 2: link-check-disable, but links are disabled since line 1 already");
+    }
+
+    #[test]
+    fn commented_out() {
+        let source = "\
+[a](https://a.example)
+<!--
+<a href=\"https://dead.example\">dead</a>
+-->
+<a href=\"https://b.example\">b</a> <!-- <a href=\"https://c.example\">c</a> -->
+
+```
+<!--
+```
+[d](https://d.example)
+";
+        let ignored = Ignored::from_markdown(source).unwrap();
+        let commented: Vec<_> = ["[a]", "<a href=\"https://dead", "<a href=\"https://b", "[d]"].iter()
+            .map(|link| ignored.commented_out(source.find(link).unwrap()))
+            .collect();
+        assert_eq!(commented, vec![false, true, false, false]);
+    }
+}
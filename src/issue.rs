@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+use crate::failures::{split_location, Kind};
+use crate::report::{Report, ReportedFailure, SuggestionKind, name};
+
+/// Where a link is, for a reader of the issue: the section and the line, and the file too if
+/// there's more than one
+fn place(sections: &[String], locations: &[String], files: &[String]) -> String {
+    let mut parts = vec![];
+    if let Some(section) = sections.first() {
+        parts.push(format!("section \"{}\"", section));
+    }
+    if let Some((file, line, _)) = locations.first().and_then(|location| split_location(location)) {
+        if files.len() > 1 {
+            parts.push(format!("{} line {}", file, line));
+        } else {
+            parts.push(format!("line {}", line));
+        }
+    }
+    if parts.is_empty() {
+        String::new()
+    } else {
+        format!(" ({})", parts.join(", "))
+    }
+}
+
+fn task(failure: &ReportedFailure, files: &[String]) -> String {
+    let mut task = format!("- [ ] {}{}", failure.failure.message, place(&failure.failure.sections, &failure.failure.locations, files));
+    let mut suggestions = vec![];
+    if let Some(suggestion) = &failure.failure.suggestion {
+        suggestions.push(suggestion.clone());
+    }
+    if let Some(snapshot) = failure.archived {
+        suggestions.push(format!("archived copy {}", snapshot.url));
+    }
+    if !suggestions.is_empty() {
+        task += &format!(" \u{2014} suggestion: {}", suggestions.join(", or "));
+    }
+    task
+}
+
+/// The report as the body of an issue: a line with the counts, then a task for each broken link,
+/// grouped by what's wrong with it, and one for each link with a better url. Tools parse the
+/// tasks, so they keep the form `- [ ] message (section "...", line N) — suggestion: ...`.
+pub fn render(report: &Report) -> String {
+    let counts = &report.summary;
+    let mut issue = format!(
+        "**{} broken links** out of {} urls in {} ({} permanent)\n",
+        counts.failed, counts.urls, counts.files.join(", "), counts.permanent,
+    );
+    let mut kinds: BTreeMap<Kind, Vec<&ReportedFailure>> = BTreeMap::new();
+    for failure in &report.failures {
+        kinds.entry(failure.failure.kind).or_default().push(failure);
+    }
+    for (kind, failures) in kinds {
+        let mut title = name(kind).replace('_', " ");
+        title[..1].make_ascii_uppercase();
+        issue += &format!("\n### {} ({})\n\n", title, failures.len());
+        for failure in failures {
+            issue += &task(failure, &counts.files);
+            issue += "\n";
+        }
+    }
+    let better: Vec<_> = report.suggestions.iter()
+        .filter(|suggestion| matches!(suggestion.kind, SuggestionKind::BetterUrl | SuggestionKind::MovedToHttps))
+        .collect();
+    if !better.is_empty() {
+        issue += &format!("\n### Better urls ({})\n\n", better.len());
+        for suggestion in better {
+            let url = report.urls.iter().find(|url| url.url == suggestion.link);
+            let place = url.map(|url| place(url.sections, url.locations, &counts.files)).unwrap_or_default();
+            issue += &format!("- [ ] {}{} \u{2014} suggestion: {}\n", suggestion.link, place, suggestion.replacement);
+        }
+    }
+    issue
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::Snapshot;
+    use crate::failures::Failure;
+    use crate::report::Fixture;
+
+    #[test]
+    fn issue_body() {
+        let mut fixture = Fixture::new();
+        fixture.results.failed.get_mut("https://b.example/").unwrap().sections = vec!["Applications > Audio".to_string()];
+        fixture.results.archived.insert("https://b.example/".to_string(), Snapshot {url: "https://web.archive.org/web/2019/https://b.example/".to_string(), timestamp: "20190102030405".to_string()});
+        let mut failure = Failure::new(Kind::Http, "[500] https://broken.example/".to_string());
+        failure.locations = vec!["README.md:5:3".to_string()];
+        fixture.results.failed.insert("https://broken.example/".to_string(), failure);
+        fixture.locations.insert("https://broken.example/".to_string(), vec!["README.md:5:3".to_string()]);
+        assert_eq!(render(&fixture.report()), "\
+**3 broken links** out of 4 urls in README.md (1 permanent)
+
+### Http (2)
+
+- [ ] [404] https://b.example/ (section \"Applications > Audio\", line 3) \u{2014} suggestion: archived copy https://web.archive.org/web/2019/https://b.example/
+- [ ] [500] https://broken.example/ (line 5)
+
+### No such heading (1)
+
+- [ ] [no such heading] #usage (line 7) \u{2014} suggestion: #usages
+
+### Better urls (1)
+
+- [ ] https://a.example/ (line 1) \u{2014} suggestion: https://a.example/home
+");
+    }
+}
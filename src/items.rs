@@ -0,0 +1,116 @@
+use pulldown_cmark::{Event, Parser, Tag};
+
+/// A piece of an entry, with the badges (images, and links around nothing but an image) told
+/// apart from what the entry says
+#[derive(Debug, Clone, PartialEq)]
+pub enum Piece {
+    Text(String),
+    Link {url: String, text: String, offset: usize},
+    Badge,
+}
+
+/// A list item, with the list it's in
+#[derive(Debug, Clone, PartialEq)]
+pub struct Item {
+    pub offset: usize,
+    /// Which item it is, counting from 0 through the document in the order they start. Items of
+    /// sub-lists count on their own.
+    pub index: usize,
+    /// Lists are numbered in the order they start
+    pub list: usize,
+    pub pieces: Vec<Piece>,
+    pub sublist: bool,
+}
+
+/// The items of the lists in a document, in the order they end, so sub-lists come before the
+/// item they're in. This is the one pass over them that the lints and the duplicates share.
+pub fn items(source: &str) -> Vec<Item> {
+    let mut items = vec![];
+    let mut open: Vec<Item> = vec![];
+    let mut lists = vec![];
+    let mut list_count = 0;
+    let mut item_count = 0;
+    // The link we're in, with its url, text and offset, and whether there's an image in it
+    let mut link: Option<(String, String, usize, bool)> = None;
+    let mut in_image = 0;
+    for (event, range) in Parser::new(source).into_offset_iter() {
+        match event {
+            Event::Start(Tag::List(_)) => {
+                if let Some(item) = open.last_mut() {
+                    item.sublist = true;
+                }
+                lists.push(list_count);
+                list_count += 1;
+            }
+            Event::End(Tag::List(_)) => {
+                lists.pop();
+            }
+            Event::Start(Tag::Item) => {
+                let list = lists.last().copied().unwrap_or_default();
+                open.push(Item {offset: range.start, index: item_count, list, pieces: vec![], sublist: false});
+                item_count += 1;
+            }
+            Event::End(Tag::Item) => items.extend(open.pop()),
+            Event::Start(Tag::Link(_, url, _)) => link = Some((url.to_string(), String::new(), range.start, false)),
+            Event::End(Tag::Link(..)) => {
+                if let (Some((url, text, offset, image)), Some(item)) = (link.take(), open.last_mut()) {
+                    if image && text.trim().is_empty() {
+                        item.pieces.push(Piece::Badge);
+                    } else {
+                        item.pieces.push(Piece::Link {url, text, offset});
+                    }
+                }
+            }
+            Event::Start(Tag::Image(..)) => {
+                in_image += 1;
+                match link.as_mut() {
+                    Some((_, _, _, image)) => *image = true,
+                    None => open.last_mut().into_iter().for_each(|item| item.pieces.push(Piece::Badge)),
+                }
+            }
+            Event::End(Tag::Image(..)) => in_image -= 1,
+            // Badges are often written as html, like `[<img src="...">](...)`
+            Event::Html(html) if html.contains("<img") => match link.as_mut() {
+                Some((_, _, _, image)) => *image = true,
+                None => open.last_mut().into_iter().for_each(|item| item.pieces.push(Piece::Badge)),
+            },
+            // An image's alt text isn't part of what the entry says
+            Event::Text(text) | Event::Code(text) if in_image == 0 => push_text(&mut link, &mut open, &text),
+            Event::SoftBreak | Event::HardBreak => push_text(&mut link, &mut open, " "),
+            _ => {}
+        }
+    }
+    items
+}
+
+fn push_text(link: &mut Option<(String, String, usize, bool)>, open: &mut [Item], text: &str) {
+    if let Some((_, link_text, _, _)) = link.as_mut() {
+        link_text.push_str(text);
+    } else if let Some(item) = open.last_mut() {
+        match item.pieces.last_mut() {
+            Some(Piece::Text(previous)) => previous.push_str(text),
+            _ => item.pieces.push(Piece::Text(text.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pieces() {
+        let source = "\
+* [serde](https://serde.rs) — Serialization [![crates.io](https://img.shields.io/crates/v/serde.svg)](https://crates.io/crates/serde)
+  * [`serde_json`](https://github.com/serde-rs/json) <img src=\"https://img.example/b.svg\">
+* Plain
+";
+        let items: Vec<_> = items(source).into_iter().map(|item| (item.index, item.list, item.sublist, item.pieces)).collect();
+        let link = |url: &str, text: &str| Piece::Link {url: url.to_string(), text: text.to_string(), offset: source[..source.find(url).unwrap()].rfind('[').unwrap()};
+        assert_eq!(items, vec![
+            (1, 1, false, vec![link("https://github.com/serde-rs/json", "serde_json"), Piece::Text(" ".to_string()), Piece::Badge]),
+            (0, 0, true, vec![link("https://serde.rs", "serde"), Piece::Text(" — Serialization ".to_string()), Piece::Badge]),
+            (2, 0, false, vec![Piece::Text("Plain".to_string())]),
+        ]);
+    }
+}
@@ -0,0 +1,43 @@
+/// Where the lines of a document start, to turn byte offsets into lines without counting the
+/// newlines before each of them
+pub struct Lines<'a> {
+    source: &'a str,
+    starts: Vec<usize>,
+}
+
+impl<'a> Lines<'a> {
+    pub fn new(source: &'a str) -> Lines<'a> {
+        let starts = std::iter::once(0).chain(source.match_indices('\n').map(|(newline, _)| newline + 1)).collect();
+        Lines {source, starts}
+    }
+
+    /// The (1-based) line of a byte offset
+    pub fn line(&self, offset: usize) -> usize {
+        self.starts.partition_point(|&start| start <= offset)
+    }
+
+    /// The (1-based) line and column of a byte offset, counting characters rather than bytes
+    pub fn line_column(&self, offset: usize) -> (usize, usize) {
+        let line = self.line(offset);
+        (line, self.source[self.starts[line - 1]..offset].chars().count() + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_columns() {
+        let source = "# Awesome Rust\n\n* [serde](https://serde.rs) — “fast”\n* [tokio](https://tokio.rs)";
+        let lines = Lines::new(source);
+        assert_eq!(lines.line_column(0), (1, 1));
+        assert_eq!(lines.line_column(source.find("[serde]").unwrap()), (3, 3));
+        assert_eq!(lines.line_column(source.find("[tokio]").unwrap()), (4, 3));
+        // Columns count characters, not bytes
+        assert_eq!(lines.line_column(source.find("fast").unwrap()), (3, 32));
+        // The newline is still on its line
+        assert_eq!(lines.line_column(source.find('\n').unwrap()), (1, 15));
+        assert_eq!(lines.line(source.len()), 4);
+    }
+}
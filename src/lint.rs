@@ -0,0 +1,210 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use crate::items::{Item, Piece};
+use crate::lines::Lines;
+
+/// How entries are supposed to look: `* [name](url) — Description.`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EntryFormat {
+    /// What can go between the link and the description
+    pub separators: Vec<String>,
+    /// What a description can end with. Empty if it can end with anything
+    pub endings: Vec<String>,
+}
+
+impl Default for EntryFormat {
+    fn default() -> EntryFormat {
+        EntryFormat {
+            separators: vec!["—".to_string(), "-".to_string()],
+            endings: vec![".".to_string(), "!".to_string(), "?".to_string()],
+        }
+    }
+}
+
+/// An entry which doesn't look the way it should
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    /// Where the entry starts
+    pub offset: usize,
+    pub rule: String,
+}
+
+/// The entries of the lists in a document which don't follow the format. Entries with a list of
+/// their own are categories rather than entries, and ones with nothing but links to headings
+/// are a table of contents, so those are left alone, as are lines with nothing but badges.
+pub fn entries(items: &[Item], format: &EntryFormat) -> Vec<Violation> {
+    let mut violations: Vec<_> = items.iter()
+        .filter(|item| !item.sublist)
+        .filter_map(|item| Some(Violation {offset: item.offset, rule: check(&item.pieces, format)?}))
+        .collect();
+    violations.sort_by_key(|violation| violation.offset);
+    violations
+}
+
+/// The first entry of each list which isn't in alphabetical order, by the text of the link it
+/// starts with, not minding case or punctuation. Items which don't start with a link don't count,
+/// and neither do links to headings.
+pub fn order(items: &[Item], lines: &Lines) -> Vec<Violation> {
+    let mut lists: BTreeMap<usize, Vec<(usize, String, String)>> = BTreeMap::new();
+    for item in items {
+        if let Some(Piece::Link {url, text, ..}) = item.pieces.iter().find(|piece| !matches!(piece, Piece::Text(text) if text.trim().is_empty())) {
+            if !url.starts_with('#') {
+                lists.entry(item.list).or_default().push((item.offset, text.trim().to_string(), sort_key(text)));
+            }
+        }
+    }
+    let mut violations = vec![];
+    for mut entries in lists.into_values() {
+        entries.sort_by_key(|(offset, _, _)| *offset);
+        let misplaced = (1..entries.len()).find(|&i| entries[i].2 < entries[i - 1].2);
+        if let Some(i) = misplaced {
+            let (offset, name, key) = &entries[i];
+            // It goes before the first entry which sorts after it
+            let (before, before_name, _) = entries.iter().find(|(_, _, other)| other > key).unwrap();
+            let line = lines.line(*before);
+            violations.push(Violation {offset: *offset, rule: format!("{} is out of order, it goes before {} on line {}", name, before_name, line)});
+        }
+    }
+    violations.sort_by_key(|violation| violation.offset);
+    violations
+}
+
+/// What entries are sorted by: their name in lowercase, without punctuation or spaces
+fn sort_key(name: &str) -> String {
+    name.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect()
+}
+
+/// The first rule an entry breaks, if it breaks any
+fn check(pieces: &[Piece], format: &EntryFormat) -> Option<String> {
+    let pieces: Vec<_> = pieces.iter().filter(|piece| !matches!(piece, Piece::Text(text) if text.trim().is_empty())).collect();
+    // A line of badges, or nothing at all
+    if pieces.iter().all(|piece| **piece == Piece::Badge) {
+        return None;
+    }
+    match pieces[0] {
+        Piece::Link {url, ..} if url.starts_with('#') => return None,
+        Piece::Link {..} => {}
+        _ => return Some("doesn't start with a link".to_string()),
+    }
+    if pieces[1..].iter().all(|piece| **piece == Piece::Badge) {
+        return None;
+    }
+    let separators = format.separators.join(" or ");
+    let after_link = match pieces[1] {
+        Piece::Text(text) => text.trim_start(),
+        Piece::Link {..} => return Some("more than one link before the description".to_string()),
+        Piece::Badge => return Some(format!("no {} between the link and the description", separators)),
+    };
+    let description = format.separators.iter()
+        .filter_map(|separator| after_link.strip_prefix(separator.as_str()))
+        .find(|description| description.is_empty() || description.starts_with(char::is_whitespace));
+    let description = match description {
+        Some(description) => description,
+        None => {
+            let written = after_link.split_whitespace().next().unwrap_or_default();
+            if written.chars().all(|c| !c.is_alphanumeric()) {
+                return Some(format!("{} between the link and the description instead of {}", written, separators));
+            }
+            return Some(format!("no {} between the link and the description", separators));
+        }
+    };
+    // What the entry says, up to the badges at the end
+    let mut said = description.to_string();
+    let end = pieces.iter().rposition(|piece| **piece != Piece::Badge).unwrap();
+    for piece in pieces.iter().take(end + 1).skip(2) {
+        match piece {
+            Piece::Text(text) | Piece::Link {text, ..} => said.push_str(text),
+            Piece::Badge => {}
+        }
+    }
+    let said = said.trim();
+    if said.is_empty() {
+        return Some("no description".to_string());
+    }
+    if !format.endings.is_empty() && !format.endings.iter().any(|ending| said.ends_with(ending.as_str())) {
+        return Some(format!("the description doesn't end with {}", format.endings.join(" or ")));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::items::items;
+
+    fn rules(source: &str) -> Vec<(usize, String)> {
+        entries(&items(source), &EntryFormat::default()).into_iter()
+            .map(|violation| (Lines::new(source).line(violation.offset), violation.rule))
+            .collect()
+    }
+
+    #[test]
+    fn well_formed_entries() {
+        let source = "\
+* [serde](https://serde.rs) — Serialization framework. [![crates.io](https://img.shields.io/crates/v/serde.svg)](https://crates.io/crates/serde)
+* [tokio](https://tokio.rs) - An async runtime, see [the docs](https://docs.rs/tokio).
+* [`rg`](https://github.com/BurntSushi/ripgrep) — Searches
+  recursively!
+* [kondrak/rust64](https://github.com/kondrak/rust64) [<img src=\"https://api.travis-ci.org/kondrak/rust64.svg\">](https://travis-ci.org/kondrak/rust64)
+* [Sandstorm Collections App](https://github.com/sandstorm-io/collections-app)
+* Commodore 64
+  * [kondrak/rust64](https://github.com/kondrak/rust64) — A Commodore 64 emulator.
+- [Applications](#applications)
+  - [Audio](#audio)
+";
+        assert_eq!(rules(source), vec![]);
+    }
+
+    #[test]
+    fn broken_rules() {
+        let source = "\
+* Plain text, no link.
+* [a](https://a.example) [b](https://b.example) — Two links.
+* [cargo-tree](https://github.com/sfackler/cargo-tree) – Shows the dependency graph.
+* [tinyrick](https://github.com/mcandre/tinyrick) a basic task dependency tool.
+* [empty](https://empty.example) — [![badge](https://img.example/b.svg)](https://ci.example)
+* [unfinished](https://unfinished.example) — A description without a full stop [![badge](https://img.example/b.svg)](https://ci.example)
+";
+        assert_eq!(rules(source), vec![
+            (1, "doesn't start with a link".to_string()),
+            (2, "more than one link before the description".to_string()),
+            (3, "– between the link and the description instead of — or -".to_string()),
+            (4, "no — or - between the link and the description".to_string()),
+            (5, "no description".to_string()),
+            (6, "the description doesn't end with . or ! or ?".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn tolerances() {
+        let format = EntryFormat {separators: vec![":".to_string()], endings: vec![]};
+        let violations = entries(&items("* [a](https://a.example): no full stop\n* [b](https://b.example) — Dash.\n"), &format);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].rule, "— between the link and the description instead of :");
+    }
+
+    #[test]
+    fn alphabetical_order() {
+        let source = "\
+* [Actix](https://actix.rs) — A.
+* [alacritty](https://github.com/alacritty/alacritty) — B.
+* [`cargo-edit`](https://github.com/killercup/cargo-edit) — C.
+* [Cargo make](https://github.com/sagiegurari/cargo-make) — D.
+* [serde](https://serde.rs) — E.
+* [rayon](https://github.com/rayon-rs/rayon) — F.
+* [bat](https://github.com/sharkdp/bat) — G.
+* Emulators
+  * [zinc](https://zinc.example) — H.
+  * [kondrak/rust64](https://github.com/kondrak/rust64) — I.
+- [Libraries](#libraries)
+- [Applications](#applications)
+";
+        let violations: Vec<_> = order(&items(source), &Lines::new(source)).into_iter().map(|violation| violation.rule).collect();
+        assert_eq!(violations, vec![
+            "rayon is out of order, it goes before serde on line 5",
+            "kondrak/rust64 is out of order, it goes before zinc on line 9",
+        ]);
+        assert_eq!(sort_key("`Cargo-Edit`"), "cargoedit");
+    }
+}
@@ -0,0 +1,166 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use crate::anchors::Anchors;
+
+/// Whether a link points to a file next to the markdown instead of somewhere on the web, like
+/// `CONTRIBUTING.md` or `../logo.png`
+pub fn is_relative(url: &str) -> bool {
+    lazy_static! {
+        static ref SCHEME_REGEX: Regex = Regex::new(r"^[a-zA-Z][a-zA-Z0-9+.-]*:").unwrap();
+    }
+    !url.is_empty() && !url.starts_with('#') && !url.starts_with("//") && !SCHEME_REGEX.is_match(url)
+}
+
+/// What's wrong with a relative link
+#[derive(Debug, PartialEq)]
+pub enum Problem {
+    /// There's no such file, or only one whose name differs in case
+    NoSuchFile(PathBuf),
+    /// The markdown file exists, but has no heading for the fragment
+    NoSuchHeading {
+        file: PathBuf,
+        nearest: Option<String>,
+    },
+}
+
+/// Checks a relative link from a markdown file in `dir`. Links starting with `/` are relative
+/// to `root`, which is where GitHub resolves them.
+pub fn check(root: &Path, dir: &Path, url: &str) -> Result<PathBuf, Problem> {
+    let (path, fragment) = match url.find('#') {
+        Some(hash) => (&url[..hash], Some(&url[hash + 1..])),
+        None => (url, None),
+    };
+    let path = path.split('?').next().unwrap_or_default();
+    let path = percent_encoding::percent_decode_str(path).decode_utf8_lossy();
+    let file = match path.strip_prefix('/') {
+        Some(path) => root.join(path),
+        None => dir.join(&*path),
+    };
+    if !exists_case_sensitive(&file) {
+        return Err(Problem::NoSuchFile(file));
+    }
+    if let Some(fragment) = fragment.filter(|_| file.extension().is_some_and(|extension| extension == "md")) {
+        let anchor = percent_encoding::percent_decode_str(fragment).decode_utf8_lossy();
+        let anchors = Anchors::from_markdown(&fs::read_to_string(&file).unwrap_or_default());
+        if !fragment.is_empty() && !anchors.contains(&anchor) {
+            let nearest = anchors.nearest(&anchor).map(str::to_string);
+            return Err(Problem::NoSuchHeading {file, nearest});
+        }
+    }
+    Ok(file)
+}
+
+pub fn is_markdown(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "md") && path.is_file()
+}
+
+/// The path without any `..` or symlinks, so the same file always looks the same
+pub fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Whether the path is somewhere below `root`, rather than e.g. `../../elsewhere.md`
+pub fn inside(root: &Path, path: &Path) -> bool {
+    canonical(path).starts_with(canonical(root))
+}
+
+/// Whether the path exists with exactly this case. GitHub (and Linux) only find `Logo.png` as
+/// `Logo.png`, even where the file system of whoever wrote the link doesn't care.
+fn exists_case_sensitive(path: &Path) -> bool {
+    if !path.exists() {
+        return false;
+    }
+    let mut current = PathBuf::new();
+    for component in path.components() {
+        if let Component::Normal(name) = component {
+            let listed = fs::read_dir(if current.as_os_str().is_empty() { Path::new(".") } else { &current })
+                .map(|entries| entries.filter_map(Result::ok).any(|entry| entry.file_name() == name))
+                .unwrap_or(false);
+            if !listed {
+                return false;
+            }
+        }
+        current.push(component);
+    }
+    true
+}
+
+/// The files matching a pattern, where `*` is any part of a name, `?` any one character and
+/// `**` any number of directories. A pattern without any of them is the file itself, whether it
+/// exists or not, so it's reported like any other file we can't read.
+pub fn glob(pattern: &Path) -> Vec<PathBuf> {
+    let pattern = pattern.to_string_lossy();
+    if !pattern.contains(['*', '?']) {
+        return vec![PathBuf::from(pattern.as_ref())];
+    }
+    // Like in a shell, only patterns starting with a dot find hidden files
+    let options = glob::MatchOptions {case_sensitive: true, require_literal_separator: true, require_literal_leading_dot: true};
+    let mut matches: Vec<_> = glob::glob_with(&pattern, options).into_iter().flatten()
+        .filter_map(Result::ok)
+        .filter(|path| path.is_file())
+        .collect();
+    matches.sort();
+    matches
+}
+
+/// Matches a name against a pattern with `*` and `?`, case sensitively like the files are
+pub fn wildcard_matches(pattern: &str, name: &str) -> bool {
+    glob::Pattern::new(pattern).is_ok_and(|pattern| pattern.matches(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_urls() {
+        for url in &["CONTRIBUTING.md", "./logo.png", "../README.md", "/docs/", "docs/a%20b.md#intro"] {
+            assert!(is_relative(url), "{}", url);
+        }
+        for url in &["https://example.com", "#heading", "mailto:a@example.com", "//cdn.example.com/x.js", ""] {
+            assert!(!is_relative(url), "{}", url);
+        }
+    }
+
+    #[test]
+    fn repository_files() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let src = root.join("src");
+        assert_eq!(check(root, root, "CONTRIBUTING.md"), Ok(root.join("CONTRIBUTING.md")));
+        assert!(check(root, root, "CONTRIBUTING.md#how").is_ok());
+        assert!(check(root, root, "src").is_ok());
+        assert!(check(root, root, "src/").is_ok());
+        assert!(check(root, &src, "../README.md").is_ok());
+        assert!(check(root, &src, "/CONTRIBUTING.md?plain=1").is_ok());
+        assert!(inside(root, &src.join("../README.md")));
+        assert!(!inside(&src, &src.join("../README.md")));
+        assert!(is_markdown(&root.join("README.md")) && !is_markdown(&src));
+        assert_eq!(check(root, root, "contributing.md"), Err(Problem::NoSuchFile(root.join("contributing.md"))));
+        assert_eq!(check(root, root, "logo.png"), Err(Problem::NoSuchFile(root.join("logo.png"))));
+        assert_eq!(check(root, root, "CONTRIBUTING.md#hw"), Err(Problem::NoSuchHeading {
+            file: root.join("CONTRIBUTING.md"),
+            nearest: Some("how".to_string()),
+        }));
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(wildcard_matches("*.md", "README.md"));
+        assert!(wildcard_matches("READ?E.*", "README.md"));
+        assert!(wildcard_matches("*", "anything"));
+        assert!(!wildcard_matches("*.md", "readme.MD"));
+        assert!(!wildcard_matches("?", ""));
+    }
+
+    #[test]
+    fn globs() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        assert_eq!(glob(&root.join("*.md")), vec![root.join("CONTRIBUTING.md"), root.join("README.md")]);
+        assert!(glob(&root.join("src/**/*.rs")).contains(&root.join("src/local.rs")));
+        assert!(glob(&root.join("s*/**/local.rs")).contains(&root.join("src/local.rs")));
+        assert_eq!(glob(&root.join("*.nothing")), Vec::<PathBuf>::new());
+        assert_eq!(glob(Path::new("missing.md")), vec![PathBuf::from("missing.md")]);
+    }
+}
@@ -1,28 +1,451 @@
-use pulldown_cmark::{Parser, Event, Tag};
+// failure_derive generates its impls inside a const block
+#![allow(non_local_definitions)]
+
+use pulldown_cmark::{Parser, Options, Event, Tag, LinkType};
+use std::cell::RefCell;
 use std::fs;
 use futures::future::{select_all, BoxFuture, FutureExt};
-use std::collections::{BTreeSet, BTreeMap};
+use futures::stream::StreamExt;
+use std::collections::{BTreeSet, BTreeMap, VecDeque};
 use serde::{Serialize, Deserialize};
-use lazy_static::lazy_static;
-use std::sync::atomic::{AtomicU32, Ordering};
 use async_std::task;
 use std::time;
-use log::{warn, debug};
-use std::io::Write;
-use reqwest::{Client, redirect::Policy, StatusCode, header};
-use regex::Regex;
+use log::{info, warn, debug};
+use std::io::Read;
+use reqwest::{Client, redirect::Policy, Method, StatusCode, header};
+use regex::RegexSet;
+use url::Url;
 use scraper::{Html, Selector};
 use failure::{Fail, Error, format_err};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use tokio::sync::{Semaphore, SemaphorePermit, OwnedSemaphorePermit};
+use structopt::StructOpt;
+use std::path::{Path, PathBuf};
+
+mod actions;
+mod anchors;
+mod archive;
+mod config;
+mod content;
+mod diff;
+mod dns;
+mod duplicates;
+mod failures;
+mod domains;
+mod github;
+mod html;
+mod html_report;
+mod ignore;
+mod issue;
+mod items;
+mod lines;
+mod lint;
+mod local;
+mod progress;
+mod proxy;
+mod references;
+mod report;
+mod retry;
+mod sarif;
+mod schemes;
+mod sections;
+mod tls;
+mod toc;
+mod urls;
+mod writer;
+use config::Config;
+use failures::Failure;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// Config file with per-domain settings. Defaults to link-check.yaml if that exists
+    #[structopt(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// Maximum number of requests in flight at once
+    #[structopt(long, env = "LINK_CHECK_CONCURRENCY", default_value = "20")]
+    concurrency: usize,
+
+    /// Proxy for all requests, e.g. socks5://localhost:1080. Overrides HTTP_PROXY and HTTPS_PROXY
+    #[structopt(long)]
+    proxy: Option<String>,
+
+    /// User agent for hosts which don't have one configured. The default keeps some sites
+    /// (e.g. sciter.com) from rejecting us
+    #[structopt(long, default_value = "curl/7.54.0")]
+    user_agent: String,
+
+    /// Don't retry 403s while pretending to be a browser
+    #[structopt(long)]
+    no_browser_retry: bool,
+
+    /// Maximum number of bytes we read from a response body, for checks which look at the content
+    #[structopt(long, default_value = "2097152")]
+    max_body_size: usize,
+
+    /// Don't send If-None-Match/If-Modified-Since from the previous run, in case a server
+    /// answers 304 when it shouldn't
+    #[structopt(long)]
+    unconditional: bool,
+
+    /// Stop after this many seconds, leaving whatever wasn't checked yet for the next run
+    #[structopt(long)]
+    deadline: Option<u64>,
+
+    /// Total time (in seconds) a single request may take
+    #[structopt(long, env = "LINK_CHECK_TIMEOUT", default_value = "20")]
+    timeout: u64,
+
+    /// Time (in seconds) to wait for a connection to be established
+    #[structopt(long, env = "LINK_CHECK_CONNECT_TIMEOUT", default_value = "10")]
+    connect_timeout: u64,
+
+    /// Maximum number of redirects to follow before giving up on a url
+    #[structopt(long, default_value = "10")]
+    max_redirects: usize,
+
+    /// Longest Retry-After (in seconds) on a 503 that we wait out. Anything longer is reported
+    /// as temporarily unavailable instead of failed
+    #[structopt(long, default_value = "300")]
+    max_unavailable_wait: u64,
+
+    /// Maximum number of concurrent requests to a single host
+    #[structopt(long, default_value = "2")]
+    per_host: usize,
+
+    /// Minimum time (in milliseconds) between two requests to the same host
+    #[structopt(long, default_value = "500")]
+    host_delay: u64,
+
+    /// Number of consecutive connection failures after which we skip all remaining urls on a host
+    #[structopt(long, default_value = "3")]
+    max_host_failures: u32,
+
+    /// Check every url, even on hosts that seem to be down
+    #[structopt(long)]
+    no_circuit_breaker: bool,
+
+    /// Accept invalid certificates everywhere, which is how older versions checked. Useful to
+    /// compare against, otherwise list the hosts under `insecure_hosts` in the config
+    #[structopt(long)]
+    insecure: bool,
+
+    /// Warn about certificates which expire within this many days
+    #[structopt(long, default_value = "30")]
+    cert_expiry_days: u64,
+
+    /// Don't look at when certificates expire, which saves inspecting every handshake
+    #[structopt(long)]
+    no_cert_expiry_check: bool,
+
+    /// Look at html pages for signs that they are really an error page, despite the 200
+    #[structopt(long)]
+    detect_soft_404: bool,
+
+    /// Follow meta refresh redirects on every html page, which takes a GET after the HEAD for most
+    /// of them. Without this, only the pages we read anyway are looked at, e.g. for a #fragment.
+    #[structopt(long)]
+    meta_refresh: bool,
+
+    /// Fail links we can't verify, e.g. because they are behind bot protection or a login
+    #[structopt(long)]
+    strict: bool,
+
+    /// Fail links to a section which the page doesn't have, instead of only warning about them
+    #[structopt(long)]
+    strict_anchors: bool,
+
+    /// Look up all GitHub repositories before checking anything else, a hundred at a time
+    /// through the GraphQL API. Needs GITHUB_TOKEN
+    #[structopt(long)]
+    github_graphql: bool,
+
+    /// Look up failed urls in the Wayback Machine, to point the readme at an archived copy
+    #[structopt(long)]
+    suggest_archive: bool,
+
+    /// Have the Wayback Machine save working urls it has no recent copy of, so there is one
+    /// once they die. Slow, as it saves one page every few seconds
+    #[structopt(long)]
+    archive_working: bool,
+
+    /// Also check urls written out in the text without any link syntax, which GitHub turns into
+    /// links anyway
+    #[structopt(long)]
+    bare_urls: bool,
+
+    /// Also check urls in code blocks and code spans, like the ones in installation
+    /// instructions. Placeholders on example.com or localhost are left alone
+    #[structopt(long)]
+    include_code_blocks: bool,
+
+    /// What the files are: `markdown`, or `urls` for plain lists with one url per line, where
+    /// blank lines and lines starting with `#` don't count
+    #[structopt(long, default_value = "markdown", possible_values = &["markdown", "urls"])]
+    format: InputFormat,
+
+    /// Check working urls again once it's been this many days since they were last checked.
+    /// Each url gets a bit more or less, so they don't all come due in the same run
+    #[structopt(long, default_value = "30")]
+    recheck_after: u64,
+
+    /// Also keep how each working url answered in the results, under `working_details`: the
+    /// status, content type, final url and how long it took
+    #[structopt(long)]
+    verbose_results: bool,
+
+    /// Start over when the results file is there but can't be parsed, instead of refusing to
+    /// overwrite it
+    #[structopt(long)]
+    force: bool,
+
+    /// Where to keep the results between runs. Defaults to results.yaml, unless the markdown
+    /// comes from stdin. Written as JSON if it ends in .json, YAML otherwise
+    #[structopt(long, env = "LINK_CHECK_RESULTS", parse(from_os_str))]
+    results: Option<PathBuf>,
+
+    /// Also check the markdown files in the repository which the files link to, and the ones
+    /// those link to, up to --recurse-depth links away
+    #[structopt(long)]
+    recurse_local: bool,
+
+    /// How many links away from the given files --recurse-local goes
+    #[structopt(long, default_value = "3")]
+    recurse_depth: usize,
+
+    /// Only check the links under headings with this name, e.g. "Web programming", including
+    /// its subsections. Can be a pattern with `*` and `?`, and be given more than once
+    #[structopt(long, number_of_values = 1)]
+    section: Vec<String>,
+
+    /// Don't check the links under headings with this name, or matching this pattern
+    #[structopt(long, number_of_values = 1)]
+    skip_section: Vec<String>,
+
+    /// Allow images inlined as data: urls up to this many bytes, like small SVG badges. Other
+    /// data: urls are always errors
+    #[structopt(long)]
+    allow_data_images: Option<usize>,
+
+    /// Check that list entries look like `* [name](url) — Description.`, which the config's
+    /// `entry_format` can loosen. Entries which don't are listed apart from the link failures
+    #[structopt(long)]
+    lint_entries: bool,
+
+    /// Check that the entries in each list are in alphabetical order, except under the config's
+    /// `unsorted_sections`
+    #[structopt(long)]
+    lint_order: bool,
+
+    /// Check that the table of contents lists the headings it should, in the order and at the
+    /// depth they're in
+    #[structopt(long)]
+    check_toc: bool,
+
+    /// Print the table of contents the way it should be, to paste over the old one
+    #[structopt(long)]
+    print_toc: bool,
+
+    /// Fail when --lint-entries, --lint-order or --check-toc find anything, not only when links are broken
+    #[structopt(long)]
+    strict_lint: bool,
+
+    /// Fail when different entries link to the same project, instead of only warning about it
+    #[structopt(long)]
+    fail_on_duplicates: bool,
+
+    /// Only check the links on lines that were added or changed since this git ref, e.g.
+    /// `origin/master` for a pull request
+    #[structopt(long)]
+    diff_base: Option<String>,
+
+    /// With --diff-base, forget whatever we know about urls which were removed since then and
+    /// aren't anywhere in the files anymore
+    #[structopt(long, requires = "diff-base")]
+    prune_removed: bool,
+
+    /// Forget the working urls which aren't in the files anymore, so they get checked again if
+    /// they come back
+    #[structopt(long)]
+    prune: bool,
+
+    /// How to write the report at the end: `text`, `json`, whose schema is the `Report` struct
+    /// in src/report.rs, `junit` for CI systems which show JUnit XML as tests, `sarif` for
+    /// GitHub code scanning, `csv` with a row for each url, `html` for a page of its own, or
+    /// `markdown` for the body of an issue. The progress goes to stderr when the report has
+    /// stdout to itself
+    #[structopt(long, default_value = "text", possible_values = &["text", "json", "junit", "sarif", "csv", "html", "markdown"])]
+    output_format: report::Format,
+
+    /// Write the --output-format report to this file instead of stdout, which then gets the text
+    /// report as usual
+    #[structopt(long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// How to order the failures in each category of the report: `domain` groups them by host,
+    /// `section` under the headings they're in, `url` and `status` list them by url, or by status
+    /// and then url
+    #[structopt(long, default_value = "domain", possible_values = &["url", "status", "domain", "section"])]
+    sort_by: SortBy,
+
+    /// Only put the failures in the --output-format csv rows, not every url
+    #[structopt(long)]
+    failures_only: bool,
+
+    /// Color the line for each check, with a glyph for how it went: `auto` does on a terminal,
+    /// otherwise the lines are plain records which start with ok, failed, suspect and so on
+    #[structopt(long, default_value = "auto", possible_values = &["auto", "always", "never"])]
+    color: writer::Color,
+
+    /// No progress bar on stderr, which there only is on a terminal anyway
+    #[structopt(short, long)]
+    quiet: bool,
+
+    /// Annotate the lines of the broken links for GitHub Actions, and write a summary of the run
+    /// to the job page: `auto` does when GITHUB_ACTIONS is true, `always` to try it locally
+    #[structopt(long, default_value = "auto", possible_values = &["auto", "always", "never"])]
+    github_actions: actions::Mode,
+
+    /// The file GitHub Actions shows as the job summary, which it sets itself
+    #[structopt(long, env = "GITHUB_STEP_SUMMARY", parse(from_os_str))]
+    step_summary: Option<PathBuf>,
+
+    /// Markdown files to check, or globs like `docs/*.md`. `-` reads from stdin. Defaults to
+    /// README.md
+    #[structopt(parse(from_os_str))]
+    files: Vec<PathBuf>,
+}
+
+impl Opt {
+    /// Whether stdout is for the progress and the text report, not for a report in another format
+    fn text_on_stdout(&self) -> bool {
+        self.output_format == report::Format::Text || self.output.is_some()
+    }
+}
+
+/// How the failures are ordered within their category
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SortBy {
+    Url,
+    Status,
+    Domain,
+    Section,
+}
+
+impl std::str::FromStr for SortBy {
+    type Err = String;
+
+    fn from_str(sort_by: &str) -> Result<SortBy, String> {
+        match sort_by {
+            "url" => Ok(SortBy::Url),
+            "status" => Ok(SortBy::Status),
+            "domain" => Ok(SortBy::Domain),
+            "section" => Ok(SortBy::Section),
+            _ => Err(format!("can't sort by {}", sort_by)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum InputFormat {
+    Markdown,
+    Urls,
+}
+
+impl std::str::FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<InputFormat, String> {
+        match format {
+            "markdown" => Ok(InputFormat::Markdown),
+            "urls" => Ok(InputFormat::Urls),
+            _ => Err(format!("unknown format {}", format)),
+        }
+    }
+}
 
 #[derive(Debug, Fail)]
 enum CheckerError {
-    #[fail(display = "failed to try url")]
-    NotTried, // Generally shouldn't happen, but useful to have
-
     #[fail(display = "http error: {}", status)]
     HttpError {
         status: StatusCode,
-        location: Option<String>,
+        chain: Vec<String>,
+    },
+
+    #[fail(display = "redirect loop")]
+    RedirectLoop {
+        chain: Vec<String>,
+    },
+
+    #[fail(display = "rate limited")]
+    RateLimited,
+
+    #[fail(display = "temporarily unavailable, retry after {:?}", retry_after)]
+    TemporarilyUnavailable {
+        retry_after: time::Duration,
+    },
+
+    #[fail(display = "skipped because {} is unreachable", host)]
+    CircuitOpen {
+        host: String,
+    },
+
+    #[fail(display = "too many redirects")]
+    TooManyRedirects {
+        chain: Vec<String>,
+    },
+
+    #[fail(display = "no such host {}", host)]
+    DnsError {
+        host: String,
+    },
+
+    #[fail(display = "{} timeout", phase)]
+    Timeout {
+        phase: TimeoutPhase,
+    },
+
+    #[fail(display = "connection refused")]
+    ConnectionRefused,
+
+    #[fail(display = "connection reset")]
+    ConnectionReset,
+
+    #[fail(display = "can't verify: {}", reason)]
+    Unverifiable {
+        reason: String,
+    },
+
+    #[fail(display = "redirects to login page {}", login)]
+    AccessRestricted {
+        login: String,
+    },
+
+    #[fail(display = "expected {}, got {}", expected, got)]
+    WrongContentType {
+        expected: String,
+        got: String,
+    },
+
+    #[fail(display = "no anchor #{}", anchor)]
+    MissingAnchor {
+        anchor: String,
+    },
+
+    #[fail(display = "mangled url, {} works", repaired)]
+    MangledUrl {
+        repaired: String,
+    },
+
+    #[fail(display = "badge says {}", text)]
+    BrokenBadge {
+        text: String,
+    },
+
+    #[fail(display = "tls error: {}", reason)]
+    TlsError {
+        reason: String,
     },
 
     #[fail(display = "reqwest error: {}", error)]
@@ -31,210 +454,3037 @@ enum CheckerError {
     }
 }
 
-struct MaxHandles {
-    remaining: AtomicU32
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TimeoutPhase {
+    Connect,
+    /// The timeout for the whole request, which can run out anywhere from sending it to reading
+    /// the last of the body
+    Total,
 }
 
-struct Handle<'a> {
-    parent: &'a MaxHandles
+impl std::fmt::Display for TimeoutPhase {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TimeoutPhase::Connect => write!(f, "connect"),
+            TimeoutPhase::Total => write!(f, "request"),
+        }
+    }
 }
 
-impl MaxHandles {
-    fn new(max: u32) -> MaxHandles {
-        MaxHandles { remaining: AtomicU32::new(max) }
+impl CheckerError {
+    /// Errors which won't go away by trying again: the ones the retry policy doesn't retry, and
+    /// the ones which are down to the url itself
+    fn is_permanent(&self, retries: &retry::RetryPolicy) -> bool {
+        match self {
+            CheckerError::HttpError {status, ..} => retries.for_status(*status).attempts == 1,
+            CheckerError::ReqwestError {error} => error.is_builder(),
+            CheckerError::RedirectLoop {..} | CheckerError::TooManyRedirects {..} | CheckerError::MangledUrl {..} => true,
+            err if err.kind() != "other" => retries.for_error(err.kind()).attempts == 1,
+            _ => false
+        }
     }
 
-    async fn get<'a>(&'a self) -> Handle<'a> {
-        loop {
-            let current = self.remaining.load(Ordering::Relaxed);
-            if current > 0 {
-                let new_current = self.remaining.compare_and_swap(current, current - 1, Ordering::Relaxed);
-                if new_current == current { // worked
-                    debug!("Got handle with {}", new_current);
-                    return Handle { parent: self };
+    /// What kind of error this is for the retry policy, see `retry::ERROR_KINDS`
+    fn kind(&self) -> &'static str {
+        match self {
+            CheckerError::DnsError {..} => "dns",
+            CheckerError::Timeout {..} => "timeout",
+            CheckerError::ConnectionRefused => "connection_refused",
+            CheckerError::ConnectionReset => "connection_reset",
+            CheckerError::TlsError {..} => "tls",
+            _ => "other",
+        }
+    }
+
+    /// Errors which suggest the whole host is down, rather than just this url
+    fn is_host_failure(&self) -> bool {
+        matches!(self,
+            CheckerError::DnsError {..} | CheckerError::Timeout {..} |
+            CheckerError::ConnectionRefused | CheckerError::ConnectionReset |
+            CheckerError::ReqwestError {..})
+    }
+
+    /// Errors where we didn't even get to talk to the server
+    fn is_connection_failure(&self) -> bool {
+        matches!(self,
+            CheckerError::ConnectionRefused | CheckerError::ConnectionReset |
+            CheckerError::Timeout {phase: TimeoutPhase::Connect})
+    }
+}
+
+/// Finds out whether the handshake failed, e.g. because of an expired or self-signed certificate
+fn tls_error(error: &reqwest::Error) -> Option<String> {
+    let mut source = std::error::Error::source(error);
+    while let Some(err) = source {
+        if let Some(tls) = err.downcast_ref::<rustls::Error>() {
+            return Some(tls.to_string());
+        }
+        // tokio-rustls hands the rustls error up wrapped in (possibly several) io::Errors, whose
+        // source() skips the wrapped error itself
+        source = match err.downcast_ref::<std::io::Error>() {
+            Some(io) => io.get_ref().map(|inner| inner as &(dyn std::error::Error + 'static)),
+            None => err.source(),
+        };
+    }
+    None
+}
+
+impl From<reqwest::Error> for CheckerError {
+    fn from(error: reqwest::Error) -> CheckerError {
+        if let Some(reason) = tls_error(&error) {
+            return CheckerError::TlsError {reason};
+        }
+        let mut source = std::error::Error::source(&error);
+        while let Some(err) = source {
+            // Neither hyper nor reqwest expose the resolver errors as types, so go by their
+            // description. Behind a socks5h proxy, an unknown host comes back as host unreachable.
+            let description = err.to_string();
+            if error.is_connect() && (description.starts_with("dns error") ||
+                    description == "socks connect error: Host unreachable") {
+                let host = error.url().and_then(|u| u.host_str()).unwrap_or_default().to_string();
+                return CheckerError::DnsError {host};
+            }
+            if let Some(io) = err.downcast_ref::<std::io::Error>() {
+                match io.kind() {
+                    std::io::ErrorKind::ConnectionRefused => return CheckerError::ConnectionRefused,
+                    std::io::ErrorKind::ConnectionReset => return CheckerError::ConnectionReset,
+                    _ => {}
                 }
             }
-            task::sleep(time::Duration::from_millis(500)).await;
+            source = err.source();
         }
+        if error.is_timeout() {
+            let phase = if error.is_connect() { TimeoutPhase::Connect } else { TimeoutPhase::Total };
+            return CheckerError::Timeout {phase};
+        }
+        CheckerError::ReqwestError {error}
+    }
+}
+
+struct MaxHandles {
+    remaining: Semaphore
+}
+
+struct Handle<'a> {
+    _permit: SemaphorePermit<'a>
+}
+
+impl MaxHandles {
+    fn new(max: usize) -> MaxHandles {
+        MaxHandles { remaining: Semaphore::new(max) }
+    }
+
+    async fn get(&self) -> Handle<'_> {
+        // The semaphore is never closed
+        let permit = self.remaining.acquire().await.unwrap();
+        debug!("Got handle with {}", self.remaining.available_permits());
+        Handle { _permit: permit }
     }
 }
 
 impl<'a> Drop for Handle<'a> {
     fn drop(&mut self) {
         debug!("Dropping");
-        self.parent.remaining.fetch_add(1, Ordering::Relaxed);
     }
 }
 
-lazy_static! {
-    static ref CLIENT: Client = Client::builder()
-        .danger_accept_invalid_certs(true) // because some certs are out of date
-        .user_agent("curl/7.54.0") // so some sites (e.g. sciter.com) don't reject us
-        .redirect(Policy::none())
-        .timeout(time::Duration::from_secs(20))
-        .build().unwrap();
+const DEFAULT_CONFIG: &str = "link-check.yaml";
 
-    // This is to avoid errors with running out of file handles, so we only do 20 requests at a time
-    static ref HANDLES: MaxHandles = MaxHandles::new(20);
-}
+/// How suspect results for parked domains start, so the report can single them out
+const PARKED: &str = "parked domain";
 
-fn get_url(url: String) -> BoxFuture<'static, (String, Result<String, CheckerError>)> {
-    async move {
-        let _handle = HANDLES.get().await;
-        let mut res = Err(CheckerError::NotTried);
-        for _ in 0..5u8 {
-            debug!("Running {}", url);
-            let resp = CLIENT
-                .get(&url)
-                .header(header::ACCEPT, "text/html, */*;q=0.8")
-                .send()
-                .await;
-            match resp {
-                Err(err) => {
-                    warn!("Error while getting {}, retrying: {}", url, err);
-                    res = Err(CheckerError::ReqwestError{error: err});
-                    continue;
-                }
-                Ok(ref ok) => {
-                    let status = ok.status();
-                    if status != StatusCode::OK {
-                        lazy_static! {
-                            static ref ACTIONS_REGEX: Regex = Regex::new(r"https://github.com/(?P<org>[^/]+)/(?P<repo>[^/]+)/actions(?:\?workflow=.+)?").unwrap();
-                        }
-                        if status == StatusCode::NOT_FOUND && ACTIONS_REGEX.is_match(&url) {
-                            let rewritten = ACTIONS_REGEX.replace_all(&url, "https://github.com/$org/$repo");
-                            warn!("Got 404 with Github actions, so replacing {} with {}", url, rewritten);
-                            let (_new_url, res) = get_url(rewritten.to_string()).await;
-                            return (url, res);
-                        }
+/// Urls of login pages, which we don't get past without an account. More can be added with
+/// `login_pages` in the config.
+const LOGIN_PAGES: &[&str] = &[
+    r"(?i)^https?://[^/]+/(login|log-in|signin|sign-in|sign_in|users/sign_in|account/login|accounts/login)([/?#.]|$)",
+    r"^https?://(mobile\.)?(twitter|x)\.com/i/flow/login",
+    r"^https?://(www\.)?linkedin\.com/(authwall|uas/login)",
+    r"^https?://accounts\.google\.com/",
+    r"^https?://login\.microsoftonline\.com/",
+];
 
-                        warn!("Error while getting {}, retrying: {}", url, status);
-                        if status.is_redirection() {
-                            res = Err(CheckerError::HttpError {status: status, location: ok.headers().get(header::LOCATION).and_then(|h| h.to_str().ok()).map(|x| x.to_string())});
-                        } else {
-                            res = Err(CheckerError::HttpError {status: status, location: None});
-                        }
-                        continue;
-                    }
-                    debug!("Finished {}", url);
-                    res = Ok(format!("{:?}", ok));
-                    break;
-                }
+/// Everything the checks share, set up once in main
+struct Checker {
+    opt: Opt,
+    config: Config,
+    client: Client,
+    /// Same as `client`, but accepts invalid certificates, for hosts the config tolerates them on
+    insecure_client: Client,
+    // This is to avoid errors with running out of file handles, so we only do so many requests at a time
+    handles: MaxHandles,
+    // Created lazily the first time we see a host
+    hosts: Mutex<BTreeMap<String, Arc<Host>>>,
+    /// Bytes received over the whole run, as far as we can tell
+    downloaded: AtomicU64,
+    /// How many checks are running, for the progress bar
+    in_flight: AtomicUsize,
+    /// Validators from the previous run, for conditional requests
+    validators: BTreeMap<String, Validators>,
+    /// Whether requests go through a proxy, in which case only the proxy can resolve hosts
+    proxied: bool,
+    /// What the hosts resolved to, for both clients
+    dns: dns::Cache,
+    /// When the certificates of the hosts we connected to expire
+    expiries: tls::Expiries,
+    login_pages: RegexSet,
+    retries: retry::RetryPolicy,
+    /// `Bearer $GITHUB_TOKEN`, for the higher rate limit of authenticated requests
+    github_token: Option<header::HeaderValue>,
+    /// How many requests we sent for each url, retries included
+    attempts: Mutex<BTreeMap<String, u32>>,
+    /// How long the check of each url took, retries and redirects included
+    durations: Mutex<BTreeMap<String, time::Duration>>,
+    /// The lowest remaining/total GitHub rate limit we saw, when using the token
+    github_rate_limit: Mutex<Option<(u64, u64)>>,
+    /// Whether repositories are checked through the GitHub API, until we run out of requests
+    github_api: AtomicBool,
+    /// Repositories looked up with `--github-graphql`, by lowercase owner/name. None if they don't exist.
+    github_repos: Mutex<BTreeMap<String, Option<github::Repository>>>,
+}
+
+impl Checker {
+    fn new(opt: Opt) -> Result<Checker, Error> {
+        let config = match opt.config {
+            Some(ref path) => Config::load(path)?,
+            None if Path::new(DEFAULT_CONFIG).exists() => Config::load(Path::new(DEFAULT_CONFIG))?,
+            None => Config::default(),
+        };
+        let proxy = match opt.proxy {
+            Some(ref proxy) => Some(proxy::from_flag(proxy)?),
+            None => proxy::from_env()?,
+        };
+        let proxied = proxy.is_some();
+        let expiries = tls::Expiries::default();
+        let dns = dns::Cache::default();
+        let build = |accept_invalid_certs| {
+            let mut builder = Client::builder()
+                .danger_accept_invalid_certs(accept_invalid_certs)
+                .redirect(Policy::none())
+                // Consent pages set a cookie and redirect back, so keep them for the run (in memory only)
+                .cookie_store(true)
+                .timeout(time::Duration::from_secs(opt.timeout))
+                .connect_timeout(time::Duration::from_secs(opt.connect_timeout))
+                .dns_resolver(Arc::new(dns.clone()))
+                // reqwest's own detection doesn't know about NO_PROXY
+                .no_proxy();
+            if let Some(ref proxy) = proxy {
+                builder = builder.proxy(proxy.clone());
             }
+            if !opt.no_cert_expiry_check {
+                builder = builder.use_preconfigured_tls(tls::config(accept_invalid_certs, expiries.clone()));
+            }
+            builder.build()
+        };
+        let client = build(opt.insecure)?;
+        let insecure_client = build(true)?;
+        header::HeaderValue::from_str(&opt.user_agent)
+            .map_err(|_| format_err!("Invalid user agent {}", opt.user_agent))?;
+        let login_pages = RegexSet::new(LOGIN_PAGES.iter().copied().chain(config.login_pages.iter().map(String::as_str)))
+            .map_err(|e| format_err!("Invalid login page pattern: {}", e))?;
+        let retries = retry::RetryPolicy::new(&config.retries)?;
+        let github_token = match std::env::var("GITHUB_TOKEN") {
+            Ok(ref token) if !token.trim().is_empty() => {
+                // Careful not to include the token in the error
+                let mut token = header::HeaderValue::from_str(&format!("Bearer {}", token.trim()))
+                    .map_err(|_| format_err!("GITHUB_TOKEN isn't a valid header value"))?;
+                token.set_sensitive(true);
+                info!("Authenticating to GitHub with GITHUB_TOKEN");
+                Some(token)
+            }
+            _ => None,
+        };
+        if opt.github_graphql && github_token.is_none() {
+            return Err(format_err!("--github-graphql needs GITHUB_TOKEN"));
         }
-        (url, res)
-    }.boxed()
+        Ok(Checker {
+            config,
+            client,
+            insecure_client,
+            handles: MaxHandles::new(opt.concurrency),
+            hosts: Mutex::new(BTreeMap::new()),
+            downloaded: AtomicU64::new(0),
+            in_flight: AtomicUsize::new(0),
+            validators: BTreeMap::new(),
+            proxied,
+            dns,
+            expiries,
+            login_pages,
+            retries,
+            github_token,
+            attempts: Mutex::new(BTreeMap::new()),
+            durations: Mutex::new(BTreeMap::new()),
+            github_rate_limit: Mutex::new(None),
+            github_api: AtomicBool::new(true),
+            github_repos: Mutex::new(BTreeMap::new()),
+            opt,
+        })
+    }
+
+    fn host(&self, url: &str) -> Arc<Host> {
+        self.hosts.lock().unwrap()
+            .entry(host_of(url))
+            .or_insert_with(|| Arc::new(Host {
+                // Badge services rate limit hard, so only ever ask them one thing at a time
+                permits: Arc::new(Semaphore::new(if is_badge_host(&host_of(url)) || is_archive_host(&host_of(url)) { 1 } else { self.opt.per_host })),
+                min_delay: if is_archive_host(&host_of(url)) { ARCHIVE_DELAY } else { time::Duration::from_secs(0) },
+                last_request: Mutex::new(None),
+                paused_until: Mutex::new(None),
+                failures: Mutex::new(0),
+            }))
+            .clone()
+    }
+
+    fn user_agent(&self, url: &str) -> &str {
+        self.config.user_agent(&host_of(url)).unwrap_or(&self.opt.user_agent)
+    }
+
+    /// When the certificate for this url expires, if that is soon enough to warn about
+    fn expires_soon(&self, url: &str) -> Option<time::SystemTime> {
+        let expiry = *self.expiries.lock().unwrap().get(&host_of(url))?;
+        let window = time::Duration::from_secs(self.opt.cert_expiry_days * 24 * 60 * 60);
+        if expiry < time::SystemTime::now() + window { Some(expiry) } else { None }
+    }
+
+    /// The total timeout for requests to this url, which can be overridden per domain
+    fn timeout(&self, url: &str) -> time::Duration {
+        self.config.timeout(&host_of(url))
+            .unwrap_or_else(|| time::Duration::from_secs(self.opt.timeout))
+    }
+
+    /// How many requests we sent for these urls, like the ones along a redirect chain
+    fn attempts<'a>(&self, urls: impl IntoIterator<Item = &'a String>) -> u32 {
+        let attempts = self.attempts.lock().unwrap();
+        urls.into_iter().filter_map(|url| attempts.get(url)).sum()
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Results {
-    working: BTreeSet<String>,
-    failed: BTreeMap<String, String>
+/// Per-host state, so we don't overwhelm any single server
+struct Host {
+    permits: Arc<Semaphore>,
+    /// Time between requests this host needs, whatever `--host-delay` says
+    min_delay: time::Duration,
+    last_request: Mutex<Option<time::Instant>>,
+    /// Set when the host told us to stop until its rate limit resets
+    paused_until: Mutex<Option<time::Instant>>,
+    /// Consecutive urls which failed with a host failure, for the circuit breaker
+    failures: Mutex<u32>,
 }
 
-impl Results {
-    fn new() -> Results {
-        Results {
-            working: BTreeSet::new(),
-            failed: BTreeMap::new()
+/// Services which render badges, which answer 200 even when the badge shows an error
+fn is_badge_host(host: &str) -> bool {
+    ["shields.io", "badgen.net"].iter()
+        .any(|badge_host| host == *badge_host || host.ends_with(&format!(".{}", badge_host)))
+}
+
+/// The Wayback Machine asks to be gentle with it
+fn is_archive_host(host: &str) -> bool {
+    host == "archive.org" || host.ends_with(".archive.org")
+}
+
+const ARCHIVE_DELAY: time::Duration = time::Duration::from_secs(1);
+
+fn host_of(url: &str) -> String {
+    reqwest::Url::parse(url).ok().and_then(|u| u.host_str().map(str::to_string)).unwrap_or_default()
+}
+
+impl Host {
+    /// Waits for a free slot on this host, and until enough time passed since the last request
+    async fn permit(&self, opt: &Opt, url: &str) -> OwnedSemaphorePermit {
+        let permit = self.permits.clone().acquire_owned().await.unwrap();
+        // Another request might extend the pause while we sleep
+        loop {
+            let paused_until = *self.paused_until.lock().unwrap();
+            match paused_until {
+                Some(until) if until > time::Instant::now() => task::sleep(until - time::Instant::now()).await,
+                _ => break,
+            }
+        }
+        let last_request = *self.last_request.lock().unwrap();
+        if let Some(last_request) = last_request {
+            let delay = time::Duration::from_millis(opt.host_delay).max(self.min_delay);
+            let elapsed = last_request.elapsed();
+            if elapsed < delay {
+                debug!("Delaying {} by {:?}", url, delay - elapsed);
+                task::sleep(delay - elapsed).await;
+            }
+        }
+        permit
+    }
+
+    /// Pauses all requests to this host for a while. Returns false if it was paused for at least
+    /// that long already.
+    fn pause(&self, duration: time::Duration) -> bool {
+        let until = time::Instant::now() + duration;
+        let mut paused_until = self.paused_until.lock().unwrap();
+        if matches!(*paused_until, Some(paused_until) if paused_until >= until) {
+            return false;
+        }
+        *paused_until = Some(until);
+        true
+    }
+
+    fn finished(&self) {
+        *self.last_request.lock().unwrap() = Some(time::Instant::now());
+    }
+
+    fn tripped(&self, opt: &Opt) -> bool {
+        !opt.no_circuit_breaker && *self.failures.lock().unwrap() >= opt.max_host_failures
+    }
+
+    fn record(&self, res: &Result<Checked, CheckerError>) {
+        let mut failures = self.failures.lock().unwrap();
+        match res {
+            Err(CheckerError::CircuitOpen {..}) => {}
+            Err(err) if err.is_host_failure() => *failures += 1,
+            _ => *failures = 0,
         }
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Error> {
-    env_logger::init();
-    let markdown_input = fs::read_to_string("README.md").expect("Can't read README.md");
-    let parser = Parser::new(&markdown_input);
+#[derive(Debug, Default)]
+struct Checked {
+    /// Every url we were redirected to, in order. Empty if there were no redirects.
+    chain: Vec<String>,
+    fallbacks: Fallbacks,
+    validators: Validators,
+    /// The https version of a plain http url, if that works as well
+    upgrade: Option<String>,
+    /// The https version of a plain http url, if only that works
+    moved: Option<String>,
+    /// Why the page might not be what the link promised, even though it answered
+    suspect: Option<String>,
+    /// What a badge says, e.g. "build: passing"
+    badge: Option<String>,
+    /// The GitHub repository is read-only, which usually means it isn't maintained anymore
+    archived: bool,
+    /// The repository of a GitHub workflow which doesn't exist anymore
+    stale_workflow: Option<String>,
+    /// The error status the config told us to accept for this url
+    accepted: Option<StatusCode>,
+    /// How the page we ended up on answered
+    success: CheckSuccess,
+    /// The fragment of the url, which the page has no element for
+    missing_anchor: Option<String>,
+}
 
-    let mut results: Results = fs::read_to_string("results.yaml")
-        .map_err(|e| format_err!("{}", e))
-        .and_then(|x| serde_yaml::from_str(&x).map_err(|e| format_err!("{}", e)))
-        .unwrap_or(Results::new());
-    results.failed.clear();
+/// The final response of a working url, after following redirects
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+struct CheckSuccess {
+    status: u16,
+    final_url: String,
+    http_version: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    content_type: Option<String>,
+    /// Including retries and redirects
+    duration_ms: u64,
+}
 
-    let mut url_checks = vec![];
+/// Cache validators of a working url, so the next run can ask whether it changed
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+struct Validators {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_modified: Option<String>,
+}
 
-    let mut do_check = |url: String| {
-        if !url.starts_with("http") {
-            return;
+impl Validators {
+    fn from_headers(headers: &header::HeaderMap) -> Validators {
+        let get = |name| headers.get(name).and_then(|h: &header::HeaderValue| h.to_str().ok()).map(str::to_string);
+        Validators {
+            etag: get(header::ETAG),
+            last_modified: get(header::LAST_MODIFIED),
         }
-        if results.working.contains(&url) {
-            return;
+    }
+
+    fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+/// Workarounds we needed before a server gave us a proper answer
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Fallbacks {
+    /// The server misbehaved on HEAD, so we had to fall back to GET
+    get: bool,
+    /// The server rejected us until we sent the headers of a regular browser
+    browser: bool,
+    /// Why the certificate was invalid, if we only got through by ignoring that
+    insecure: Option<String>,
+    /// The server answered 406 to the Accept header we sent first
+    accept: Accept,
+}
+
+/// The Accept headers we try, in order, until the server stops answering 406
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum Accept {
+    #[default]
+    Html,
+    Any,
+    Omitted,
+}
+
+impl Accept {
+    fn header(self) -> Option<&'static str> {
+        match self {
+            Accept::Html => Some("text/html, */*;q=0.8"),
+            Accept::Any => Some("*/*"),
+            Accept::Omitted => None,
         }
-        let check = get_url(url).boxed();
-        url_checks.push(check);
-    };
+    }
 
-    for (event, _range) in parser.into_offset_iter() {
-        match event {
-            Event::Start(tag) => {
-                match tag {
-                    Tag::Link(_link_type, url, _title) | Tag::Image(_link_type, url, _title) => {
-                        do_check(url.to_string());
-                    }
-                    _ => {}
-                }
-            }
-            Event::Html(content) => {
-                let fragment = Html::parse_fragment(&content);
-                for element in fragment.select(&Selector::parse("img").unwrap()) {
-                    let img_src = element.value().attr("src");
-                    if let Some(src) = img_src {
-                        do_check(src.to_string());
-                    }
-                }
-                for element in fragment.select(&Selector::parse("a").unwrap()) {
-                    let a_href = element.value().attr("href");
-                    if let Some(href) = a_href {
-                        do_check(href.to_string());
-                    }
-                }
-            }
-            _ => {}
+    fn next(self) -> Accept {
+        match self {
+            Accept::Html => Accept::Any,
+            Accept::Any | Accept::Omitted => Accept::Omitted,
+        }
+    }
+}
+
+impl std::fmt::Display for Accept {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.header().unwrap_or("no accept header"))
+    }
+}
+
+impl Fallbacks {
+    fn merge(&mut self, other: Fallbacks) {
+        self.get |= other.get;
+        self.browser |= other.browser;
+        if other.accept != Accept::Html {
+            self.accept = other.accept;
+        }
+        if other.insecure.is_some() {
+            self.insecure = other.insecure;
         }
     }
+}
 
-    while url_checks.len() > 0 {
-        debug!("Waiting...");
-        let ((url, res), _index, remaining) = select_all(url_checks).await;
-        url_checks = remaining;
-        match res {
-            Ok(_) => {
-                print!("\u{2714} ");
-                results.working.insert(url);
-            },
-            Err(err) => {
-                print!("\u{2718} ");
-                let message = match err {
-                    CheckerError::HttpError {status, location} => {
-                        match location {
-                            Some(loc) => {
-                                format!("[{}] {} -> {}", status.as_u16(), url, loc)
-                            }
-                            None => {
-                                format!("[{}] {}", status.as_u16(), url)
-                            }
-                        }
-                    }
-                    _ => {
-                        format!("{:?}", err)
-                    }
-                };
-                results.failed.insert(url, message);
-            }
+/// Headers of a current desktop Firefox, for hosts behind bot protection
+const BROWSER_HEADERS: &[(&str, &str)] = &[
+    ("user-agent", "Mozilla/5.0 (X11; Linux x86_64; rv:72.0) Gecko/20100101 Firefox/72.0"),
+    ("accept", "text/html,application/xhtml+xml,application/xml;q=0.9,image/webp,*/*;q=0.8"),
+    ("accept-language", "en-US,en;q=0.5"),
+    ("upgrade-insecure-requests", "1"),
+    ("sec-fetch-dest", "document"),
+    ("sec-fetch-mode", "navigate"),
+    ("sec-fetch-site", "none"),
+    ("sec-fetch-user", "?1"),
+];
+
+/// A 403 from GitHub means we are rate limited, not that our headers were rejected
+fn browser_retry_allowed(opt: &Opt, url: &str) -> bool {
+    !opt.no_browser_retry && !is_github(&host_of(url))
+}
+
+/// github.com and its subdomains, like api.github.com
+fn is_github(host: &str) -> bool {
+    host == "github.com" || host.ends_with(".github.com")
+}
+
+/// Statuses for which a HEAD response isn't trusted and we retry with GET.
+/// Some hosts don't implement HEAD at all, others answer it with bogus errors.
+fn head_unsupported(status: StatusCode) -> bool {
+    matches!(status,
+        StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED |
+        StatusCode::NOT_FOUND | StatusCode::FORBIDDEN)
+}
+
+async fn send(checker: &Checker, url: &str, fallbacks: &Fallbacks) -> Result<reqwest::Response, reqwest::Error> {
+    let method = if fallbacks.get { Method::GET } else { Method::HEAD };
+    let mut headers = header::HeaderMap::new();
+    if let Some(accept) = fallbacks.accept.header() {
+        headers.insert(header::ACCEPT, header::HeaderValue::from_static(accept));
+    }
+    // Both the flag and the config are validated on startup
+    headers.insert(header::USER_AGENT, header::HeaderValue::from_str(checker.user_agent(url)).unwrap());
+    if fallbacks.browser {
+        for (name, value) in BROWSER_HEADERS {
+            headers.insert(*name, header::HeaderValue::from_static(value));
         }
-        std::io::stdout().flush().unwrap();
-        fs::write("results.yaml", serde_yaml::to_string(&results)?)?;
     }
-    println!("");
-    if results.failed.is_empty() {
-        println!("No errors!");
-        Ok(())
-    } else {
-        for (_url, error) in &results.failed {
-            println!("{}", error);
+    if let Some(validators) = checker.validators.get(url).filter(|_| !checker.opt.unconditional) {
+        // These came back from a server, so they are valid header values
+        if let Some(ref etag) = validators.etag {
+            headers.insert(header::IF_NONE_MATCH, header::HeaderValue::from_str(etag).unwrap());
+        }
+        if let Some(ref last_modified) = validators.last_modified {
+            headers.insert(header::IF_MODIFIED_SINCE, header::HeaderValue::from_str(last_modified).unwrap());
         }
-        Err(format_err!("{} urls with errors", results.failed.len()))
     }
-}
\ No newline at end of file
+    if let Some(token) = checker.github_token.as_ref().filter(|_| is_github(&host_of(url))) {
+        headers.insert(header::AUTHORIZATION, token.clone());
+    }
+    checker.config.apply_headers(&host_of(url), &mut headers);
+    // The token is marked sensitive, so this doesn't print it
+    debug!("Running {} {} with {:?}", method, url, headers);
+    let client = if fallbacks.insecure.is_some() { &checker.insecure_client } else { &checker.client };
+    let resp = client
+        .request(method, urls::to_uri(url))
+        .headers(headers)
+        .timeout(checker.timeout(url))
+        .send()
+        .await?;
+    // hyper doesn't tell us the size on the wire, so approximate it from the status line
+    // ("HTTP/1.1 200 OK\r\n") and headers ("name: value\r\n")
+    let head_size: usize = 17 + resp.headers().iter()
+        .map(|(name, value)| name.as_str().len() + value.len() + 4)
+        .sum::<usize>();
+    checker.downloaded.fetch_add(head_size as u64, Ordering::Relaxed);
+    if checker.github_token.is_some() && is_github(&host_of(url)) {
+        let header = |name| resp.headers().get(name).and_then(|h: &header::HeaderValue| h.to_str().ok()?.parse().ok());
+        if let (Some(remaining), Some(limit)) = (header("x-ratelimit-remaining"), header("x-ratelimit-limit")) {
+            let mut rate_limit = checker.github_rate_limit.lock().unwrap();
+            if !matches!(*rate_limit, Some((lowest, _)) if lowest <= remaining) {
+                *rate_limit = Some((remaining, limit));
+            }
+        }
+    }
+    Ok(resp)
+}
+
+/// Reads the body for content checks, but at most `limit` (and never more than `--max-body-size`)
+/// bytes of it. Whatever we got until then is treated as the whole content.
+async fn read_body(checker: &Checker, resp: reqwest::Response, limit: usize) -> Result<Vec<u8>, CheckerError> {
+    let limit = limit.min(checker.opt.max_body_size);
+    let url = resp.url().to_string();
+    let mut body = vec![];
+    let mut stream = resp.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        checker.downloaded.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+        if body.len() + chunk.len() >= limit {
+            body.extend_from_slice(&chunk[..limit - body.len()]);
+            debug!("Stopped reading {} after {} bytes", url, limit);
+            break;
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// GETs the start of a page, for checks which need the content after a HEAD told us the status
+async fn get_body(checker: &Checker, url: &str, fallbacks: &Fallbacks, limit: usize) -> Result<Vec<u8>, CheckerError> {
+    let host = checker.host(url);
+    let _handle = checker.handles.get().await;
+    let _permit = host.permit(&checker.opt, url).await;
+    let fallbacks = Fallbacks { get: true, ..fallbacks.clone() };
+    let resp = send(checker, url, &fallbacks).await;
+    host.finished();
+    read_body(checker, resp?, limit).await
+}
+
+fn is_html(headers: &header::HeaderMap) -> bool {
+    headers.get(header::CONTENT_TYPE)
+        .and_then(|h| h.to_str().ok())
+        .map(|content_type| {
+            let content_type = content_type.trim_start().to_ascii_lowercase();
+            content_type.starts_with("text/html") || content_type.starts_with("application/xhtml+xml")
+        })
+        .unwrap_or(false)
+}
+
+/// Whether a browser would show this as an image, which is what GitHub's image proxy checks too
+fn is_image(content_type: Option<&str>, url: &str) -> bool {
+    let content_type = match content_type {
+        Some(content_type) => content_type.split(';').next().unwrap().trim().to_ascii_lowercase(),
+        None => return false,
+    };
+    // Some hosts serve svg files as generic xml
+    let svg = Url::parse(url).map(|url| url.path().to_ascii_lowercase().ends_with(".svg")).unwrap_or(false);
+    content_type.starts_with("image/") || content_type == "application/svg+xml" ||
+        (svg && (content_type == "text/xml" || content_type == "application/xml"))
+}
+
+/// The meta refresh is in the head, so there is no need to read further than this
+const META_REFRESH_BYTES: usize = 32 * 1024;
+
+/// Finds the target of a `<meta http-equiv="refresh" content="0; url=...">`, resolved against
+/// the page's url. Refreshes without a url, or to the page itself, only reload the page.
+fn meta_refresh(url: &str, body: &[u8]) -> Option<String> {
+    let document = Html::parse_document(&String::from_utf8_lossy(body));
+    let selector = Selector::parse("meta[http-equiv][content]").unwrap();
+    let content = document.select(&selector)
+        .find(|meta| meta.value().attr("http-equiv").unwrap().eq_ignore_ascii_case("refresh"))?
+        .value().attr("content").unwrap();
+    // The delay comes first, separated by a semicolon or comma
+    let target = content.split_once([';', ','])?.1.trim();
+    let target = match target.find('=') {
+        Some(equals) if target[..equals].trim().eq_ignore_ascii_case("url") => target[equals + 1..].trim(),
+        _ => target,
+    };
+    let target = resolve(url, target.trim_matches(['\'', '"']))?;
+    if target == url { None } else { Some(target) }
+}
+
+/// Resolves a redirect target against the url that pointed there (RFC 7231 allows relative
+/// Location headers), so that redirect chains only ever contain absolute urls
+fn resolve(base: &str, target: &str) -> Option<String> {
+    Url::parse(base).ok()?.join(target).ok().map(String::from)
+}
+
+/// Bits of the pages bot protection serves instead of the content, and who serves them
+const CHALLENGE_MARKERS: &[(&str, &str)] = &[
+    ("cf-browser-verification", "Cloudflare challenge"),
+    ("/cdn-cgi/challenge-platform/", "Cloudflare challenge"),
+    ("Checking your browser before accessing", "Cloudflare challenge"),
+    ("hcaptcha.com", "hCaptcha"),
+    ("captcha-delivery.com", "DataDome captcha"),
+    ("px-captcha", "PerimeterX captcha"),
+];
+
+/// The challenge page is near the top, so there is no need to read further than this
+const CHALLENGE_BYTES: usize = 16 * 1024;
+
+/// Recognizes the challenge pages of bot protection, which a human gets past just fine. We are
+/// still holding the host permit and handle here, so this sends the GET itself if it has to.
+async fn challenge(checker: &Checker, url: &str, resp: reqwest::Response, fallbacks: &Fallbacks) -> Option<String> {
+    let mitigated = resp.headers().get("cf-mitigated").and_then(|h| h.to_str().ok());
+    if mitigated == Some("challenge") {
+        return Some("Cloudflare challenge".to_string());
+    }
+    let resp = if fallbacks.get {
+        resp
+    } else {
+        drop(resp);
+        send(checker, url, &Fallbacks { get: true, ..fallbacks.clone() }).await.ok()?
+    };
+    let body = read_body(checker, resp, CHALLENGE_BYTES).await.ok()?;
+    let body = String::from_utf8_lossy(&body);
+    CHALLENGE_MARKERS.iter()
+        .find(|(marker, _)| body.contains(marker))
+        .map(|(_, reason)| reason.to_string())
+}
+
+/// Longest we are willing to wait when a server rate limits us
+const MAX_RETRY_AFTER: time::Duration = time::Duration::from_secs(120);
+
+/// Parses a Retry-After value, which is either delay-seconds or an HTTP-date
+fn parse_retry_after(value: &str, now: time::SystemTime) -> Option<time::Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse() {
+        return Some(time::Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    // A date in the past means we can retry right away
+    Some(date.duration_since(now).unwrap_or_default())
+}
+
+fn retry_after(resp: &reqwest::Response) -> Option<time::Duration> {
+    let value = resp.headers().get(header::RETRY_AFTER)?.to_str().ok()?;
+    parse_retry_after(value, time::SystemTime::now())
+}
+
+/// When the rate limit resets, if the response says we ran into it. This is how GitHub (and
+/// others copying it) tell us, with a 429, or a 403 from GitHub itself. Anywhere else a 403 is
+/// about the page, whatever the headers say about some API's limit.
+fn rate_limit_reset(resp: &reqwest::Response) -> Option<time::Duration> {
+    let header = |name| resp.headers().get(name).and_then(|h: &header::HeaderValue| h.to_str().ok());
+    let limited = resp.status() == StatusCode::TOO_MANY_REQUESTS ||
+        (resp.status() == StatusCode::FORBIDDEN && is_github(&host_of(resp.url().as_str())));
+    if !limited || header("x-ratelimit-remaining") != Some("0") {
+        return None;
+    }
+    let reset = time::UNIX_EPOCH + time::Duration::from_secs(header("x-ratelimit-reset")?.trim().parse().ok()?);
+    Some(reset.duration_since(time::SystemTime::now()).unwrap_or_default().min(MAX_RATE_LIMIT_PAUSE))
+}
+
+/// GitHub's limits reset every hour, anything longer than that is bogus
+const MAX_RATE_LIMIT_PAUSE: time::Duration = time::Duration::from_secs(60 * 60);
+
+/// Fetches a single url, retrying on errors. Redirects are returned as-is.
+/// Tries HEAD first, and returns which fallbacks it needed to get a response.
+async fn get_single(checker: &Checker, url: &str) -> Result<(reqwest::Response, Fallbacks), CheckerError> {
+    let opt = &checker.opt;
+    let mut res;
+    let mut fallbacks = Fallbacks::default();
+    let mut attempts = 0;
+    loop {
+        // The global handle first and then the host's permit, the same order everywhere, so a
+        // request never holds a host's permit while it waits for a handle
+        let handle = checker.handles.get().await;
+        let host = checker.host(url);
+        let permit = host.permit(opt, url).await;
+        // The host might have gone down while we were queued up for it
+        if host.tripped(opt) {
+            return Err(CheckerError::CircuitOpen {host: host_of(url)});
+        }
+        let mut resp = send(checker, url, &fallbacks).await;
+        if let Err(ref err) = resp {
+            if let Some(reason) = tls_error(err) {
+                if fallbacks.insecure.is_none() && checker.config.tolerates_invalid_certs(&host_of(url)) {
+                    debug!("Invalid certificate for {} ({}), retrying without checking it", url, reason);
+                    fallbacks.insecure = Some(reason);
+                    resp = send(checker, url, &fallbacks).await;
+                }
+            }
+        }
+        if let Ok(ref ok) = resp {
+            if !fallbacks.get && head_unsupported(ok.status()) {
+                debug!("Got {} for HEAD {}, falling back to GET", ok.status(), url);
+                fallbacks.get = true;
+                resp = send(checker, url, &fallbacks).await;
+            }
+        }
+        if let Ok(ref ok) = resp {
+            if ok.status() == StatusCode::FORBIDDEN && !fallbacks.browser && browser_retry_allowed(opt, url) {
+                debug!("Got 403 for {}, retrying with browser headers", url);
+                fallbacks.browser = true;
+                resp = send(checker, url, &fallbacks).await;
+            }
+        }
+        host.finished();
+        attempts += 1;
+        *checker.attempts.lock().unwrap().entry(url.to_string()).or_default() += 1;
+        let (retry, wait) = match resp {
+            Err(err) => {
+                warn!("Error while getting {}: {}", url, err);
+                let err = CheckerError::from(err);
+                let retry = checker.retries.for_error(err.kind());
+                res = Err(err);
+                (retry, None)
+            }
+            Ok(ok) => {
+                let status = ok.status();
+                if status.is_success() || status.is_redirection() || checker.config.accepts_status(url, status.as_u16()) {
+                    return Ok((ok, fallbacks));
+                }
+                let retry = checker.retries.for_status(status);
+                let retry_after = retry_after(&ok);
+                let rate_limit_reset = rate_limit_reset(&ok);
+                match (status, retry_after, rate_limit_reset) {
+                    (_, _, Some(reset)) => {
+                        // Every other request to the host would run into the same limit
+                        if host.pause(reset) {
+                            warn!("Rate limit reached, pausing {} for {:?}", host_of(url), reset);
+                        }
+                        res = Err(CheckerError::RateLimited);
+                        (checker.retries.for_status(StatusCode::TOO_MANY_REQUESTS), Some(time::Duration::from_secs(0)))
+                    }
+                    (StatusCode::TOO_MANY_REQUESTS, wait, _) => {
+                        res = Err(CheckerError::RateLimited);
+                        (retry, wait.map(|wait| wait.min(MAX_RETRY_AFTER)))
+                    }
+                    (StatusCode::SERVICE_UNAVAILABLE, Some(wait), _) => {
+                        res = Err(CheckerError::TemporarilyUnavailable {retry_after: wait});
+                        if wait > time::Duration::from_secs(opt.max_unavailable_wait) {
+                            break;
+                        }
+                        (retry, Some(wait))
+                    }
+                    _ => {
+                        if status == StatusCode::FORBIDDEN || status == StatusCode::SERVICE_UNAVAILABLE {
+                            if let Some(reason) = challenge(checker, url, ok, &fallbacks).await {
+                                // Retrying won't get us past it
+                                warn!("Got {} for {}, looks like a {}", status, url, reason);
+                                return Err(CheckerError::Unverifiable {reason});
+                            }
+                        }
+                        warn!("Error while getting {}: {}", url, status);
+                        if status == StatusCode::NOT_ACCEPTABLE {
+                            fallbacks.accept = fallbacks.accept.next();
+                            debug!("Retrying {} with {}", url, fallbacks.accept);
+                        }
+                        res = Err(CheckerError::HttpError {status, chain: vec![]});
+                        (retry, None)
+                    }
+                }
+            }
+        };
+        if attempts >= retry.attempts {
+            debug!("Giving up on {} after {} attempts", url, attempts);
+            break;
+        }
+        let wait = wait.unwrap_or_else(|| retry.backoff(attempts));
+        if wait > time::Duration::from_secs(0) {
+            // Wait without holding a handle, so other hosts can make progress meanwhile
+            warn!("Retrying {} in {:?}", url, wait);
+            drop(permit);
+            drop(handle);
+            task::sleep(wait).await;
+        }
+    }
+    res
+}
+
+/// Checks a url, following redirects, and times how long that took. `image` is whether the
+/// readme shows it as an image, which the url it ends up at has to be too.
+fn get_url(checker: Arc<Checker>, url: String, image: bool) -> BoxFuture<'static, (String, Result<Checked, CheckerError>)> {
+    async move {
+        let start = time::Instant::now();
+        let (url, res) = check_url(checker.clone(), url, image).await;
+        let duration = start.elapsed();
+        checker.durations.lock().unwrap().insert(url.clone(), duration);
+        let res = res.map(|mut checked| {
+            checked.success.duration_ms = duration.as_millis() as u64;
+            checked
+        });
+        (url, res)
+    }.boxed()
+}
+
+fn check_url(checker: Arc<Checker>, url: String, image: bool) -> BoxFuture<'static, (String, Result<Checked, CheckerError>)> {
+    async move {
+        let opt = &checker.opt;
+        let origin = checker.host(&url);
+        if origin.tripped(opt) {
+            return (url.clone(), Err(CheckerError::CircuitOpen {host: host_of(&url)}));
+        }
+        // Embeds of deleted videos still load, the player just says the video is gone
+        if let Some(oembed) = urls::youtube_oembed(&url) {
+            let (_oembed, res) = get_url(checker.clone(), oembed, false).await;
+            return (url, res);
+        }
+        if let Some((org, repo)) = github::repo_of(&url) {
+            if let Some(res) = github::check_repo(&checker, &org, &repo).await {
+                let res = res.map(|repository| {
+                    // GraphQL answers for the new name of a renamed repository, where the page redirects
+                    let chain = if repository.full_name.eq_ignore_ascii_case(&format!("{}/{}", org, repo)) {
+                        vec![]
+                    } else {
+                        vec![format!("https://github.com/{}", repository.full_name)]
+                    };
+                    let success = CheckSuccess {
+                        status: StatusCode::OK.as_u16(),
+                        final_url: chain.last().unwrap_or(&url).clone(),
+                        http_version: repository.http_version.clone(),
+                        content_type: Some("application/json".to_string()),
+                        duration_ms: 0,
+                    };
+                    Checked { chain, archived: repository.archived, success, ..Checked::default() }
+                });
+                origin.record(&res);
+                return (url, res);
+            }
+        }
+        let mut chain: Vec<String> = vec![];
+        let mut fallbacks = Fallbacks::default();
+        let fragment = Url::parse(&url).ok()
+            .and_then(|parsed| parsed.fragment().map(|fragment| percent_encoding::percent_decode_str(fragment).decode_utf8_lossy().to_string()))
+            .filter(|fragment| content::checkable_fragment(fragment));
+        // Coming back to a url is only a loop if no cookies were set in between, because consent
+        // pages set a cookie and then send us back to where we came from
+        let mut cookies_set = 0;
+        let mut visited = BTreeMap::new();
+        visited.insert(url.clone(), cookies_set);
+        let res = loop {
+            let current = chain.last().unwrap_or(&url).clone();
+            let (resp, hop_fallbacks) = match get_single(&checker, &current).await {
+                Ok((resp, hop_fallbacks)) => {
+                    fallbacks.merge(hop_fallbacks.clone());
+                    (resp, hop_fallbacks)
+                }
+                Err(CheckerError::HttpError {status, ..}) => break Err(CheckerError::HttpError {status, chain}),
+                Err(err) => break Err(err),
+            };
+            let status = resp.status();
+            let location = resp.headers().get(header::LOCATION).and_then(|h| h.to_str().ok()).map(str::to_string);
+            let sets_cookie = resp.headers().contains_key(header::SET_COOKIE);
+            let validators = Validators::from_headers(resp.headers());
+            let content_type = resp.headers().get(header::CONTENT_TYPE).and_then(|h| h.to_str().ok()).map(str::to_string);
+            let success = CheckSuccess {
+                status: status.as_u16(),
+                final_url: current.clone(),
+                http_version: format!("{:?}", resp.version()),
+                content_type: content_type.clone(),
+                duration_ms: 0,
+            };
+            let html = is_html(resp.headers());
+            // Badges are tiny, and only their text tells whether they work
+            let badge = is_badge_host(&host_of(&current)) && image;
+            // Pages which answered a HEAD take another request for the body, so only when something
+            // needs it: a meta refresh is rare enough to only look for it on a page we read anyway
+            let needs_body = fragment.is_some() || opt.detect_soft_404 || opt.meta_refresh || hop_fallbacks.get;
+            let body = if status.is_success() && ((html && needs_body) || badge) {
+                // The section might be anywhere on the page
+                let limit = if fragment.is_some() && html {
+                    opt.max_body_size
+                } else if opt.detect_soft_404 {
+                    content::SOFT_404_BYTES
+                } else {
+                    META_REFRESH_BYTES
+                };
+                // A HEAD response has no body to look at
+                let body = if hop_fallbacks.get {
+                    read_body(&checker, resp, limit).await
+                } else {
+                    drop(resp);
+                    get_body(&checker, &current, &hop_fallbacks, limit).await
+                };
+                match body {
+                    Ok(body) => Some(body),
+                    Err(err) => {
+                        debug!("Can't read the body of {}: {}", current, err);
+                        None
+                    }
+                }
+            } else {
+                // All we need is the status and headers, so close the connection rather than let it
+                // download a body nobody reads (which might be a huge release tarball)
+                drop(resp);
+                None
+            };
+            let refresh = body.as_ref().filter(|_| html).and_then(|body| meta_refresh(&current, body));
+            if status == StatusCode::NOT_MODIFIED {
+                debug!("Not modified since last run: {}", url);
+                let validators = checker.validators.get(&url).cloned().unwrap_or_default();
+                break Ok(Checked { chain, fallbacks, validators, success, ..Checked::default() });
+            }
+            let location = if let Some(refresh) = refresh {
+                debug!("Meta refresh from {} to {}", current, refresh);
+                refresh
+            } else if !status.is_redirection() {
+                debug!("Finished {}", url);
+                if !status.is_success() {
+                    // Only accepted statuses get this far, and there is nothing to check on them
+                    break Ok(Checked { chain, fallbacks, accepted: Some(status), success, ..Checked::default() });
+                }
+                if image && !is_image(content_type.as_deref(), &current) {
+                    let got = content_type.unwrap_or_else(|| "no content type".to_string());
+                    break Err(CheckerError::WrongContentType {expected: "image/*".to_string(), got});
+                }
+                // Validators only make sense for the url that returned them
+                let validators = if chain.is_empty() { validators } else { Validators::default() };
+                let badge = body.as_ref().filter(|_| badge).and_then(|body| content::badge_text(body));
+                if let Some(text) = badge.as_ref().filter(|text| content::broken_badge(text)) {
+                    break Err(CheckerError::BrokenBadge {text: text.clone()});
+                }
+                let body = body.filter(|_| html);
+                let missing_anchor = match (fragment.clone(), github::raw_markdown(&current)) {
+                    // GitHub renders files with scripts, the page itself has no anchors
+                    (Some(fragment), Some(raw)) => match get_body(&checker, &raw, &Fallbacks::default(), opt.max_body_size).await {
+                        Ok(source) => Some(fragment)
+                            .filter(|fragment| !anchors::Anchors::from_markdown(&String::from_utf8_lossy(&source)).contains(fragment)),
+                        Err(err) => {
+                            debug!("Can't get {} to look for #{}: {}", raw, fragment, err);
+                            None
+                        }
+                    },
+                    // Up to the limit, the anchor might be further down
+                    (fragment, None) => fragment.filter(|fragment| matches!(body,
+                        Some(ref body) if body.len() < opt.max_body_size && !content::has_anchor(body, fragment))),
+                    (None, _) => None,
+                };
+                if let Some(anchor) = missing_anchor.as_ref().filter(|_| opt.strict_anchors) {
+                    break Err(CheckerError::MissingAnchor {anchor: anchor.clone()});
+                }
+                let parked = body.as_ref()
+                    .and_then(|body| content::parked(body))
+                    .map(|provider| format!("{} ({})", PARKED, provider));
+                let suspect = parked.or_else(|| body.filter(|_| opt.detect_soft_404)
+                    .and_then(|body| content::soft_404(&body, &checker.config.soft_404_phrases)));
+                break Ok(Checked { chain, fallbacks, validators, suspect, badge, success, missing_anchor, ..Checked::default() });
+            } else if let Some(location) = location.as_ref().and_then(|location| resolve(&current, location)) {
+                debug!("Redirect from {} to {}", current, location);
+                location
+            } else {
+                warn!("Redirect from {} without a valid location: {:?}", current, location);
+                break Err(CheckerError::HttpError {status, chain});
+            };
+            if checker.login_pages.is_match(&location) {
+                break Err(CheckerError::AccessRestricted {login: location});
+            }
+            if sets_cookie {
+                cookies_set += 1;
+            }
+            let looped = visited.insert(location.clone(), cookies_set) == Some(cookies_set);
+            chain.push(location);
+            if looped {
+                break Err(CheckerError::RedirectLoop {chain});
+            }
+            if chain.len() > opt.max_redirects {
+                break Err(CheckerError::TooManyRedirects {chain});
+            }
+        };
+        if let Err(CheckerError::HttpError {status: StatusCode::NOT_FOUND, ..}) = res {
+            if let Some(repository) = github::workflow_repository(&url) {
+                warn!("Got 404 for GitHub workflow {}, checking {} instead", url, repository);
+                let (_new_url, res) = get_url(checker.clone(), repository.clone(), false).await;
+                return (url, res.map(|checked| Checked { stale_workflow: Some(repository), ..checked }));
+            }
+            // Parentheses in urls trip up markdown, e.g. for Wikipedia articles, and bare urls pick
+            // up the punctuation after them
+            if let Some(repaired) = urls::repaired(&url) {
+                warn!("Got 404 for {}, checking {} instead", url, repaired);
+                if let (_, Ok(_)) = get_url(checker.clone(), repaired.clone(), image).await {
+                    return (url, Err(CheckerError::MangledUrl {repaired}));
+                }
+            }
+        }
+        if matches!(res, Err(ref err) if err.is_connection_failure()) && url.starts_with("http://") {
+            // Some sites stopped listening on port 80 altogether
+            let https = format!("https://{}", &url["http://".len()..]);
+            warn!("Can't connect to {}, trying {}", url, https);
+            // The same host, which already recorded how this went
+            return match get_url(checker.clone(), https.clone(), image).await {
+                (_, Ok(checked)) => (url, Ok(Checked { moved: Some(https), ..checked })),
+                (_, Err(_)) => (url, res),
+            };
+        }
+        origin.record(&res);
+        let res = match res {
+            Ok(checked) if checked.suspect.is_none() && checked.chain.last()
+                    .is_some_and(|target| domains::crosses_domains(&checker.config, &host_of(&url), &host_of(target))) => {
+                let target = checked.chain.last().unwrap().clone();
+                Ok(Checked { suspect: Some(format!("redirects to a different domain, {}", target)), ..checked })
+            }
+            res => res,
+        };
+        let res = match res {
+            // Urls which redirect already say where the content lives now, and a page which might
+            // not be there isn't worth an upgrade
+            Ok(checked) if checked.chain.is_empty() && url.starts_with("http://") &&
+                    checked.suspect.is_none() && checked.accepted.is_none() => {
+                let https = format!("https://{}", &url["http://".len()..]);
+                let upgrade = match get_single(&checker, &https).await {
+                    Ok((resp, _)) if resp.status().is_success() => Some(https),
+                    _ => None,
+                };
+                Ok(Checked { upgrade, ..checked })
+            }
+            res => res,
+        };
+        (url, res)
+    }.boxed()
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Results {
+    working: BTreeSet<String>,
+    #[serde(deserialize_with = "failures::deserialize")]
+    failed: BTreeMap<String, Failure>,
+    /// Working urls that only work after following redirects, with the full chain
+    #[serde(default)]
+    redirected: BTreeMap<String, Vec<String>>,
+    /// Hosts which don't answer HEAD requests properly, so we used GET
+    #[serde(default)]
+    needs_get: BTreeSet<String>,
+    /// Urls which only work with the headers of a regular browser
+    #[serde(default)]
+    needs_browser: BTreeSet<String>,
+    /// ETag and Last-Modified of working urls, for conditional requests
+    #[serde(default)]
+    validators: BTreeMap<String, Validators>,
+    /// Urls we didn't get to before the deadline, which the next run checks first
+    #[serde(default)]
+    unchecked: BTreeSet<String>,
+    /// Urls which asked us to come back later, e.g. because of a maintenance window
+    #[serde(default)]
+    unavailable: BTreeMap<String, String>,
+    /// Working urls with problems we chose to tolerate, like an invalid certificate
+    #[serde(default)]
+    warnings: BTreeMap<String, Vec<String>>,
+    /// Urls which should be updated, with the new url. Either they work over https too, or they
+    /// redirect there without changing anything that matters.
+    #[serde(default)]
+    suggestions: BTreeMap<String, String>,
+    /// Urls we couldn't get past bot protection for, with what kind of protection it was
+    #[serde(default)]
+    unverifiable: BTreeMap<String, String>,
+    /// Pages which answered, but look like they aren't there anymore, with the reason
+    #[serde(default)]
+    suspect: BTreeMap<String, String>,
+    /// What the badges in the readme currently say
+    #[serde(default)]
+    badges: BTreeMap<String, String>,
+    /// Urls which send anonymous readers to a login page, with the login page
+    #[serde(default)]
+    access_restricted: BTreeMap<String, String>,
+    /// Plain http urls which don't connect anymore, but work over https, with the https url
+    #[serde(default)]
+    moved_to_https: BTreeMap<String, String>,
+    /// GitHub workflow urls (including badges) which 404, with the repository they belong to
+    #[serde(default)]
+    stale_workflows: BTreeMap<String, String>,
+    /// When each working url was last checked, in seconds since the Unix epoch
+    #[serde(default)]
+    last_checked: BTreeMap<String, u64>,
+    /// How each working url answered when we last checked it, with `--verbose-results`
+    #[serde(default, alias = "successes", skip_serializing_if = "BTreeMap::is_empty")]
+    working_details: BTreeMap<String, CheckSuccess>,
+    /// Urls which answer 406 to our usual Accept header, with the one that worked
+    #[serde(default)]
+    needs_accept: BTreeMap<String, String>,
+    /// Working urls whose page doesn't have the section the fragment points to, with the fragment
+    #[serde(default)]
+    missing_anchors: BTreeMap<String, String>,
+    /// Reference definitions nothing links to, with their url and where they are
+    #[serde(default)]
+    unused_references: BTreeMap<String, String>,
+    /// Failed urls the Wayback Machine has a copy of, with `--suggest-archive`
+    #[serde(default)]
+    archived: BTreeMap<String, archive::Snapshot>,
+    /// Urls whose host looks like it's pretending to be another one, with why
+    #[serde(default)]
+    homographs: BTreeMap<String, String>,
+    /// Projects which more than one entry links to, with where those links are
+    #[serde(default)]
+    duplicates: BTreeMap<String, Vec<String>>,
+}
+
+impl Results {
+    fn new() -> Results {
+        Results {
+            working: BTreeSet::new(),
+            failed: BTreeMap::new(),
+            redirected: BTreeMap::new(),
+            needs_get: BTreeSet::new(),
+            needs_browser: BTreeSet::new(),
+            validators: BTreeMap::new(),
+            unchecked: BTreeSet::new(),
+            unavailable: BTreeMap::new(),
+            warnings: BTreeMap::new(),
+            suggestions: BTreeMap::new(),
+            moved_to_https: BTreeMap::new(),
+            stale_workflows: BTreeMap::new(),
+            last_checked: BTreeMap::new(),
+            working_details: BTreeMap::new(),
+            needs_accept: BTreeMap::new(),
+            archived: BTreeMap::new(),
+            missing_anchors: BTreeMap::new(),
+            unused_references: BTreeMap::new(),
+            unverifiable: BTreeMap::new(),
+            access_restricted: BTreeMap::new(),
+            suspect: BTreeMap::new(),
+            badges: BTreeMap::new(),
+            homographs: BTreeMap::new(),
+            duplicates: BTreeMap::new(),
+        }
+    }
+
+    /// The results of the last run, or empty ones if there are none (or we don't keep any). A
+    /// file we can't make sense of is an error, as overwriting it would lose everything in it,
+    /// unless `force` says to start over.
+    fn load(path: Option<&Path>, force: bool) -> Result<Results, Error> {
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(Results::new()),
+        };
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Results::new()),
+            Err(e) => return Err(format_err!("Can't read {}: {}", path.display(), e)),
+        };
+        // Whatever it's called, so renaming it to switch formats doesn't lose anything
+        let parsed = if contents.trim_start().starts_with('{') {
+            serde_json::from_str(&contents).map_err(Error::from)
+        } else {
+            serde_yaml::from_str(&contents).map_err(Error::from)
+        };
+        match parsed {
+            Ok(results) => Ok(Results::normalized(results)),
+            Err(e) if force => {
+                warn!("Can't parse {} ({}), starting over because of --force", path.display(), e);
+                Ok(Results::new())
+            }
+            Err(e) => Err(format_err!("Can't parse {}: {}. It's left alone, fix it, delete it or pass --force to start over", path.display(), e)),
+        }
+    }
+
+    /// The results with the urls normalized like the ones in the readme, as older versions kept
+    /// them the way they were written
+    fn normalized(self) -> Results {
+        fn keys<V>(map: BTreeMap<String, V>) -> BTreeMap<String, V> {
+            map.into_iter().map(|(url, value)| (urls::normalize(&url), value)).collect()
+        }
+        fn set(urls: BTreeSet<String>) -> BTreeSet<String> {
+            urls.iter().map(|url| urls::normalize(url)).collect()
+        }
+        Results {
+            working: set(self.working),
+            failed: keys(self.failed),
+            redirected: keys(self.redirected),
+            needs_get: self.needs_get,
+            needs_browser: set(self.needs_browser),
+            validators: keys(self.validators),
+            unchecked: set(self.unchecked),
+            unavailable: keys(self.unavailable),
+            warnings: keys(self.warnings),
+            suggestions: keys(self.suggestions),
+            unverifiable: keys(self.unverifiable),
+            suspect: keys(self.suspect),
+            badges: keys(self.badges),
+            access_restricted: keys(self.access_restricted),
+            moved_to_https: keys(self.moved_to_https),
+            stale_workflows: keys(self.stale_workflows),
+            last_checked: keys(self.last_checked),
+            working_details: keys(self.working_details),
+            needs_accept: keys(self.needs_accept),
+            missing_anchors: keys(self.missing_anchors),
+            unused_references: self.unused_references,
+            archived: keys(self.archived),
+            homographs: keys(self.homographs),
+            duplicates: self.duplicates,
+        }
+    }
+
+    /// Drops everything about a url, for when it's gone from the readme
+    fn forget(&mut self, url: &str) {
+        self.forget_checks(url);
+        self.failed.remove(url);
+        self.archived.remove(url);
+        self.homographs.remove(url);
+    }
+
+    /// Drops what the checks of a url found, but not how it failed or what the readme says about
+    /// it, for when a check finds something else
+    fn forget_checks(&mut self, url: &str) {
+        self.working.remove(url);
+        self.redirected.remove(url);
+        self.needs_browser.remove(url);
+        self.validators.remove(url);
+        self.unchecked.remove(url);
+        self.unavailable.remove(url);
+        self.warnings.remove(url);
+        self.suggestions.remove(url);
+        self.unverifiable.remove(url);
+        self.suspect.remove(url);
+        self.badges.remove(url);
+        self.access_restricted.remove(url);
+        self.moved_to_https.remove(url);
+        self.stale_workflows.remove(url);
+        self.last_checked.remove(url);
+        self.working_details.remove(url);
+        self.needs_accept.remove(url);
+        self.missing_anchors.remove(url);
+    }
+
+    /// Forgets the working urls which aren't in the files anymore, given how the urls in the
+    /// files are written by their normalized url, and returns how many that were
+    fn prune(&mut self, spellings: &BTreeMap<String, BTreeSet<String>>) -> usize {
+        // Every url in the files is in `spellings`, even the ones we didn't check this time
+        let gone: Vec<_> = self.working.iter().filter(|url| !spellings.contains_key(*url)).cloned().collect();
+        for url in &gone {
+            self.forget(url);
+        }
+        gone.len()
+    }
+
+    /// Whether a working url was last checked long enough ago to check it again, `max_age` give
+    /// or take a fifth
+    fn due_for_recheck(&self, url: &str, max_age: time::Duration, now: u64) -> bool {
+        match self.last_checked.get(url) {
+            Some(checked) => now.saturating_sub(*checked) as f64 >= max_age.as_secs_f64() * (0.8 + 0.4 * jitter(url)),
+            None => true,
+        }
+    }
+
+    /// Gives the working urls from before we kept track a time they were last checked, spread
+    /// over the last `max_age` so they come due over the next runs rather than all at once
+    fn backdate_unknown(&mut self, max_age: time::Duration, now: u64) {
+        for url in &self.working {
+            let age = (max_age.as_secs_f64() * jitter(url)) as u64;
+            self.last_checked.entry(url.clone()).or_insert_with(|| now.saturating_sub(age));
+        }
+    }
+
+    /// Writes the results next to the file and then moves them over it, so being killed
+    /// halfway through doesn't leave half a file behind
+    fn save(&self, path: Option<&Path>) -> Result<(), Error> {
+        if let Some(path) = path {
+            let mut name = path.file_name().ok_or_else(|| format_err!("{} isn't a file", path.display()))?.to_os_string();
+            name.push(".tmp");
+            let temporary = path.with_file_name(name);
+            let contents = if is_json(path) { serde_json::to_string_pretty(self)? } else { serde_yaml::to_string(self)? };
+            fs::write(&temporary, contents)
+                .map_err(|e| format_err!("Can't write {}: {}", temporary.display(), e))?;
+            fs::rename(&temporary, path)
+                .map_err(|e| format_err!("Can't replace {}: {}", path.display(), e))?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether results are written as JSON rather than YAML
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("json"))
+}
+
+/// A fraction between 0 and 1 which is the same for a url on every run, to spread out what
+/// would otherwise happen to lots of urls at once
+fn jitter(url: &str) -> f64 {
+    use std::hash::{Hash, Hasher};
+    // Unlike a HashMap's, this one isn't seeded randomly
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    url.hash(&mut hasher);
+    (hasher.finish() % 1000) as f64 / 1000.0
+}
+
+/// Orders urls round-robin by host, keeping the original order within each host
+fn interleave_by_host(urls: Vec<String>) -> Vec<String> {
+    let mut by_host: BTreeMap<String, VecDeque<String>> = BTreeMap::new();
+    for url in urls {
+        by_host.entry(host_of(&url)).or_default().push_back(url);
+    }
+    let mut interleaved = vec![];
+    while !by_host.is_empty() {
+        for queue in by_host.values_mut() {
+            interleaved.extend(queue.pop_front());
+        }
+        by_host.retain(|_, queue| !queue.is_empty());
+    }
+    interleaved
+}
+
+/// Whether a redirect only adds a trailing slash or `www.`, or switches to https. Those mean the
+/// link is fine, it could just be a bit more exact.
+fn trivial_redirect(from: &str, to: &str) -> bool {
+    let (from, to) = match (Url::parse(from), Url::parse(to)) {
+        (Ok(from), Ok(to)) => (from, to),
+        _ => return false,
+    };
+    let scheme = from.scheme() == to.scheme() || (from.scheme() == "http" && to.scheme() == "https");
+    let host = |url: &Url| url.host_str().map(|host| host.trim_start_matches("www.").to_string());
+    let path = |url: &Url| url.path().trim_end_matches('/').to_string();
+    // Explicit ports only, the default one changes along with the scheme
+    scheme && host(&from) == host(&to) && from.port() == to.port() &&
+        path(&from) == path(&to) && from.query() == to.query()
+}
+
+/// Escapes a url for use as a single quoted sed pattern or replacement, with `|` as the delimiter
+fn sed_escape(url: &str) -> String {
+    url.chars().fold(String::new(), |mut escaped, c| {
+        match c {
+            '\'' => escaped.push_str("'\\''"),
+            c if "\\|&.*[]^$".contains(c) => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+        escaped
+    })
+}
+
+/// A failure message with the sections its link is in, if it's in any
+fn in_sections(message: String, sections: &[String]) -> String {
+    if sections.is_empty() {
+        message
+    } else {
+        format!("{} in {}", message, sections.join("; "))
+    }
+}
+
+/// Failures with what the results know them by, by host or section, or all under "" when they
+/// aren't grouped
+type Groups<'a> = BTreeMap<String, Vec<(&'a String, &'a Failure)>>;
+
+/// The failures in each category, most actionable first, by host with --sort-by domain, under
+/// each section heading they're in with --sort-by section (the ones outside of any section first),
+/// or all together otherwise, by url or by status and then url
+fn by_category(failed: &BTreeMap<String, Failure>, sort_by: SortBy) -> BTreeMap<failures::Category, Groups<'_>> {
+    let mut grouped: BTreeMap<_, BTreeMap<_, Vec<_>>> = BTreeMap::new();
+    for (key, failure) in failed {
+        let groups = match sort_by {
+            SortBy::Domain => vec![host_of(key)],
+            SortBy::Section if !failure.sections.is_empty() => failure.sections.clone(),
+            SortBy::Url | SortBy::Status | SortBy::Section => vec![String::new()],
+        };
+        let category = grouped.entry(failure.category()).or_default();
+        for group in groups {
+            category.entry(group).or_default().push((key, failure));
+        }
+    }
+    if sort_by == SortBy::Status {
+        // The sort is stable, so the ones with the same status stay in url order
+        for failures in grouped.values_mut().flat_map(BTreeMap::values_mut) {
+            failures.sort_by_key(|(_, failure)| failure.status);
+        }
+    }
+    grouped
+}
+
+/// How many failures there are in each category, like `3 4xx, 1 DNS`
+fn breakdown(failed: &BTreeMap<String, Failure>) -> String {
+    let mut counts: BTreeMap<failures::Category, usize> = BTreeMap::new();
+    for failure in failed.values() {
+        *counts.entry(failure.category()).or_default() += 1;
+    }
+    counts.iter().map(|(category, count)| format!("{} {}", count, category)).collect::<Vec<_>>().join(", ")
+}
+
+fn print_failures(results: &Results, sort_by: SortBy) {
+    for (category, groups) in by_category(&results.failed, sort_by) {
+        // A link in several sections is listed under each of them, but only counts once
+        let count = groups.values().flatten().map(|(key, _)| key).collect::<BTreeSet<_>>().len();
+        println!("{} ({}):", category, count);
+        for (group, failures) in groups {
+            let indent = if group.is_empty() {
+                "  "
+            } else {
+                println!("  {} ({}):", group, failures.len());
+                "    "
+            };
+            for (url, error) in failures {
+                match results.archived.get(url) {
+                    Some(snapshot) => println!("{}{} (archived copy available from {}: {})", indent, error, snapshot.date(), snapshot.url),
+                    None => println!("{}{}", indent, error),
+                }
+            }
+        }
+    }
+}
+
+fn redirect_chain(url: &str, chain: &[String]) -> String {
+    std::iter::once(url)
+        .chain(chain.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    env_logger::init();
+    let started = time::Instant::now();
+    let opt = Opt::from_args();
+    if opt.concurrency == 0 {
+        return Err(format_err!("--concurrency must be at least 1"));
+    }
+    if opt.per_host == 0 {
+        return Err(format_err!("--per-host must be at least 1"));
+    }
+    info!("Checking up to {} urls at once", opt.concurrency);
+    if let Some(ref proxy) = opt.proxy {
+        proxy::check_flag(proxy, time::Duration::from_secs(opt.connect_timeout)).await?;
+    }
+    let mut checker = Checker::new(opt)?;
+    info!("Retry policy: {}", checker.retries);
+    // Piped input isn't the readme the results are about, so only keep results if asked to
+    let stdin = checker.opt.files.iter().any(|file| file == Path::new("-"));
+    if stdin && checker.opt.diff_base.is_some() {
+        return Err(format_err!("--diff-base needs files in a git repository, not stdin"));
+    }
+    let results_path = match &checker.opt.results {
+        Some(path) => Some(path.clone()),
+        None if stdin => None,
+        None => Some(PathBuf::from("results.yaml")),
+    };
+    let results_path = results_path.as_deref();
+    let mut results = Results::load(results_path, checker.opt.force)?;
+    results.failed.clear();
+    results.unavailable.clear();
+    results.unverifiable.clear();
+    results.access_restricted.clear();
+    results.suspect.clear();
+    results.unused_references.clear();
+    results.homographs.clear();
+    results.duplicates.clear();
+    if !checker.opt.verbose_results {
+        results.working_details.clear();
+    }
+    let now = time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap_or_default().as_secs();
+    let max_age = time::Duration::from_secs(checker.opt.recheck_after * 24 * 60 * 60);
+    results.backdate_unknown(max_age, now);
+    // Working urls which are due to be checked again
+    let mut rechecked = 0;
+    checker.validators = results.validators.clone();
+
+    let mut to_check = vec![];
+    let mut images = BTreeSet::new();
+    // Where each url is in the markdown files, as path:line:column
+    let mut locations: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    // Which sections each link is in, like `Applications > Emulators`, under the key its failure
+    // would have in the results
+    let mut link_sections: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    // Links which aren't fetched, but only checked for how they're written: how many are fine,
+    // and what's wrong with the others, with where they are
+    let mut validated = 0;
+    let mut invalid: BTreeMap<String, (String, Vec<String>)> = BTreeMap::new();
+    // The links in list entries to each project, with the entry they're in, counting through all
+    // files, and where they are
+    let mut projects: BTreeMap<String, Vec<(usize, String, Option<String>)>> = BTreeMap::new();
+    let mut entries = 0;
+    // Entries which don't follow the format or are out of order, as path:line: rule
+    let mut lints = vec![];
+    let sorted = sections::Scope::new(&[], &checker.config.unsorted_sections);
+    // The tables of contents as they should be, for --print-toc
+    let mut tocs = vec![];
+    // How the urls are written in the files, which can differ from the way they're normalized
+    let mut spellings: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+
+    let mut files = vec![];
+    for pattern in &checker.opt.files {
+        let matches = local::glob(pattern);
+        if matches.is_empty() {
+            return Err(format_err!("No files match {}", pattern.display()));
+        }
+        for file in matches {
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+    }
+    if files.is_empty() {
+        files.push(PathBuf::from("README.md"));
+    }
+    // Links left alone because of link-check comments, with where they are
+    let mut ignored_links: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut commented_out = 0;
+    let scope = sections::Scope::new(&checker.opt.section, &checker.opt.skip_section);
+    // Links in the sections --section and --skip-section leave out, and which sections those are
+    let mut out_of_scope = 0;
+    let mut selected_sections = BTreeSet::new();
+    let mut skipped_sections = BTreeSet::new();
+    // Links on lines --diff-base didn't see change, urls it saw removed, and all the urls still there
+    let mut unchanged = 0;
+    let mut removed = BTreeSet::new();
+    let mut present = BTreeSet::new();
+    // Files linked from the ones we check, with how many links away from the given files they are
+    let mut queue: VecDeque<(PathBuf, usize)> = files.drain(..).map(|file| (file, 0)).collect();
+    let mut seen: BTreeSet<PathBuf> = queue.iter().map(|(file, _)| local::canonical(file)).collect();
+    while let Some((file, depth)) = queue.pop_front() {
+        files.push(file.clone());
+        let file = &file;
+        let (readme, markdown_input) = if file == Path::new("-") {
+            let mut input = String::new();
+            std::io::stdin().read_to_string(&mut input).map_err(|e| format_err!("Can't read stdin: {}", e))?;
+            ("stdin".to_string(), input)
+        } else {
+            let readme = file.display().to_string();
+            let input = fs::read_to_string(file).map_err(|e| format_err!("Can't read {}: {}", readme, e))?;
+            (readme, input)
+        };
+        // Labels of references without a definition, which pulldown-cmark asks us about
+        let undefined = RefCell::new(BTreeSet::new());
+        let broken_link = |label: &str, _: &str| {
+            undefined.borrow_mut().insert(references::normalize(label));
+            None
+        };
+        let parser = Parser::new_with_broken_link_callback(&markdown_input, Options::empty(), Some(&broken_link));
+        let lines = lines::Lines::new(&markdown_input);
+        let definitions = references::definitions(&markdown_input);
+        let mut used = BTreeSet::new();
+
+        let anchors = anchors::Anchors::from_markdown(&markdown_input);
+        // Links within the file, with where they are
+        let mut fragments: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        // Links to files in the repository, with where they are
+        let mut relative: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+        let outline = sections::Outline::new(anchors.headings());
+        selected_sections.extend(outline.titles().filter(|title| scope.selects(title)).map(str::to_string));
+        skipped_sections.extend(outline.titles().filter(|title| scope.skips(title)).map(str::to_string));
+        let changes = match &checker.opt.diff_base {
+            Some(base) => Some(diff::Changes::since(base, file).map_err(|e| format_err!("{}, {}", readme, e))?),
+            None => None,
+        };
+        if let Some(changes) = &changes {
+            removed.extend(changes.removed.iter().map(|url| urls::normalize(url)));
+            present.extend(urls::in_text(&markdown_input).into_iter().map(|(_, url)| urls::normalize(url)));
+        }
+        // Reference links whose definition changed, which count as changed themselves
+        let changed_references = RefCell::new(BTreeSet::new());
+        let changed = |offset: usize| changes.as_ref().is_none_or(|changes| {
+            changes.added(lines.line_column(offset).0) || changed_references.borrow().contains(&offset)
+        });
+        let mut ignored = match checker.opt.format {
+            InputFormat::Markdown => ignore::Ignored::from_markdown(&markdown_input).map_err(|e| format_err!("{}, {}", readme, e))?,
+            InputFormat::Urls => ignore::Ignored::default(),
+        };
+        let mut do_check = |written: String, image: bool, offset: usize, location: String| {
+            let url = urls::normalize(&written);
+            spellings.entry(url.clone()).or_default().insert(written);
+            if ignored.commented_out(offset) {
+                commented_out += 1;
+                return;
+            }
+            if ignored.ignores(offset) {
+                ignored_links.entry(url).or_default().push(location);
+                return;
+            }
+            let path = outline.path(offset);
+            if !scope.includes(&path) {
+                out_of_scope += 1;
+                return;
+            }
+            if !changed(offset) {
+                unchanged += 1;
+                return;
+            }
+            let key = if url.starts_with('#') {
+                format!("{}{}", readme, url)
+            } else if local::is_relative(&url) {
+                format!("{}: {}", readme, url)
+            } else {
+                url.clone()
+            };
+            let section = path.join(" > ");
+            let sections = link_sections.entry(key).or_default();
+            if !section.is_empty() && !sections.contains(&section) {
+                sections.push(section);
+            }
+            if url.starts_with('#') {
+                fragments.entry(url).or_default().push(location);
+                return;
+            }
+            if local::is_relative(&url) {
+                relative.entry(url).or_default().push(location);
+                return;
+            }
+            let scheme = url.split(':').next().unwrap_or_default().to_lowercase();
+            let syntax = match scheme.as_str() {
+                "mailto" => Some(schemes::check_mailto(&url)),
+                "tel" => Some(schemes::check_tel(&url)),
+                // Nothing to check, GitHub drops these, so however it's written it does nothing there
+                "javascript" => Some(Err("GitHub removes those, so it does nothing there".to_string())),
+                "data" => Some(schemes::check_data(&url, image, checker.opt.allow_data_images)),
+                _ => None,
+            };
+            match syntax {
+                Some(Ok(())) => {
+                    validated += 1;
+                    return;
+                }
+                Some(Err(reason)) => {
+                    let message = format!("[invalid {} link, {}] {}", scheme, reason, schemes::abbreviate(&url));
+                    invalid.entry(url).or_insert_with(|| (message, vec![])).1.push(location);
+                    return;
+                }
+                None => {}
+            }
+            if !url.starts_with("http") {
+                return;
+            }
+            if let Some(reason) = urls::homograph(&url) {
+                results.homographs.insert(url.clone(), reason);
+            }
+            let seen = locations.contains_key(&url);
+            locations.entry(url.clone()).or_default().push(location);
+            if image {
+                images.insert(url.clone());
+            }
+            // The same url in several places (or files) only needs checking once
+            if seen {
+                return;
+            }
+            if results.working.contains(&url) {
+                if !results.due_for_recheck(&url, max_age, now) {
+                    return;
+                }
+                rechecked += 1;
+            }
+            to_check.push(url);
+        };
+
+        if checker.opt.format == InputFormat::Urls {
+            for (offset, line, url) in urls::in_list(&markdown_input) {
+                match url {
+                    Ok(url) => do_check(url.to_string(), false, offset, format!("{}:{}", readme, line)),
+                    Err(text) => warn!("{}:{}: not a url: {}", readme, line, text),
+                }
+            }
+            continue;
+        }
+
+        // Text can come in several events, so bare urls are looked for once it's all there
+        let mut text: Option<std::ops::Range<usize>> = None;
+        let mut in_link = 0;
+        let mut in_code_block = false;
+        for (event, range) in parser.into_offset_iter() {
+            if let (Event::Text(_), true) | (Event::Code(_), _) = (&event, in_code_block) {
+                if checker.opt.include_code_blocks {
+                    let origin = if in_code_block { "code block" } else { "code span" };
+                    for (offset, url) in urls::in_text(&markdown_input[range.clone()]) {
+                        if urls::placeholder(url) || checker.config.ignores_code_url(url) {
+                            continue;
+                        }
+                        let (line, column) = lines.line_column(range.start + offset);
+                        do_check(url.to_string(), false, range.start + offset, format!("{}:{}:{} from {}", readme, line, column, origin));
+                    }
+                }
+            }
+            if let Event::Text(_) = event {
+                if checker.opt.bare_urls && in_link == 0 && !in_code_block {
+                    text = Some(text.map_or(range.clone(), |text| text.start..range.end));
+                }
+                continue;
+            }
+            if let Some(text) = text.take() {
+                for (offset, url) in urls::in_text(&markdown_input[text.clone()]) {
+                    let (line, column) = lines.line_column(text.start + offset);
+                    do_check(url.to_string(), false, text.start + offset, format!("{}:{}:{}", readme, line, column));
+                }
+            }
+            let (line, column) = lines.line_column(range.start);
+            let image = matches!(event, Event::Start(Tag::Image(..)));
+            match event {
+                Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+                Event::End(Tag::CodeBlock(_)) => in_code_block = false,
+                Event::End(Tag::Link(..)) | Event::End(Tag::Image(..)) => in_link -= 1,
+                // Email autolinks are addresses, not something we could fetch
+                Event::Start(Tag::Link(LinkType::Email, _, _)) => in_link += 1,
+                Event::Start(Tag::Link(link_type, url, _title)) | Event::Start(Tag::Image(link_type, url, _title)) => {
+                    in_link += 1;
+                    let mut location = format!("{}:{}:{}", readme, line, column);
+                    // Failures are fixed where the url is, which for references is the definition
+                    if let Some(definition) = references::label(&markdown_input, range.start, link_type).and_then(|label| definitions.get(&label)) {
+                        used.insert(references::normalize(&definition.label));
+                        if changes.as_ref().is_some_and(|changes| changes.added(lines.line_column(definition.offset).0)) {
+                            changed_references.borrow_mut().insert(range.start);
+                        }
+                        location = format!("{} via {}:{}", location, readme, lines.line_column(definition.offset).0);
+                    }
+                    do_check(url.to_string(), image, range.start, location);
+                }
+                Event::Html(content) => {
+                    // scraper doesn't tell us where in the html it found something
+                    let (links, warnings) = html::links(&content);
+                    for warning in warnings {
+                        warn!("{}:{}: {}", readme, line, warning);
+                    }
+                    for link in links {
+                        let location = match link.embed {
+                            Some(element) => format!("{}:{} from {}", readme, line, element),
+                            None => format!("{}:{}", readme, line),
+                        };
+                        do_check(link.url, link.image, range.start, location);
+                    }
+                }
+                _ => {}
+            }
+        }
+        let items = items::items(&markdown_input);
+        let first_entry = entries;
+        entries += items.len();
+        for link in duplicates::entry_links(&items) {
+            let path = outline.path(link.offset);
+            if ignored.commented_out(link.offset) || !scope.includes(&path) {
+                continue;
+            }
+            if let Some(project) = duplicates::project(&link.url) {
+                let (line, column) = lines.line_column(link.offset);
+                let section = Some(path.join(" > ")).filter(|section| !section.is_empty());
+                projects.entry(project).or_default().push((first_entry + link.entry, format!("{}:{}:{}", readme, line, column), section));
+            }
+        }
+        let mut violations = vec![];
+        if checker.opt.lint_entries {
+            violations.extend(lint::entries(&items, &checker.config.entry_format));
+        }
+        // Only the first entry out of order in a section, as moving it can put the rest in order
+        let mut unordered = BTreeSet::new();
+        if checker.opt.lint_order {
+            violations.extend(lint::order(&items, &lines).into_iter()
+                .filter(|violation| sorted.includes(&outline.path(violation.offset)))
+                .filter(|violation| unordered.insert(outline.path(violation.offset))));
+        }
+        violations.sort_by_key(|violation| violation.offset);
+        for violation in violations {
+            if !ignored.commented_out(violation.offset) && scope.includes(&outline.path(violation.offset)) && changed(violation.offset) {
+                lints.push(format!("{}:{}: {}", readme, lines.line_column(violation.offset).0, violation.rule));
+            }
+        }
+        // The table of contents is about the whole file, whatever changed or is checked
+        if checker.opt.check_toc || checker.opt.print_toc {
+            let headings = anchors.headings();
+            match toc::Toc::find(&markdown_input) {
+                Some(toc) if checker.opt.check_toc => {
+                    for problem in toc.check(headings) {
+                        lints.push(format!("{}:{}: table of contents: {}", readme, lines.line_column(problem.offset).0, problem.message));
+                    }
+                    tocs.push((readme.clone(), toc.render(headings)));
+                }
+                Some(toc) => tocs.push((readme.clone(), toc.render(headings))),
+                None => warn!("{} has no table of contents", readme),
+            }
+        }
+        for (label, definition) in &definitions {
+            if !used.contains(label) && scope.includes(&outline.path(definition.offset)) && changed(definition.offset) {
+                let location = format!("{}:{}", readme, lines.line_column(definition.offset).0);
+                results.unused_references.insert(format!("[{}]", definition.label), format!("{} ({})", definition.url, location));
+            }
+        }
+        for label in undefined.borrow().iter() {
+            if let Some(offset) = references::explicit_reference(&markdown_input, label).filter(|offset| scope.includes(&outline.path(*offset)) && changed(*offset)) {
+                let location = format!("{}:{}", readme, lines.line_column(offset).0);
+                let section = outline.path(offset).join(" > ");
+                let sections = link_sections.entry(format!("[{}]", label)).or_default();
+                if !section.is_empty() && !sections.contains(&section) {
+                    sections.push(section);
+                }
+                let mut failure = Failure::new(failures::Kind::UndefinedReference, format!("[undefined reference] [{}]", label));
+                failure.locations = vec![location];
+                failure.sections = sections.clone();
+                results.failed.insert(format!("[{}]", label), failure);
+            }
+        }
+        for (fragment, locations) in fragments {
+            let anchor = percent_encoding::percent_decode_str(&fragment[1..]).decode_utf8_lossy().to_string();
+            if !anchors.contains(&anchor) {
+                let key = format!("{}{}", readme, fragment);
+                let mut failure = Failure::new(failures::Kind::NoSuchHeading, format!("[no such heading] {}", fragment));
+                failure.locations = locations;
+                failure.sections = link_sections[&key].clone();
+                failure.suggestion = anchors.nearest(&anchor).map(|nearest| format!("#{}", nearest));
+                results.failed.insert(key, failure);
+            }
+        }
+        let readme_dir = file.parent().unwrap_or(Path::new(""));
+        for (url, locations) in relative {
+            let (message, nearest) = match local::check(Path::new(""), readme_dir, &url) {
+                Ok(target) => {
+                    let linked = checker.opt.recurse_local && depth < checker.opt.recurse_depth
+                        && local::is_markdown(&target) && local::inside(Path::new("."), &target);
+                    // Documents which link to each other only get checked once
+                    if linked && seen.insert(local::canonical(&target)) {
+                        queue.push_back((target, depth + 1));
+                    }
+                    continue;
+                }
+                Err(local::Problem::NoSuchFile(file)) => (format!("[no such file] {}", file.display()), None),
+                Err(local::Problem::NoSuchHeading {file, nearest}) => (format!("[no such heading in {}] {}", file.display(), url), nearest),
+            };
+            let key = format!("{}: {}", readme, url);
+            let mut failure = Failure::new(failures::Kind::NoSuchFile, message);
+            failure.locations = locations;
+            failure.sections = link_sections[&key].clone();
+            failure.suggestion = nearest.map(|nearest| format!("#{}", nearest));
+            results.failed.insert(key, failure);
+        }
+    }
+    if !checker.opt.section.is_empty() && selected_sections.is_empty() {
+        return Err(format_err!("No headings match --section {}", checker.opt.section.join(", ")));
+    }
+    for (project, links) in projects {
+        let linking = links.iter().map(|(entry, _, _)| entry).collect::<BTreeSet<_>>().len();
+        if linking < 2 {
+            continue;
+        }
+        if checker.opt.fail_on_duplicates {
+            // Under a url like every other failure, so the reports can link to it. If the url
+            // itself fails, that failure takes its place, and the run fails all the same.
+            let url = format!("https://{}", project);
+            let mut failure = Failure::new(failures::Kind::Duplicate, format!("[linked from {} entries] {}", linking, url));
+            failure.locations = links.iter().map(|(_, location, _)| location.clone()).collect();
+            failure.sections = links.iter().filter_map(|(_, _, section)| section.clone()).collect::<BTreeSet<_>>().into_iter().collect();
+            results.failed.insert(url, failure);
+        }
+        let locations = links.into_iter()
+            .map(|(_, location, section)| in_sections(location, section.as_slice()))
+            .collect();
+        results.duplicates.insert(project, locations);
+    }
+    for (url, (message, locations)) in invalid {
+        let mut failure = Failure::new(failures::Kind::InvalidLink, message);
+        failure.locations = locations;
+        failure.sections = link_sections.get(&url).cloned().unwrap_or_default();
+        results.failed.insert(url, failure);
+    }
+    if checker.opt.prune_removed {
+        for url in removed.difference(&present) {
+            results.forget(url);
+        }
+    }
+    let pruned = if checker.opt.prune { results.prune(&spellings) } else { 0 };
+    if checker.opt.prune_removed || checker.opt.prune {
+        results.save(results_path)?;
+    }
+    let checker = Arc::new(checker);
+    if checker.opt.github_graphql {
+        github::prefetch(&checker, &to_check).await;
+    }
+
+    // Only start a check once there is room for it, and alternate between hosts so we don't send
+    // a burst of requests to whichever host happens to be next in the readme
+    let hosts: BTreeSet<String> = to_check.iter().map(|url| host_of(url)).filter(|host| !host.is_empty()).collect();
+    let host_count = hosts.len();
+    let unresolved = if checker.proxied {
+        BTreeSet::new()
+    } else {
+        checker.dns.resolve_all(hosts, checker.opt.concurrency).await
+    };
+    let start = |url: String| {
+        let host = host_of(&url);
+        if unresolved.contains(&host) {
+            futures::future::ready((url, Err(CheckerError::DnsError {host}))).boxed()
+        } else {
+            let image = images.contains(&url);
+            get_url(checker.clone(), url, image)
+        }
+    };
+
+    let total = to_check.len();
+    // Whatever the last run didn't get to goes first
+    let (skipped, rest) = to_check.into_iter().partition(|url| results.unchecked.contains(url));
+    results.unchecked.clear();
+    let mut queue = interleave_by_host(skipped).into_iter().chain(interleave_by_host(rest));
+    let mut in_flight = BTreeSet::new();
+    let mut url_checks: Vec<_> = queue.by_ref()
+        .take(checker.opt.concurrency)
+        .inspect(|url| { in_flight.insert(url.clone()); })
+        .map(start)
+        .collect();
+    checker.in_flight.store(in_flight.len(), Ordering::Relaxed);
+
+    let deadline = checker.opt.deadline.map(|seconds| time::Instant::now() + time::Duration::from_secs(seconds));
+    let mut deadline_reached = false;
+    let mut unchecked = vec![];
+    let bar = progress::Bar::new(total, checker.opt.quiet);
+    let writer = writer::Writer::spawn(results, checker.clone(), locations.clone(), link_sections.clone(), results_path.map(Path::to_path_buf), bar);
+    while !url_checks.is_empty() {
+        debug!("Waiting...");
+        let next = match deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(time::Instant::now());
+                async_std::future::timeout(remaining, select_all(url_checks)).await.ok()
+            }
+            None => Some(select_all(url_checks).await),
+        };
+        let ((url, res), _index, remaining) = match next {
+            Some(next) => next,
+            None => {
+                // The timed out select dropped the checks in flight, which cancels them
+                warn!("Deadline reached, {} checks in flight", in_flight.len());
+                deadline_reached = true;
+                unchecked = in_flight.into_iter().chain(queue).collect();
+                break;
+            }
+        };
+        url_checks = remaining;
+        in_flight.remove(&url);
+        if let Some(next) = queue.next() {
+            in_flight.insert(next.clone());
+            url_checks.push(start(next));
+        }
+        checker.in_flight.store(in_flight.len(), Ordering::Relaxed);
+        writer.send(url, res);
+    }
+    let writer::Recorder {mut results, permanent, newly_working, ..} = writer.finish(unchecked).await?;
+    if checker.opt.suggest_archive && !deadline_reached {
+        let snapshots: Vec<_> = futures::stream::iter(results.failed.keys().cloned())
+            .map(|url| {
+                let checker = checker.clone();
+                async move {
+                    let snapshot = archive::closest_snapshot(&checker, &url).await;
+                    (url, snapshot)
+                }
+            })
+            .buffer_unordered(checker.opt.concurrency)
+            .collect()
+            .await;
+        // Whatever works again doesn't need a copy anymore
+        results.archived.clear();
+        results.archived.extend(snapshots.into_iter().filter_map(|(url, snapshot)| Some((url, snapshot?))));
+        results.save(results_path)?;
+    }
+    if checker.opt.archive_working && !deadline_reached {
+        archive::save_all(&checker, &newly_working).await;
+    }
+    let github_actions = checker.opt.github_actions.enabled();
+    if checker.opt.output_format != report::Format::Text || github_actions {
+        let durations = checker.durations.lock().unwrap().clone();
+        let attempts = checker.attempts.lock().unwrap().clone();
+        let run = report::Run {
+            files: files.iter().map(|file| file.display().to_string()).collect(),
+            locations: &locations,
+            link_sections: &link_sections,
+            lints: &lints,
+            ignored: &ignored_links,
+            durations: &durations,
+            attempts: &attempts,
+            permanent,
+            downloaded: checker.downloaded.load(Ordering::Relaxed),
+            duration: started.elapsed(),
+            deadline_reached,
+        };
+        let report = report::Report::new(&results, &run);
+        report.write(&checker.opt, &checker.config)?;
+        if github_actions {
+            for annotation in actions::annotations(&report) {
+                if checker.opt.text_on_stdout() {
+                    println!("{}", annotation);
+                } else {
+                    eprintln!("{}", annotation);
+                }
+            }
+            if let Some(path) = &checker.opt.step_summary {
+                actions::append_summary(path, &report)?;
+            }
+        }
+    }
+    // With the report in another format on stdout, the exit code is all that's left of this one
+    let text = checker.opt.text_on_stdout();
+    if text {
+        println!();
+        println!("Downloaded {} KiB", checker.downloaded.load(Ordering::Relaxed) / 1024);
+        // Each url was checked once, however many links it has
+        println!("{} unique urls in {} links", locations.len(), locations.values().map(Vec::len).sum::<usize>());
+        println!("{} distinct hosts, {} failed to resolve", host_count, unresolved.len());
+        if !ignored_links.is_empty() {
+            println!("{} links ignored because of link-check comments", ignored_links.values().map(Vec::len).sum::<usize>());
+        }
+        if validated > 0 {
+            println!("{} mailto:, tel: and data: links validated (not fetched)", validated);
+        }
+        if pruned > 0 {
+            println!("{} working urls which aren't in the files anymore pruned from the results", pruned);
+        }
+        if rechecked > 0 {
+            println!("{} working urls checked again, as it's been about {} days", rechecked, checker.opt.recheck_after);
+        }
+        if commented_out > 0 {
+            println!("{} links skipped because they are commented out", commented_out);
+        }
+        // A run over some sections shouldn't look like one over everything
+        if !scope.is_everything() {
+            if !checker.opt.section.is_empty() {
+                println!("Only checked sections: {}", selected_sections.into_iter().collect::<Vec<_>>().join(", "));
+            }
+            if !skipped_sections.is_empty() {
+                println!("Skipped sections: {}", skipped_sections.into_iter().collect::<Vec<_>>().join(", "));
+            }
+            println!("{} links outside the checked sections", out_of_scope);
+        }
+        if let Some(base) = &checker.opt.diff_base {
+            println!("Only checked links added or changed since {}, not the {} unchanged ones", base, unchanged);
+        }
+        if checker.github_token.is_some() {
+            match *checker.github_rate_limit.lock().unwrap() {
+                Some((remaining, limit)) => println!("GitHub rate limit: {} of {} requests remaining", remaining, limit),
+                None => println!("GitHub rate limit: not reported"),
+            }
+        }
+        for (url, chain) in &results.redirected {
+            println!("works, but redirects {}", redirect_chain(url, chain));
+        }
+        for (url, message) in &results.unavailable {
+            println!("temporarily unavailable, {}: {}", message, url);
+        }
+        for (url, warnings) in &results.warnings {
+            for warning in warnings {
+                println!("works, but {}: {}", warning, url);
+            }
+        }
+        // Parked domains are the worst kind of dead link, they lead readers to ads and worse
+        let (parked, suspect): (Vec<_>, Vec<_>) = results.suspect.iter().partition(|(_, reason)| reason.starts_with(PARKED));
+        if !parked.is_empty() {
+            println!("{} links lead to parked domains and should be removed:", parked.len());
+            for (url, reason) in parked {
+                println!("  {}: {}", reason, url);
+            }
+        }
+        for (url, reason) in suspect {
+            println!("suspect, {}: {}", reason, url);
+        }
+        // Readers click through the list without looking closely at every host
+        for (url, reason) in &results.homographs {
+            println!("possible look-alike host, {}: {}", reason, url);
+        }
+        for (url, reason) in &results.unverifiable {
+            println!("can't verify, {}: {}", reason, url);
+        }
+        if !results.access_restricted.is_empty() {
+            println!("{} links need a login:", results.access_restricted.len());
+            for (url, login) in &results.access_restricted {
+                println!("  {} -> {}", url, login);
+            }
+        }
+        for (url, moved) in &results.moved_to_https {
+            println!("works, but only at {}: {}", moved, url);
+        }
+        for (project, locations) in &results.duplicates {
+            println!("{} is linked from several entries: {}", project, locations.join(", "));
+        }
+        for (label, definition) in &results.unused_references {
+            println!("reference {} is never used: {}", label, definition);
+        }
+        for (url, anchor) in &results.missing_anchors {
+            println!("works, but the page has no #{}: {}", anchor, url);
+        }
+        for (url, repository) in &results.stale_workflows {
+            println!("workflow was renamed or removed, {} is still there: {}", repository, url);
+        }
+        if !results.suggestions.is_empty() || !results.moved_to_https.is_empty() {
+            let upgrades: BTreeMap<_, _> = results.suggestions.iter().chain(&results.moved_to_https).collect();
+            println!("{} links have a better url, to update them all at once:", upgrades.len());
+            println!("sed -i \\");
+            for (url, upgrade) in upgrades {
+                // sed has to find the urls the way they're written
+                let written = spellings.get(url).map_or_else(|| vec![url], |written| written.iter().collect());
+                for written in written {
+                    println!("  -e 's|{}|{}|g' \\", sed_escape(written), sed_escape(upgrade));
+                }
+            }
+            println!("  {}", files.iter().map(|file| file.display().to_string()).collect::<Vec<_>>().join(" "));
+        }
+        if checker.opt.print_toc {
+            for (readme, toc) in &tocs {
+                println!("Table of contents for {}:", readme);
+                print!("{}", toc);
+            }
+        }
+        // Not broken links, so they only fail the run with --strict-lint
+        if !lints.is_empty() {
+            println!("Lint, {} problems with the entries:", lints.len());
+            for lint in &lints {
+                println!("  {}", lint);
+            }
+        }
+    }
+    if deadline_reached {
+        if text {
+            print_failures(&results, checker.opt.sort_by);
+            println!("Deadline reached, {} urls not checked", results.unchecked.len());
+        }
+        // Don't let a partial run pass as a full one
+        std::process::exit(2);
+    }
+    if results.failed.is_empty() {
+        if checker.opt.strict_lint && !lints.is_empty() {
+            return Err(format_err!("{} problems with the entries", lints.len()));
+        }
+        if text {
+            println!("No errors!");
+        }
+        Ok(())
+    } else {
+        if text {
+            print_failures(&results, checker.opt.sort_by);
+        }
+        Err(format_err!("{} urls with errors ({} permanent): {}", results.failed.len(), permanent, breakdown(&results.failed)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn retry_after_seconds() {
+        let now = time::SystemTime::now();
+        assert_eq!(parse_retry_after("120", now), Some(time::Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 0 ", now), Some(time::Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn retry_after_http_date() {
+        let now = httpdate::parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT").unwrap();
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:30:00 GMT", now), Some(time::Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now), Some(time::Duration::from_secs(0)));
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    /// Serves http on a random local port, answering every request with whatever `handler`
+    /// returns for the raw request head. Returns the base url of the server.
+    fn serve<F>(handler: F) -> String where F: Fn(&str) -> String + Send + 'static {
+        use std::io::{BufRead, BufReader};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = String::new();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                while !request.ends_with("\r\n\r\n") && reader.read_line(&mut request).unwrap_or(0) > 0 {}
+                let _ = stream.write_all(handler(&request).as_bytes());
+            }
+        });
+        base
+    }
+
+    /// Like `serve`, but over https with a self-signed certificate for localhost
+    fn serve_tls<F>(handler: F) -> String where F: Fn(&str) -> String + Send + 'static {
+        serve_tls_and_plain(handler, None::<fn(&str) -> String>)
+    }
+
+    /// Like `serve_tls`, but plain http requests on the same port go to `plain`. Without it,
+    /// they're reset unanswered, like on a site which stopped listening on port 80.
+    fn serve_tls_and_plain<F, P>(handler: F, plain: Option<P>) -> String
+            where F: Fn(&str) -> String + Send + 'static, P: Fn(&str) -> String + Send + 'static {
+        use std::io::{BufRead, BufReader};
+        let certs = rustls_pemfile::certs(&mut &include_bytes!("../testdata/localhost.crt")[..]).unwrap();
+        let key = rustls_pemfile::pkcs8_private_keys(&mut &include_bytes!("../testdata/localhost.key")[..]).unwrap().remove(0);
+        let config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(certs.into_iter().map(rustls::Certificate).collect(), rustls::PrivateKey(key))
+            .unwrap();
+        let config = Arc::new(config);
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("https://localhost:{}", listener.local_addr().unwrap().port());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                // A TLS handshake starts with 0x16, a plain request with the method
+                let mut first = [0];
+                if stream.peek(&mut first).unwrap_or(0) == 0 {
+                    continue;
+                }
+                if first[0] != 0x16 {
+                    if let Some(ref plain) = plain {
+                        let mut request = String::new();
+                        let mut reader = BufReader::new(stream.try_clone().unwrap());
+                        while !request.ends_with("\r\n\r\n") && reader.read_line(&mut request).unwrap_or(0) > 0 {}
+                        let _ = stream.write_all(plain(&request).as_bytes());
+                    }
+                    // Otherwise, closing it with the request unread resets the connection
+                    continue;
+                }
+                let mut session = rustls::ServerConnection::new(config.clone()).unwrap();
+                let mut tls = rustls::Stream::new(&mut session, &mut stream);
+                let mut request = String::new();
+                let mut reader = BufReader::new(&mut tls);
+                // A client rejecting the certificate hangs up during the handshake
+                while !request.ends_with("\r\n\r\n") && reader.read_line(&mut request).unwrap_or(0) > 0 {}
+                if request.is_empty() {
+                    continue;
+                }
+                let _ = tls.write_all(handler(&request).as_bytes());
+                let _ = tls.flush();
+            }
+        });
+        base
+    }
+
+    fn response(status: &str, headers: &[&str], body: &str) -> String {
+        let mut response = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n", status, body.len());
+        for header in headers {
+            response += &format!("{}\r\n", header);
+        }
+        response + "\r\n" + body
+    }
+
+    fn checker(args: &[&str]) -> Arc<Checker> {
+        let args = std::iter::once("awesome-rust").chain(args.iter().cloned());
+        Arc::new(Checker::new(Opt::from_iter(args)).unwrap())
+    }
+
+    #[test]
+    fn per_domain_user_agent() {
+        let mut checker = Checker::new(Opt::from_iter(&["awesome-rust", "--user-agent", "global"])).unwrap();
+        checker.config = serde_yaml::from_str(r#"
+user_agents:
+  "*.example.com": "browser"
+"#).unwrap();
+        assert_eq!(checker.user_agent("https://www.example.com/page"), "browser");
+        assert_eq!(checker.user_agent("https://sciter.com/"), "global");
+        assert_eq!(Checker::new(Opt::from_iter(&["awesome-rust"])).unwrap().user_agent("https://sciter.com/"), "curl/7.54.0");
+    }
+
+    #[test]
+    fn permanent_errors() {
+        let not_found = CheckerError::HttpError {status: StatusCode::NOT_FOUND, chain: vec![]};
+        let forbidden = CheckerError::HttpError {status: StatusCode::FORBIDDEN, chain: vec![]};
+        let dns = CheckerError::DnsError {host: "example.invalid".to_string()};
+        let defaults = retry::RetryPolicy::new(&BTreeMap::new()).unwrap();
+        assert!(not_found.is_permanent(&defaults));
+        assert!(!forbidden.is_permanent(&defaults));
+        assert!(dns.is_permanent(&defaults));
+        assert!(!CheckerError::ConnectionRefused.is_permanent(&defaults));
+        assert!(CheckerError::RedirectLoop {chain: vec![]}.is_permanent(&defaults));
+        // Whatever the config retries isn't permanent, and whatever it doesn't is
+        let retries = retry::RetryPolicy::new(&serde_yaml::from_str(r#"
+"404": {attempts: 3}
+"403": {attempts: 1}
+dns: {attempts: 2}
+connection_refused: {attempts: 1}
+"#).unwrap()).unwrap();
+        assert!(!not_found.is_permanent(&retries));
+        assert!(forbidden.is_permanent(&retries));
+        assert!(!dns.is_permanent(&retries));
+        assert!(CheckerError::ConnectionRefused.is_permanent(&retries));
+    }
+
+    #[test]
+    fn interleaved_hosts() {
+        let urls = vec![
+            "https://a.example/1", "https://a.example/2", "https://a.example/3",
+            "https://b.example/1", "https://c.example/1", "https://b.example/2",
+        ].into_iter().map(str::to_string).collect();
+        // One url of each host in turn, each host's urls in the order they came in
+        assert_eq!(interleave_by_host(urls), vec![
+            "https://a.example/1", "https://b.example/1", "https://c.example/1",
+            "https://a.example/2", "https://b.example/2",
+            "https://a.example/3",
+        ]);
+        assert!(interleave_by_host(vec![]).is_empty());
+    }
+
+    #[test]
+    fn trivial_redirects() {
+        let trivial = [
+            ("https://example.com/docs", "https://example.com/docs/"),
+            ("https://example.com/", "https://www.example.com/"),
+            ("http://example.com/", "https://example.com/"),
+            ("http://example.com/a?b", "https://www.example.com/a/?b"),
+        ];
+        for (from, to) in &trivial {
+            assert!(trivial_redirect(from, to), "{} -> {}", from, to);
+        }
+        let significant = [
+            ("https://example.com/", "https://example.org/"),
+            ("https://example.com/", "https://docs.example.com/"),
+            ("https://example.com/docs", "https://example.com/documentation"),
+            ("https://example.com/?a", "https://example.com/?b"),
+            ("https://example.com/", "http://example.com/"),
+            ("https://example.com/", "https://example.com:8443/"),
+            ("https://example.com/", "/relative"),
+        ];
+        for (from, to) in &significant {
+            assert!(!trivial_redirect(from, to), "{} -> {}", from, to);
+        }
+    }
+
+    #[test]
+    fn sed_escaping() {
+        assert_eq!(sed_escape("http://example.com/a|b&c"), r"http://example\.com/a\|b\&c");
+        assert_eq!(sed_escape("http://example.com/it's"), r"http://example\.com/it'\''s");
+    }
+
+    #[test]
+    fn failures_by_category() {
+        let failure = |kind, status| Failure {status, ..Failure::new(kind, String::new())};
+        let mut failed = BTreeMap::new();
+        failed.insert("https://b.example/1".to_string(), failure(failures::Kind::Http, Some(410)));
+        failed.insert("https://a.example/1".to_string(), failure(failures::Kind::Timeout, None));
+        failed.insert("https://b.example/0".to_string(), failure(failures::Kind::Http, Some(404)));
+        failed.insert("https://a.example/2".to_string(), failure(failures::Kind::Http, Some(403)));
+        failed.insert("https://c.example/".to_string(), failure(failures::Kind::Http, Some(502)));
+        failed.insert("https://d.example/".to_string(), failure(failures::Kind::Dns, None));
+        failed.insert("README.md#usage".to_string(), failure(failures::Kind::NoSuchHeading, None));
+        type Groups = Vec<(String, Vec<(String, Vec<String>)>)>;
+        fn order(failed: &BTreeMap<String, Failure>, sort_by: SortBy) -> Groups {
+            by_category(failed, sort_by).into_iter()
+                .map(|(category, groups)| {
+                    let groups = groups.into_iter()
+                        .map(|(host, failures)| (host, failures.into_iter().map(|(key, _)| key.clone()).collect()))
+                        .collect();
+                    (category.to_string(), groups)
+                })
+                .collect()
+        }
+        let group = |host: &str, keys: &[&str]| (host.to_string(), keys.iter().map(|key| key.to_string()).collect::<Vec<_>>());
+        assert_eq!(order(&failed, SortBy::Domain), vec![
+            ("4xx".to_string(), vec![group("a.example", &["https://a.example/2"]), group("b.example", &["https://b.example/0", "https://b.example/1"])]),
+            ("DNS".to_string(), vec![group("d.example", &["https://d.example/"])]),
+            ("links within the repository".to_string(), vec![group("", &["README.md#usage"])]),
+            ("5xx".to_string(), vec![group("c.example", &["https://c.example/"])]),
+            ("timeouts".to_string(), vec![group("a.example", &["https://a.example/1"])]),
+        ]);
+        assert_eq!(order(&failed, SortBy::Url)[0], ("4xx".to_string(), vec![group("", &["https://a.example/2", "https://b.example/0", "https://b.example/1"])]));
+        assert_eq!(order(&failed, SortBy::Status)[0], ("4xx".to_string(), vec![group("", &["https://a.example/2", "https://b.example/0", "https://b.example/1"])]));
+        failed.get_mut("https://b.example/0").unwrap().status = Some(451);
+        assert_eq!(order(&failed, SortBy::Status)[0], ("4xx".to_string(), vec![group("", &["https://a.example/2", "https://b.example/1", "https://b.example/0"])]));
+        failed.get_mut("https://a.example/2").unwrap().sections = vec!["Applications > Emulators".to_string()];
+        failed.get_mut("https://b.example/1").unwrap().sections = vec!["Applications > Emulators".to_string(), "Libraries".to_string()];
+        assert_eq!(order(&failed, SortBy::Section)[0], ("4xx".to_string(), vec![
+            group("", &["https://b.example/0"]),
+            group("Applications > Emulators", &["https://a.example/2", "https://b.example/1"]),
+            group("Libraries", &["https://b.example/1"]),
+        ]));
+        assert_eq!(breakdown(&failed), "3 4xx, 1 DNS, 1 links within the repository, 1 5xx, 1 timeouts");
+        assert_eq!(in_sections("[404] https://b.example".to_string(), &["Applications > Emulators".to_string(), "Libraries".to_string()]),
+                   "[404] https://b.example in Applications > Emulators; Libraries");
+    }
+
+    #[test]
+    fn partial_results_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("results.yaml");
+        assert!(Results::load(Some(&path), false).unwrap().working.is_empty());
+        let mut results = Results::new();
+        results.working.insert("https://example.com/".to_string());
+        results.save(Some(&path)).unwrap();
+        assert_eq!(Results::load(Some(&path), false).unwrap().working, results.working);
+        assert!(!dir.path().join("results.yaml.tmp").exists());
+        // As if we were killed halfway through writing it
+        let written = fs::read_to_string(&path).unwrap();
+        fs::write(&path, &written[..written.find("https").unwrap() + 5]).unwrap();
+        let error = Results::load(Some(&path), false).unwrap_err().to_string();
+        assert!(error.starts_with(&format!("Can't parse {}", path.display())), "{}", error);
+        assert!(Results::load(Some(&path), true).unwrap().working.is_empty());
+    }
+
+    #[test]
+    fn results_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut results = Results::new();
+        results.working.insert("https://example.com/".to_string());
+        let json = dir.path().join("results.json");
+        results.save(Some(&json)).unwrap();
+        assert!(fs::read_to_string(&json).unwrap().starts_with('{'));
+        // Either format loads, whatever the file is called
+        let yaml = dir.path().join("results.yaml");
+        fs::rename(&json, &yaml).unwrap();
+        assert_eq!(Results::load(Some(&yaml), false).unwrap().working, results.working);
+        results.save(Some(&yaml)).unwrap();
+        fs::rename(&yaml, &json).unwrap();
+        assert_eq!(Results::load(Some(&json), false).unwrap().working, results.working);
+    }
+
+    #[test]
+    fn rechecks() {
+        let day = 24 * 60 * 60;
+        let max_age = time::Duration::from_secs(30 * day);
+        let now = 1_600_000_000;
+        let mut results = Results::new();
+        let urls: Vec<_> = (0..100).map(|i| format!("https://example.com/{}", i)).collect();
+        results.working.extend(urls.iter().cloned());
+        results.last_checked.insert(urls[0].clone(), now - day);
+        results.last_checked.insert(urls[1].clone(), now - 40 * day);
+        results.backdate_unknown(max_age, now);
+        assert!(!results.due_for_recheck(&urls[0], max_age, now));
+        assert!(results.due_for_recheck(&urls[1], max_age, now));
+        // The ones from before we kept track come due over the next month, not all at once
+        let due_within = |days: u64| urls[2..].iter().filter(|url| results.due_for_recheck(url, max_age, now + days * day)).count();
+        assert!(due_within(0) < 20, "{}", due_within(0));
+        assert!(due_within(14) > due_within(0) && due_within(14) < 98);
+        assert_eq!(due_within(30), 98);
+    }
+
+    #[test]
+    fn prune() {
+        let mut results = Results::new();
+        results.working.extend(["https://kept.example/", "https://gone.example/"].iter().map(|url| url.to_string()));
+        results.redirected.insert("https://gone.example/".to_string(), vec!["https://gone.example/new".to_string()]);
+        let mut spellings: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        // The files spell it differently, but it's the same url
+        for written in &["HTTPS://Kept.example:443", "https://other.example/"] {
+            spellings.entry(urls::normalize(written)).or_default().insert(written.to_string());
+        }
+        assert_eq!(results.prune(&spellings), 1);
+        assert_eq!(results.working, vec!["https://kept.example/".to_string()].into_iter().collect());
+        assert!(results.redirected.is_empty());
+        assert_eq!(results.prune(&spellings), 0);
+    }
+
+    #[tokio::test]
+    async fn consent_cookie_redirect() {
+        let base = serve(|request| {
+            let base = request.lines()
+                .find_map(|line| line.strip_prefix("Host: ").or_else(|| line.strip_prefix("host: ")))
+                .map(|host| format!("http://{}", host))
+                .unwrap();
+            if request.starts_with("GET /consent") || request.starts_with("HEAD /consent") {
+                response("302 Found", &["Set-Cookie: consent=yes; Path=/", &format!("Location: {}/page", base)], "")
+            } else if request.contains("consent=yes") {
+                response("200 OK", &[], "content")
+            } else {
+                response("302 Found", &[&format!("Location: {}/consent", base)], "")
+            }
+        });
+        let (_url, res) = get_url(checker(&["--host-delay", "0"]), format!("{}/page", base), false).await;
+        let checked = res.unwrap();
+        assert_eq!(checked.chain, vec![format!("{}/consent", base), format!("{}/page", base)]);
+    }
+
+    #[test]
+    fn meta_refresh_targets() {
+        let page = |content: &str| format!(r#"<html><head><meta http-equiv="Refresh" content="{}"></head></html>"#, content);
+        let url = "https://example.com/old/";
+        assert_eq!(meta_refresh(url, page("0; url=https://example.org/").as_bytes()), Some("https://example.org/".to_string()));
+        assert_eq!(meta_refresh(url, page("5;URL='../new/'").as_bytes()), Some("https://example.com/new/".to_string()));
+        assert_eq!(meta_refresh(url, page("0, new.html").as_bytes()), Some("https://example.com/old/new.html".to_string()));
+        assert_eq!(meta_refresh(url, page("300").as_bytes()), None);
+        assert_eq!(meta_refresh(url, page("0; url=https://example.com/old/").as_bytes()), None);
+        assert_eq!(meta_refresh(url, b"<html><body>content</body></html>"), None);
+    }
+
+    #[test]
+    fn relative_locations() {
+        let base = "https://example.com/docs/old/page?query";
+        assert_eq!(resolve(base, "https://example.org/new").unwrap(), "https://example.org/new");
+        assert_eq!(resolve(base, "//example.org/new").unwrap(), "https://example.org/new");
+        assert_eq!(resolve(base, "/new/path").unwrap(), "https://example.com/new/path");
+        assert_eq!(resolve(base, "new/path").unwrap(), "https://example.com/docs/old/new/path");
+        assert_eq!(resolve(base, "../new").unwrap(), "https://example.com/docs/new");
+        assert_eq!(resolve(base, "?other").unwrap(), "https://example.com/docs/old/page?other");
+        assert_eq!(resolve(base, "http://[::1"), None);
+    }
+
+    #[tokio::test]
+    async fn follows_meta_refresh() {
+        let base = serve(|request| {
+            if request.contains(" /old ") {
+                response("200 OK", &["Content-Type: text/html"], r#"<meta http-equiv="refresh" content="0; url=/new">"#)
+            } else {
+                response("200 OK", &["Content-Type: text/html"], "content")
+            }
+        });
+        // Not worth a GET for every page that answered the HEAD
+        let (_url, res) = get_url(checker(&["--host-delay", "0"]), format!("{}/old", base), false).await;
+        assert!(res.unwrap().chain.is_empty());
+        let (_url, res) = get_url(checker(&["--host-delay", "0", "--meta-refresh"]), format!("{}/old", base), false).await;
+        let checked = res.unwrap();
+        assert_eq!(checked.chain, vec![format!("{}/new", base)]);
+        assert_eq!(checked.success.final_url, format!("{}/new", base));
+        assert_eq!(checked.success.status, 200);
+        assert_eq!(checked.success.http_version, "HTTP/1.1");
+        assert_eq!(checked.success.content_type.as_deref(), Some("text/html"));
+    }
+
+    #[tokio::test]
+    async fn fragments() {
+        let base = serve(|_| response("200 OK", &["Content-Type: text/html"], r#"<h2 id="user-content-usage">Usage</h2>"#));
+        let (_url, res) = get_url(checker(&["--host-delay", "0"]), format!("{}/#usage", base), false).await;
+        assert_eq!(res.unwrap().missing_anchor, None);
+        let (_url, res) = get_url(checker(&["--host-delay", "0"]), format!("{}/#installation", base), false).await;
+        assert_eq!(res.unwrap().missing_anchor.as_deref(), Some("installation"));
+        match get_url(checker(&["--host-delay", "0", "--strict-anchors"]), format!("{}/#installation", base), false).await.1 {
+            Err(CheckerError::MissingAnchor {anchor}) => assert_eq!(anchor, "installation"),
+            other => panic!("expected missing anchor, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn mangled_urls() {
+        let base = serve(|request| {
+            if request.contains("/wiki/Rust_(programming_language) ") || request.contains("/wiki/Rust_%28programming_language%29 ")
+                    || request.contains("/docs ") {
+                response("200 OK", &[], "")
+            } else {
+                response("404 Not Found", &[], "")
+            }
+        });
+        match get_url(checker(&["--host-delay", "0"]), format!("{}/wiki/Rust_(programming_language", base), false).await.1 {
+            Err(CheckerError::MangledUrl {repaired}) => assert_eq!(repaired, format!("{}/wiki/Rust_(programming_language)", base)),
+            other => panic!("expected mangled url, got {:?}", other),
+        }
+        let (_url, res) = get_url(checker(&["--host-delay", "0"]), format!("{}/wiki/Rust_%28programming_language%29", base), false).await;
+        assert!(res.is_ok());
+        match get_url(checker(&["--host-delay", "0"]), format!("{}/docs.", base), false).await.1 {
+            Err(CheckerError::MangledUrl {repaired}) => assert_eq!(repaired, format!("{}/docs", base)),
+            other => panic!("expected mangled url, got {:?}", other),
+        }
+        match get_url(checker(&["--host-delay", "0"]), format!("{}/wiki/Go_(programming_language", base), false).await.1 {
+            Err(CheckerError::HttpError {status: StatusCode::NOT_FOUND, ..}) => {}
+            other => panic!("expected 404, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn bot_challenges() {
+        let base = serve(|request| {
+            if request.contains(" /header ") {
+                response("403 Forbidden", &["cf-mitigated: challenge"], "")
+            } else if request.contains(" /page ") {
+                response("503 Service Unavailable", &["Content-Type: text/html"],
+                    r#"<title>Just a moment...</title><form id="challenge-form" action="/cdn-cgi/challenge-platform/h/b">"#)
+            } else {
+                response("403 Forbidden", &[], "Forbidden")
+            }
+        });
+        for path in &["header", "page"] {
+            match get_url(checker(&["--host-delay", "0"]), format!("{}/{}", base, path), false).await.1 {
+                Err(CheckerError::Unverifiable {reason}) => assert_eq!(reason, "Cloudflare challenge"),
+                other => panic!("expected unverifiable, got {:?}", other),
+            }
+        }
+        match get_url(checker(&["--host-delay", "0"]), format!("{}/other", base), false).await.1 {
+            Err(CheckerError::HttpError {status: StatusCode::FORBIDDEN, ..}) => {}
+            other => panic!("expected 403, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn login_pages() {
+        let checker = checker(&[]);
+        assert!(checker.login_pages.is_match("https://twitter.com/i/flow/login?redirect_after_login=%2Frustlang"));
+        assert!(checker.login_pages.is_match("https://example.com/users/sign_in"));
+        assert!(checker.login_pages.is_match("https://example.com/Login.php?next=/"));
+        assert!(checker.login_pages.is_match("https://accounts.google.com/ServiceLogin?continue=x"));
+        assert!(!checker.login_pages.is_match("https://example.com/docs/login-flow"));
+        assert!(!checker.login_pages.is_match("https://twitter.com/rustlang"));
+    }
+
+    #[tokio::test]
+    async fn login_redirect() {
+        let base = serve(|request| {
+            if request.contains(" /members ") {
+                response("302 Found", &["Location: /login?next=/members"], "")
+            } else {
+                response("200 OK", &[], "log in")
+            }
+        });
+        match get_url(checker(&["--host-delay", "0"]), format!("{}/members", base), false).await.1 {
+            Err(CheckerError::AccessRestricted {login}) => assert_eq!(login, format!("{}/login?next=/members", base)),
+            other => panic!("expected access restricted, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn image_content_type() {
+        let base = serve(|request| {
+            if request.contains(" /logo.png ") {
+                response("200 OK", &["Content-Type: image/png"], "png")
+            } else if request.contains(" /badge.svg ") {
+                response("200 OK", &["Content-Type: text/xml"], "<svg></svg>")
+            } else {
+                response("200 OK", &["Content-Type: text/html"], "<h1>Not here anymore</h1>")
+            }
+        });
+        let checker = checker(&["--host-delay", "0"]);
+        let urls: Vec<_> = ["logo.png", "badge.svg", "moved.png", "page"].iter().map(|path| format!("{}/{}", base, path)).collect();
+        assert!(get_url(checker.clone(), urls[0].clone(), true).await.1.is_ok());
+        assert!(get_url(checker.clone(), urls[1].clone(), true).await.1.is_ok());
+        match get_url(checker.clone(), urls[2].clone(), true).await.1 {
+            Err(CheckerError::WrongContentType {got, ..}) => assert_eq!(got, "text/html"),
+            other => panic!("expected wrong content type, got {:?}", other),
+        }
+        // Only links, so any content type is fine
+        assert!(get_url(checker.clone(), urls[3].clone(), false).await.1.is_ok());
+    }
+
+    /// Serves `status` for the first `failures` GET requests to a path and 200 after that, and
+    /// counts the GETs. HEAD requests get a 405, so that every attempt ends up as a GET.
+    fn flaky(status: &'static str, headers: Vec<String>, failures: usize) -> (String, Arc<AtomicU64>) {
+        let count = Arc::new(AtomicU64::new(0));
+        let counter = count.clone();
+        let base = serve(move |request| {
+            if !request.starts_with("GET") {
+                return response("405 Method Not Allowed", &[], "");
+            }
+            if counter.fetch_add(1, Ordering::SeqCst) < failures as u64 {
+                response(status, &headers.iter().map(String::as_str).collect::<Vec<_>>(), "")
+            } else {
+                response("200 OK", &[], "content")
+            }
+        });
+        (base, count)
+    }
+
+    #[tokio::test]
+    async fn retry_policy() {
+        let mut checker = Checker::new(Opt::from_iter(&["awesome-rust", "--host-delay", "0"])).unwrap();
+        checker.retries = retry::RetryPolicy::new(&serde_yaml::from_str(r#""5xx": {attempts: 5}"#).unwrap()).unwrap();
+        let checker = Arc::new(checker);
+
+        let (base, count) = flaky("404 Not Found", vec![], usize::MAX);
+        assert!(get_url(checker.clone(), format!("{}/", base), false).await.1.is_err());
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        let (base, count) = flaky("500 Internal Server Error", vec![], 3);
+        assert!(get_url(checker.clone(), format!("{}/", base), false).await.1.is_ok());
+        assert_eq!(count.load(Ordering::SeqCst), 4);
+
+        let (base, count) = flaky("429 Too Many Requests", vec!["Retry-After: 1".to_string()], 1);
+        let start = time::Instant::now();
+        assert!(get_url(checker.clone(), format!("{}/", base), false).await.1.is_ok());
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+        assert!(start.elapsed() >= time::Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn not_acceptable() {
+        let base = serve(|request| {
+            if request.to_ascii_lowercase().contains("accept: text/html") {
+                response("406 Not Acceptable", &[], "")
+            } else {
+                response("200 OK", &[], "content")
+            }
+        });
+        let (_url, res) = get_url(checker(&["--host-delay", "0"]), format!("{}/", base), false).await;
+        assert_eq!(res.unwrap().fallbacks.accept, Accept::Any);
+
+        let mut checker = Checker::new(Opt::from_iter(&["awesome-rust", "--host-delay", "0"])).unwrap();
+        checker.retries = retry::RetryPolicy::new(&serde_yaml::from_str(r#""406": {attempts: 1}"#).unwrap()).unwrap();
+        match get_url(Arc::new(checker), format!("{}/", base), false).await.1 {
+            Err(CheckerError::HttpError {status: StatusCode::NOT_ACCEPTABLE, ..}) => {}
+            other => panic!("expected 406, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn rate_limit_pause() {
+        let reset = time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap().as_secs() + 2;
+        let headers = vec!["x-ratelimit-remaining: 0".to_string(), format!("x-ratelimit-reset: {}", reset)];
+        let (base, count) = flaky("429 Too Many Requests", headers.clone(), 1);
+        let checker = checker(&["--host-delay", "0", "--no-browser-retry"]);
+        let start = time::Instant::now();
+        assert!(get_url(checker.clone(), format!("{}/", base), false).await.1.is_ok());
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+        assert!(start.elapsed() >= time::Duration::from_secs(1), "took {:?}", start.elapsed());
+        // Only GitHub's 403s are about its rate limit
+        let (base, count) = flaky("403 Forbidden", headers, 1);
+        let start = time::Instant::now();
+        assert!(get_url(checker.clone(), format!("{}/", base), false).await.1.is_ok());
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+        assert!(start.elapsed() < time::Duration::from_secs(1), "took {:?}", start.elapsed());
+    }
+
+    #[tokio::test]
+    async fn accepted_statuses() {
+        let base = serve(|_| response("999 Request denied", &[], ""));
+        let mut checker = Checker::new(Opt::from_iter(&["awesome-rust", "--host-delay", "0"])).unwrap();
+        checker.config = serde_yaml::from_str(&format!(r#"
+accepted_statuses:
+  "{}/in/*": [999]
+"#, base)).unwrap();
+        let checker = Arc::new(checker);
+        let (_url, res) = get_url(checker.clone(), format!("{}/in/rustlang", base), false).await;
+        assert_eq!(res.unwrap().accepted, Some(StatusCode::from_u16(999).unwrap()));
+        match get_url(checker.clone(), format!("{}/company/rust", base), false).await.1 {
+            Err(CheckerError::HttpError {status, ..}) => assert_eq!(status.as_u16(), 999),
+            other => panic!("expected http error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn body_size_is_capped() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            use std::io::{BufRead, BufReader};
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            stream.write_all(b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n").unwrap();
+            // Keep sending until the client hangs up
+            while stream.write_all(&[b'x'; 4096]).is_ok() {}
+        });
+        let checker = checker(&["--max-body-size", "100000"]);
+        let resp = checker.client.get(&url).send().await.unwrap();
+        let body = read_body(&checker, resp, usize::MAX).await.unwrap();
+        assert_eq!(body.len(), 100_000);
+    }
+
+    #[tokio::test]
+    async fn handles_wake_waiters() {
+        let handles = MaxHandles::new(2);
+        let start = time::Instant::now();
+        let checks = (0..10).map(|_| async {
+            let _handle = handles.get().await;
+            task::sleep(time::Duration::from_millis(10)).await;
+        });
+        futures::future::join_all(checks).await;
+        // 5 rounds of 10ms each, polling would take at least 500ms per round
+        assert!(start.elapsed() < time::Duration::from_millis(400), "took {:?}", start.elapsed());
+        assert_eq!(handles.remaining.available_permits(), 2);
+    }
+
+    #[tokio::test]
+    async fn dns_error() {
+        let (_url, res) = get_url(checker(&[]), "https://example.invalid/".to_string(), false).await;
+        match res {
+            Err(CheckerError::DnsError {host}) => assert_eq!(host, "example.invalid"),
+            other => panic!("expected dns error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn connection_refused() {
+        // Grab a free port and close it again, so nothing is listening there
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let (_url, res) = get_url(checker(&["--host-delay", "0"]), format!("http://127.0.0.1:{}/", port), false).await;
+        match res {
+            Err(CheckerError::ConnectionRefused) => {}
+            other => panic!("expected connection refused, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_certificate() {
+        let base = serve_tls(|_| response("200 OK", &[], "content"));
+        let (_url, res) = get_url(checker(&["--host-delay", "0"]), format!("{}/", base), false).await;
+        match res {
+            Err(CheckerError::TlsError {reason}) => assert!(reason.starts_with("invalid peer certificate"), "{}", reason),
+            other => panic!("expected tls error, got {:?}", other),
+        }
+
+        let (_url, res) = get_url(checker(&["--host-delay", "0", "--insecure"]), format!("{}/", base), false).await;
+        assert_eq!(res.unwrap().fallbacks.insecure, None);
+    }
+
+    #[tokio::test]
+    async fn certificate_expiry() {
+        // The test certificate is good for a hundred years
+        let base = serve_tls(|_| response("200 OK", &[], "content"));
+        let url = format!("{}/", base);
+        let soon = checker(&["--host-delay", "0", "--insecure"]);
+        let later = checker(&["--host-delay", "0", "--insecure", "--cert-expiry-days", "40000"]);
+        let unchecked = checker(&["--host-delay", "0", "--insecure", "--cert-expiry-days", "40000", "--no-cert-expiry-check"]);
+        for checker in &[&soon, &later, &unchecked] {
+            get_url((*checker).clone(), url.clone(), false).await.1.unwrap();
+        }
+        assert_eq!(soon.expires_soon(&url), None);
+        assert!(later.expires_soon(&url).is_some());
+        assert_eq!(unchecked.expires_soon(&url), None);
+    }
+
+    #[tokio::test]
+    async fn tolerated_invalid_certificate() {
+        let base = serve_tls(|_| response("200 OK", &[], "content"));
+        let mut checker = Checker::new(Opt::from_iter(&["awesome-rust", "--host-delay", "0"])).unwrap();
+        checker.config = serde_yaml::from_str(r#"
+insecure_hosts: ["localhost"]
+"#).unwrap();
+        let (_url, res) = get_url(Arc::new(checker), format!("{}/", base), false).await;
+        let insecure = res.unwrap().fallbacks.insecure.unwrap();
+        assert!(insecure.starts_with("invalid peer certificate"), "{}", insecure);
+    }
+
+    #[tokio::test]
+    async fn circuit_breaker() {
+        // Closes the connection without answering while it's down
+        let down = Arc::new(AtomicBool::new(true));
+        let count = Arc::new(AtomicU64::new(0));
+        let (server_down, counter) = (down.clone(), count.clone());
+        let base = serve(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            if server_down.load(Ordering::SeqCst) { String::new() } else { response("200 OK", &[], "content") }
+        });
+        let checker = checker(&["--host-delay", "0", "--max-host-failures", "2"]);
+
+        // A url which works in between starts the count over
+        assert!(get_url(checker.clone(), format!("{}/a", base), false).await.1.is_err());
+        down.store(false, Ordering::SeqCst);
+        assert!(get_url(checker.clone(), format!("{}/b", base), false).await.1.is_ok());
+        down.store(true, Ordering::SeqCst);
+        let (_url, res) = get_url(checker.clone(), format!("{}/c", base), false).await;
+        assert!(res.unwrap_err().is_host_failure());
+        assert!(!checker.host(&base).tripped(&checker.opt));
+
+        // Two in a row, and the rest of the host is skipped without asking it
+        assert!(get_url(checker.clone(), format!("{}/d", base), false).await.1.is_err());
+        assert!(checker.host(&base).tripped(&checker.opt));
+        let requests = count.load(Ordering::SeqCst);
+        down.store(false, Ordering::SeqCst);
+        let (url, res) = get_url(checker.clone(), format!("{}/e", base), false).await;
+        match res {
+            Err(CheckerError::CircuitOpen {ref host}) => assert_eq!(host, "127.0.0.1"),
+            ref other => panic!("expected the circuit to be open, got {:?}", other),
+        }
+        assert_eq!(count.load(Ordering::SeqCst), requests);
+        let writer = writer::Writer::spawn(Results::new(), checker.clone(), BTreeMap::new(), BTreeMap::new(), None, None);
+        writer.send(url.clone(), res);
+        let recorder = writer.finish(vec![]).await.unwrap();
+        assert_eq!(recorder.results.failed[&url].kind, failures::Kind::HostUnreachable);
+
+        // Unless it's turned off
+        let checker = Arc::new(Checker::new(Opt::from_iter(&["awesome-rust", "--host-delay", "0", "--max-host-failures", "2", "--no-circuit-breaker"])).unwrap());
+        down.store(true, Ordering::SeqCst);
+        for path in &["f", "g"] {
+            assert!(get_url(checker.clone(), format!("{}/{}", base, path), false).await.1.is_err());
+        }
+        assert!(!checker.host(&base).tripped(&checker.opt));
+        down.store(false, Ordering::SeqCst);
+        assert!(get_url(checker.clone(), format!("{}/h", base), false).await.1.is_ok());
+    }
+
+    #[tokio::test]
+    async fn conditional_requests() {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let seen = requests.clone();
+        let base = serve(move |request| {
+            seen.lock().unwrap().push(request.to_ascii_lowercase());
+            if request.to_ascii_lowercase().contains("if-none-match: \"v1\"") {
+                response("304 Not Modified", &["ETag: \"v1\""], "")
+            } else {
+                response("200 OK", &["ETag: \"v1\"", "Last-Modified: Wed, 21 Oct 2015 07:28:00 GMT"], "content")
+            }
+        });
+        let url = format!("{}/", base);
+        let (_url, res) = get_url(checker(&["--host-delay", "0"]), url.clone(), false).await;
+        let validators = res.unwrap().validators;
+        assert_eq!(validators, Validators {etag: Some("\"v1\"".to_string()), last_modified: Some("Wed, 21 Oct 2015 07:28:00 GMT".to_string())});
+
+        // The next run asks whether it changed, and takes the 304 for working
+        let mut checker = Checker::new(Opt::from_iter(&["awesome-rust", "--host-delay", "0"])).unwrap();
+        checker.validators.insert(url.clone(), validators.clone());
+        let checker = Arc::new(checker);
+        requests.lock().unwrap().clear();
+        let (_url, res) = get_url(checker.clone(), url.clone(), false).await;
+        let request = requests.lock().unwrap()[0].clone();
+        assert!(request.contains("if-none-match: \"v1\"\r\n"), "{}", request);
+        assert!(request.contains("if-modified-since: wed, 21 oct 2015 07:28:00 gmt\r\n"), "{}", request);
+        let checked = res.unwrap();
+        assert_eq!(checked.success.status, 304);
+        // It didn't send them again, so they're still the ones from before
+        assert_eq!(checked.validators, validators);
+        let writer = writer::Writer::spawn(Results::new(), checker.clone(), BTreeMap::new(), BTreeMap::new(), None, None);
+        writer.send(url.clone(), Ok(checked));
+        let recorder = writer.finish(vec![]).await.unwrap();
+        assert!(recorder.results.working.contains(&url));
+        assert_eq!(recorder.results.validators[&url], validators);
+
+        let mut checker = Checker::new(Opt::from_iter(&["awesome-rust", "--host-delay", "0", "--unconditional"])).unwrap();
+        checker.validators.insert(url.clone(), validators);
+        requests.lock().unwrap().clear();
+        let (_url, res) = get_url(Arc::new(checker), url, false).await;
+        assert_eq!(res.unwrap().success.status, 200);
+        assert!(requests.lock().unwrap().iter().all(|request| !request.contains("if-none-match")));
+    }
+
+    #[tokio::test]
+    async fn browser_headers() {
+        let base = serve(|request| {
+            let request = request.to_ascii_lowercase();
+            if request.contains("user-agent: mozilla/5.0") && request.contains("accept: text/html") {
+                response("200 OK", &[], "content")
+            } else {
+                response("403 Forbidden", &[], "")
+            }
+        });
+        let url = format!("{}/", base);
+        let checker = checker(&["--host-delay", "0"]);
+        let (_url, res) = get_url(checker.clone(), url.clone(), false).await;
+        let checked = res.unwrap();
+        assert!(checked.fallbacks.browser);
+        let writer = writer::Writer::spawn(Results::new(), checker, BTreeMap::new(), BTreeMap::new(), None, None);
+        writer.send(url.clone(), Ok(checked));
+        let recorder = writer.finish(vec![]).await.unwrap();
+        assert!(recorder.results.needs_browser.contains(&url));
+
+        let (_url, res) = get_url(Arc::new(Checker::new(Opt::from_iter(&["awesome-rust", "--host-delay", "0", "--no-browser-retry"])).unwrap()), url, false).await;
+        match res {
+            Err(CheckerError::HttpError {status, ..}) => assert_eq!(status, StatusCode::FORBIDDEN),
+            other => panic!("expected a 403, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn moved_to_https() {
+        // Plain http doesn't connect, but https on the same port works
+        let base = serve_tls(|_| response("200 OK", &[], "content"));
+        let http = format!("http://{}/", &base["https://".len()..]);
+        let checker = checker(&["--host-delay", "0", "--insecure"]);
+        let (url, res) = get_url(checker.clone(), http.clone(), false).await;
+        assert_eq!(url, http);
+        let checked = res.unwrap();
+        assert_eq!(checked.moved, Some(format!("{}/", base)));
+        let writer = writer::Writer::spawn(Results::new(), checker, BTreeMap::new(), BTreeMap::new(), None, None);
+        writer.send(http.clone(), Ok(checked));
+        let recorder = writer.finish(vec![]).await.unwrap();
+        assert!(recorder.results.working.contains(&http));
+        assert_eq!(recorder.results.moved_to_https[&http], format!("{}/", base));
+    }
+
+    #[tokio::test]
+    async fn https_upgrades() {
+        let page = |title: &str| response("200 OK", &["Content-Type: text/html"], &format!("<title>{}</title>{}", title, " ".repeat(1000)));
+        let count = Arc::new(AtomicU64::new(0));
+        let counter = count.clone();
+        let base = serve_tls_and_plain(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            page("Project")
+        }, Some(move |request: &str| match request.split(' ').nth(1) {
+            Some("/gone") => page("Not Found"),
+            Some("/denied") => response("999 Request denied", &[], ""),
+            _ => page("Project"),
+        }));
+        let http = format!("http://{}", &base["https://".len()..]);
+        let mut checker = Checker::new(Opt::from_iter(&["awesome-rust", "--host-delay", "0", "--insecure", "--detect-soft-404"])).unwrap();
+        checker.config = serde_yaml::from_str(&format!("accepted_statuses:\n  \"{}/denied\": [999]", http)).unwrap();
+        let checker = Arc::new(checker);
+
+        let (_url, res) = get_url(checker.clone(), format!("{}/project", http), false).await;
+        assert_eq!(res.unwrap().upgrade, Some(format!("{}/project", base)));
+        let asked = count.load(Ordering::SeqCst);
+        assert!(asked > 0);
+
+        // Neither a page which might be gone nor one with an accepted error status is worth asking
+        let (_url, res) = get_url(checker.clone(), format!("{}/gone", http), false).await;
+        let checked = res.unwrap();
+        assert!(checked.suspect.is_some());
+        assert_eq!(checked.upgrade, None);
+        let (_url, res) = get_url(checker.clone(), format!("{}/denied", http), false).await;
+        let checked = res.unwrap();
+        assert!(checked.accepted.is_some());
+        assert_eq!(checked.upgrade, None);
+        assert_eq!(count.load(Ordering::SeqCst), asked);
+    }
+}
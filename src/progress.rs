@@ -0,0 +1,100 @@
+use std::collections::VecDeque;
+use std::io::{self, IsTerminal};
+use std::time;
+
+/// The ETA goes by how fast this many checks finished, so it follows the hosts we're on now
+/// rather than the whole run
+const WINDOW: usize = 50;
+
+const WIDTH: usize = 30;
+
+/// A line at the bottom of stderr with how far along the checks are
+pub struct Bar {
+    total: usize,
+    done: usize,
+    failed: usize,
+    /// When the last few checks finished
+    recent: VecDeque<time::Instant>,
+    drawn: bool,
+}
+
+impl Bar {
+    /// Only on a terminal, anywhere else it's a mess of carriage returns
+    pub fn new(total: usize, quiet: bool) -> Option<Bar> {
+        if quiet || !io::stderr().is_terminal() {
+            return None;
+        }
+        Some(Bar {total, done: 0, failed: 0, recent: VecDeque::new(), drawn: false})
+    }
+
+    pub fn finished(&mut self, failed: bool, now: time::Instant) {
+        self.done += 1;
+        if failed {
+            self.failed += 1;
+        }
+        if self.recent.len() == WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(now);
+    }
+
+    /// How long the rest takes at the pace of the last few checks
+    fn eta(&self) -> Option<time::Duration> {
+        let first = self.recent.front()?;
+        let last = self.recent.back()?;
+        let elapsed = last.duration_since(*first).as_secs_f64();
+        if self.recent.len() < 2 || elapsed == 0.0 {
+            return None;
+        }
+        let per_second = (self.recent.len() - 1) as f64 / elapsed;
+        Some(time::Duration::from_secs_f64(self.total.saturating_sub(self.done) as f64 / per_second))
+    }
+
+    fn line(&self, in_flight: usize) -> String {
+        let filled = WIDTH * self.done / self.total.max(1);
+        let eta = match self.eta().map(|eta| eta.as_secs_f64().round() as u64) {
+            Some(eta) if eta >= 60 => format!("{}m{:02}s", eta / 60, eta % 60),
+            Some(eta) => format!("{}s", eta),
+            None => "?".to_string(),
+        };
+        format!(
+            "[{}{}] {}/{} urls, {} failed, {} in flight, ETA {}",
+            "#".repeat(filled), "-".repeat(WIDTH - filled), self.done, self.total, self.failed, in_flight, eta,
+        )
+    }
+
+    pub fn draw(&mut self, in_flight: usize) {
+        eprint!("\r\x1b[2K{}", self.line(in_flight));
+        self.drawn = true;
+    }
+
+    /// Takes the bar away, for a line to go where it was
+    pub fn clear(&mut self) {
+        if self.drawn {
+            eprint!("\r\x1b[2K");
+            self.drawn = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_and_eta() {
+        let mut bar = Bar {total: 200, done: 0, failed: 0, recent: VecDeque::new(), drawn: false};
+        assert_eq!(bar.line(16), "[------------------------------] 0/200 urls, 0 failed, 16 in flight, ETA ?");
+        let start = time::Instant::now();
+        // Slow at first, then 10 a second
+        bar.finished(true, start);
+        for i in 0..99 {
+            bar.finished(false, start + time::Duration::from_secs(60) + time::Duration::from_millis(100 * i));
+        }
+        assert_eq!(bar.line(16), "[###############---------------] 100/200 urls, 1 failed, 16 in flight, ETA 10s");
+        for i in 0..90 {
+            bar.finished(false, start + time::Duration::from_secs(70) + time::Duration::from_secs(2 * i));
+        }
+        assert_eq!(bar.line(10), "[############################--] 190/200 urls, 1 failed, 10 in flight, ETA 20s");
+    }
+}
@@ -0,0 +1,128 @@
+use reqwest::{Proxy, Url};
+use std::env;
+use std::time;
+use failure::format_err;
+use log::info;
+use tokio::net::TcpStream;
+
+/// Reads the first of the given environment variables that is set and non-empty
+fn var(names: &[&str]) -> Option<String> {
+    names.iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.trim().is_empty())
+}
+
+/// Whether NO_PROXY excludes this url. Entries match the host itself and its subdomains, with
+/// or without a leading dot, and only on that port if they have one. `*` excludes everything.
+fn no_proxy_matches(no_proxy: &[String], url: &Url) -> bool {
+    let host = url.host_str().unwrap_or_default();
+    no_proxy.iter().any(|entry| {
+        let (entry, port) = match entry.rsplit_once(':') {
+            Some((entry, port)) if port.parse::<u16>().is_ok() => (entry, port.parse().ok()),
+            _ => (entry.as_str(), None),
+        };
+        if port.is_some() && port != url.port_or_known_default() {
+            return false;
+        }
+        let entry = entry.trim_start_matches('.');
+        entry == "*" || host.eq_ignore_ascii_case(entry) ||
+            (host.len() > entry.len() && host.to_ascii_lowercase().ends_with(&format!(".{}", entry.to_ascii_lowercase())))
+    })
+}
+
+/// Strips credentials so the proxy can be logged
+fn display(proxy: &Url) -> String {
+    let mut proxy = proxy.clone();
+    let _ = proxy.set_password(None);
+    let _ = proxy.set_username("");
+    proxy.to_string()
+}
+
+/// Builds a proxy from HTTP_PROXY, HTTPS_PROXY and NO_PROXY, if any of them are set.
+/// Credentials embedded in the proxy url are used for authentication.
+pub fn from_env() -> Result<Option<Proxy>, failure::Error> {
+    let http = var(&["HTTP_PROXY", "http_proxy"]).map(|p| Url::parse(&p)).transpose()?;
+    let https = var(&["HTTPS_PROXY", "https_proxy"]).map(|p| Url::parse(&p)).transpose()?;
+    if http.is_none() && https.is_none() {
+        info!("Not using a proxy");
+        return Ok(None);
+    }
+    let no_proxy: Vec<String> = var(&["NO_PROXY", "no_proxy"]).unwrap_or_default()
+        .split(',')
+        .map(|entry| entry.trim().to_string())
+        .filter(|entry| !entry.is_empty())
+        .collect();
+    if let Some(ref http) = http {
+        info!("Using proxy {} for http", display(http));
+    }
+    if let Some(ref https) = https {
+        info!("Using proxy {} for https", display(https));
+    }
+    if !no_proxy.is_empty() {
+        info!("Not using a proxy for {}", no_proxy.join(", "));
+    }
+    Ok(Some(Proxy::custom(move |url| {
+        if no_proxy_matches(&no_proxy, url) {
+            return None;
+        }
+        match url.scheme() {
+            "http" => http.clone(),
+            "https" => https.clone(),
+            _ => None,
+        }
+    })))
+}
+
+/// The proxy `--proxy` names. For socks5 we always let the proxy resolve names, otherwise lookups
+/// would leak past it and fail for hosts only it can see.
+fn flag_url(proxy: &str) -> Result<Url, failure::Error> {
+    let url = Url::parse(proxy)?;
+    if url.scheme() == "socks5" {
+        return Ok(Url::parse(&proxy.replacen("socks5", "socks5h", 1))?);
+    }
+    Ok(url)
+}
+
+/// Checks `--proxy` up front, so an unreachable proxy doesn't show up as every link failing
+pub async fn check_flag(proxy: &str, connect_timeout: time::Duration) -> Result<(), failure::Error> {
+    let url = flag_url(proxy)?;
+    let host = url.host_str().ok_or_else(|| format_err!("Proxy {} has no host", display(&url)))?;
+    let port = url.port_or_known_default().unwrap_or(1080);
+    for addr in tokio::net::lookup_host((host, port)).await? {
+        if let Ok(Ok(_)) = async_std::future::timeout(connect_timeout, TcpStream::connect(addr)).await {
+            return Ok(());
+        }
+    }
+    Err(format_err!("Proxy {} is unreachable", display(&url)))
+}
+
+/// Builds a proxy from `--proxy`, which is used for all requests
+pub fn from_flag(proxy: &str) -> Result<Proxy, failure::Error> {
+    let url = flag_url(proxy)?;
+    info!("Using proxy {} for all requests", display(&url));
+    Ok(Proxy::all(url)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_proxy() {
+        let no_proxy: Vec<_> = ["example.com", ".example.org", "localhost:8080"].iter().map(|entry| entry.to_string()).collect();
+        let matches = |url: &str| no_proxy_matches(&no_proxy, &Url::parse(url).unwrap());
+        // The host and its subdomains, but not hosts which only end the same
+        assert!(matches("https://example.com/"));
+        assert!(matches("https://docs.EXAMPLE.com/"));
+        assert!(!matches("https://notexample.com/"));
+        // A leading dot makes no difference
+        assert!(matches("https://example.org/"));
+        assert!(matches("https://www.example.org/"));
+        // With a port, only that port
+        assert!(matches("http://localhost:8080/"));
+        assert!(!matches("http://localhost/"));
+        assert!(!matches("https://example.net/"));
+        assert!(no_proxy_matches(&["*".to_string()], &Url::parse("https://anything.example/").unwrap()));
+        assert!(!no_proxy_matches(&[], &Url::parse("https://example.com/").unwrap()));
+    }
+}
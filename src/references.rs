@@ -0,0 +1,131 @@
+use lazy_static::lazy_static;
+use pulldown_cmark::{Event, LinkType, Parser, Tag};
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// A link reference definition, like `[rust]: https://www.rust-lang.org`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Definition {
+    pub label: String,
+    pub url: String,
+    /// Byte offset in the document
+    pub offset: usize,
+}
+
+/// Labels match case insensitively, and any run of whitespace in them counts as a single space
+pub fn normalize(label: &str) -> String {
+    label.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+/// All the reference definitions in the document, by normalized label. pulldown-cmark resolves
+/// references itself, but doesn't tell us about the definitions. Like there, the first
+/// definition of a label wins.
+pub fn definitions(source: &str) -> BTreeMap<String, Definition> {
+    lazy_static! {
+        static ref DEFINITION_REGEX: Regex = Regex::new(r"(?m)^ {0,3}\[(?P<label>[^\]]+)\]:[ \t]*<?(?P<url>[^\s>]+)>?").unwrap();
+    }
+    // Whatever looks like a definition in a code block is only an example
+    let code = code_blocks(source);
+    let mut definitions = BTreeMap::new();
+    for captures in DEFINITION_REGEX.captures_iter(source) {
+        let offset = captures.get(0).unwrap().start();
+        if code.iter().any(|range| range.contains(&offset)) {
+            continue;
+        }
+        let label = &captures["label"];
+        definitions.entry(normalize(label)).or_insert_with(|| Definition {
+            label: label.to_string(),
+            url: captures["url"].to_string(),
+            offset,
+        });
+    }
+    definitions
+}
+
+/// Where the code blocks are, whose contents look like markdown without being any
+pub fn code_blocks(source: &str) -> Vec<Range<usize>> {
+    Parser::new(source).into_offset_iter()
+        .filter(|(event, _)| matches!(event, Event::Start(Tag::CodeBlock(_))))
+        .map(|(_, range)| range)
+        .collect()
+}
+
+/// The bracketed text starting at the beginning of `source`, without the brackets
+fn bracketed(source: &str) -> Option<&str> {
+    let mut depth = 0;
+    let mut escaped = false;
+    for (i, c) in source.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '[' => depth += 1,
+            ']' if depth == 1 => return Some(&source[1..i]),
+            ']' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The label a reference link starting at `offset` uses: `[text][label]`, `[label][]` or
+/// `[label]`. pulldown-cmark only gives the position of the opening bracket.
+pub fn label(source: &str, offset: usize, link_type: LinkType) -> Option<String> {
+    let link = source[offset..].trim_start_matches('!');
+    let text = bracketed(link)?;
+    let label = match link_type {
+        LinkType::Reference | LinkType::ReferenceUnknown => bracketed(&link[text.len() + 2..])?,
+        LinkType::Collapsed | LinkType::CollapsedUnknown | LinkType::Shortcut | LinkType::ShortcutUnknown => text,
+        _ => return None,
+    };
+    Some(normalize(label))
+}
+
+/// Where an explicit reference (`[text][label]`) to a label is, if there is one. Collapsed and
+/// shortcut references to labels nobody defined are just text in brackets.
+pub fn explicit_reference(source: &str, label: &str) -> Option<usize> {
+    let label = regex::escape(label).replace(' ', r"\s+");
+    Regex::new(&format!(r"(?i)\]\[{}\]", label)).ok()?.find(source).map(|found| found.start())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "\
+See [the book][Book], [Rust] and [the   crates][].
+
+    [example]: https://example.com
+
+[book]: https://doc.rust-lang.org/book/
+[rust]: <https://www.rust-lang.org> \"Rust\"
+[The Crates]: https://crates.io
+[rust]: https://www.rust-lang.org/duplicate
+[unused]: https://example.org
+";
+
+    #[test]
+    fn reference_definitions() {
+        let definitions = definitions(SOURCE);
+        let labels: Vec<_> = definitions.keys().map(String::as_str).collect();
+        assert_eq!(labels, vec!["book", "rust", "the crates", "unused"]);
+        assert_eq!(definitions["rust"].url, "https://www.rust-lang.org");
+        assert_eq!(definitions["the crates"].label, "The Crates");
+        assert_eq!(&SOURCE[definitions["book"].offset..][..6], "[book]");
+    }
+
+    #[test]
+    fn reference_labels() {
+        let labels: Vec<_> = Parser::new(SOURCE).into_offset_iter()
+            .filter_map(|(event, range)| match event {
+                Event::Start(Tag::Link(link_type, _, _)) => label(SOURCE, range.start, link_type),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(labels, vec!["book", "rust", "the crates"]);
+        assert_eq!(label("[text](https://example.com)", 0, LinkType::Inline), None);
+        assert_eq!(label("![a [nested] \\] text][Label]", 0, LinkType::Reference), Some("label".to_string()));
+        assert_eq!(explicit_reference("a [b][Missing  Label] c", "missing label"), Some(4));
+        assert_eq!(explicit_reference("a [missing label] c", "missing label"), None);
+    }
+}
@@ -0,0 +1,677 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::time;
+use failure::Error;
+use crate::archive::Snapshot;
+use crate::config::Config;
+use crate::failures::{split_location, Failure};
+use crate::{html_report, issue, sarif};
+use crate::{CheckSuccess, Opt, Results};
+
+/// How the report at the end of a run is written
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// For people, on stdout along with the progress
+    Text,
+    Json,
+    /// A test case for each url, for CI systems which show JUnit XML
+    Junit,
+    /// For GitHub code scanning, which shows the results on the readme
+    Sarif,
+    /// A row for each url, for spreadsheets
+    Csv,
+    /// A page of its own, to pass around
+    Html,
+    /// A task list of the broken links, to paste into an issue
+    Markdown,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Format, String> {
+        match format {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "junit" => Ok(Format::Junit),
+            "sarif" => Ok(Format::Sarif),
+            "csv" => Ok(Format::Csv),
+            "html" => Ok(Format::Html),
+            "markdown" => Ok(Format::Markdown),
+            _ => Err(format!("unknown output format {}", format)),
+        }
+    }
+}
+
+/// What a run found, as `--output-format json` writes it. The fields are the schema of the
+/// document, so tools which read it break when they're renamed.
+#[derive(Debug, Serialize)]
+pub struct Report<'a> {
+    pub summary: Summary,
+    /// The broken links, in the order of the results
+    pub failures: Vec<ReportedFailure<'a>>,
+    /// Everything we know a better url or a fix for
+    pub suggestions: Vec<Suggestion<'a>>,
+    /// Every url in the files, with how it answered
+    pub urls: Vec<UrlReport<'a>>,
+    /// What --lint-entries, --lint-order and --check-toc found, as `readme:line: problem`
+    pub lint: &'a [String],
+}
+
+/// The counts of a run
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Summary {
+    pub files: Vec<String>,
+    /// Distinct urls, each of which was checked once however many links it has
+    pub urls: usize,
+    pub links: usize,
+    pub working: usize,
+    /// Everything that fails the run, including links which aren't fetched
+    pub failed: usize,
+    /// The part of `failed` which this run checked and found gone for good: whatever the retry
+    /// policy doesn't retry (by default a 404, 410 or 401, a host which doesn't resolve or a
+    /// broken certificate), or a redirect loop. The rest may work again later.
+    pub permanent: usize,
+    pub unavailable: usize,
+    pub unverifiable: usize,
+    pub login_required: usize,
+    pub suspect: usize,
+    /// Urls we didn't get to before the deadline
+    pub unchecked: usize,
+    /// Links left alone because of link-check comments
+    pub ignored_links: usize,
+    pub downloaded_bytes: u64,
+    pub duration_ms: u64,
+    pub deadline_reached: bool,
+}
+
+/// A broken link, with the failure as the results keep it
+#[derive(Debug, Serialize)]
+pub struct ReportedFailure<'a> {
+    /// The url, or what the results know a link which isn't fetched by, like `README.md#usage`
+    pub link: &'a str,
+    #[serde(flatten)]
+    pub failure: &'a Failure,
+    /// The Wayback Machine's copy, with --suggest-archive
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub archived: Option<&'a Snapshot>,
+}
+
+/// What to replace a link with
+#[derive(Debug, Serialize)]
+pub struct Suggestion<'a> {
+    pub link: &'a str,
+    pub replacement: &'a str,
+    pub kind: SuggestionKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SuggestionKind {
+    /// It works, but there's a url it should be: the https one, or where it redirects to
+    BetterUrl,
+    /// Plain http doesn't connect anymore, but https does
+    MovedToHttps,
+    /// It's broken, but looks like it was meant to be this
+    Fix,
+    /// It's broken, and the Wayback Machine has a copy
+    ArchivedCopy,
+}
+
+/// How a url did
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum State {
+    Working,
+    Failed,
+    Unavailable,
+    Unverifiable,
+    LoginRequired,
+    Suspect,
+    Unchecked,
+    /// Left alone because of a link-check comment
+    Ignored,
+}
+
+/// A url in the files
+#[derive(Debug, Serialize)]
+pub struct UrlReport<'a> {
+    pub url: &'a str,
+    pub state: State,
+    /// Why it's unavailable, unverifiable or suspect, or the login page it leads to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<&'a str>,
+    /// Where it is, as path:line:column
+    pub locations: &'a [String],
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub sections: &'a [String],
+    /// Where it redirects to, if it works after redirects
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub redirects: &'a [String],
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub warnings: &'a [String],
+    /// How it answered, with --verbose-results
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<&'a CheckSuccess>,
+    /// When it last worked, in seconds since the Unix epoch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_checked: Option<u64>,
+    /// How long checking it took this run, if it was checked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// How many requests checking it took this run, retries included
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attempts: Option<u32>,
+}
+
+/// What a run knows besides the results
+pub struct Run<'a> {
+    pub files: Vec<String>,
+    /// Where each url is in the files, and the sections it's in, under the key of its failure
+    pub locations: &'a BTreeMap<String, Vec<String>>,
+    pub link_sections: &'a BTreeMap<String, Vec<String>>,
+    pub lints: &'a [String],
+    /// Links left alone because of link-check comments, with where they are
+    pub ignored: &'a BTreeMap<String, Vec<String>>,
+    pub durations: &'a BTreeMap<String, time::Duration>,
+    pub attempts: &'a BTreeMap<String, u32>,
+    pub permanent: usize,
+    pub downloaded: u64,
+    pub duration: time::Duration,
+    pub deadline_reached: bool,
+}
+
+impl<'a> Report<'a> {
+    pub fn new(results: &'a Results, run: &Run<'a>) -> Report<'a> {
+        let ignored = run.ignored.iter().filter(|(url, _)| !run.locations.contains_key(*url));
+        let mut urls: Vec<_> = run.locations.iter().map(|(url, locations)| {
+            let (state, reason) = state(results, url);
+            UrlReport {
+                url,
+                state,
+                reason,
+                locations,
+                sections: run.link_sections.get(url).map_or(&[], Vec::as_slice),
+                redirects: results.redirected.get(url).map_or(&[], Vec::as_slice),
+                warnings: results.warnings.get(url).map_or(&[], Vec::as_slice),
+                details: results.working_details.get(url),
+                last_checked: results.last_checked.get(url).copied(),
+                duration_ms: run.durations.get(url).map(|duration| duration.as_millis() as u64),
+                attempts: run.attempts.get(url).copied(),
+            }
+        }).collect();
+        urls.extend(ignored.map(|(url, locations)| UrlReport {
+            url,
+            state: State::Ignored,
+            reason: None,
+            locations,
+            sections: run.link_sections.get(url).map_or(&[], Vec::as_slice),
+            redirects: &[],
+            warnings: &[],
+            details: None,
+            last_checked: None,
+            duration_ms: None,
+            attempts: None,
+        }));
+        let failures = results.failed.iter()
+            .map(|(link, failure)| ReportedFailure {link, failure, archived: results.archived.get(link)})
+            .collect();
+        let better = |(link, replacement): (&'a String, &'a String), kind| Suggestion {link, replacement, kind};
+        let suggestions = results.suggestions.iter().map(|suggestion| better(suggestion, SuggestionKind::BetterUrl))
+            .chain(results.moved_to_https.iter().map(|suggestion| better(suggestion, SuggestionKind::MovedToHttps)))
+            .chain(results.failed.iter().filter_map(|(link, failure)| {
+                Some(Suggestion {link, replacement: failure.suggestion.as_deref()?, kind: SuggestionKind::Fix})
+            }))
+            .chain(results.archived.iter().map(|(link, snapshot)| {
+                Suggestion {link, replacement: &snapshot.url, kind: SuggestionKind::ArchivedCopy}
+            }))
+            .collect();
+        let count = |state| urls.iter().filter(|url| url.state == state).count();
+        let summary = Summary {
+            files: run.files.clone(),
+            urls: run.locations.len(),
+            links: run.locations.values().map(Vec::len).sum(),
+            working: count(State::Working),
+            failed: results.failed.len(),
+            permanent: run.permanent,
+            unavailable: count(State::Unavailable),
+            unverifiable: count(State::Unverifiable),
+            login_required: count(State::LoginRequired),
+            suspect: count(State::Suspect),
+            unchecked: results.unchecked.len(),
+            ignored_links: run.ignored.values().map(Vec::len).sum(),
+            downloaded_bytes: run.downloaded,
+            duration_ms: run.duration.as_millis() as u64,
+            deadline_reached: run.deadline_reached,
+        };
+        Report {summary, failures, suggestions, urls, lint: run.lints}
+    }
+
+    /// Writes the report in the --output-format to the --output file, or to stdout without one
+    pub fn write(&self, opt: &Opt, config: &Config) -> Result<(), Error> {
+        let written = match opt.output_format {
+            Format::Json => serde_json::to_string_pretty(self)? + "\n",
+            Format::Junit => self.junit(),
+            Format::Sarif => sarif::render(self, &config.sarif_levels),
+            Format::Csv => self.csv(opt.failures_only)?,
+            Format::Html => html_report::render(self),
+            Format::Markdown => issue::render(self),
+            Format::Text => return Ok(()),
+        };
+        match &opt.output {
+            Some(output) => fs::write(output, written)?,
+            None => print!("{}", written),
+        }
+        Ok(())
+    }
+
+    /// A test case for each url, and for each failure of a link which isn't fetched, in a test
+    /// suite for each section. Links outside of any section get one for their file.
+    fn junit(&self) -> String {
+        let mut suites: BTreeMap<&str, Vec<Case>> = BTreeMap::new();
+        for url in &self.urls {
+            let failure = self.failures.iter().find(|failure| failure.link == url.url);
+            let outcome = match (url.state, failure) {
+                (State::Working, _) => Outcome::Passed,
+                (State::Failed, Some(failure)) => Outcome::Failed(failure.failure),
+                (state, _) => {
+                    let state = name(state).replace('_', " ");
+                    Outcome::Skipped(match url.reason {
+                        Some(reason) => format!("{}, {}", state, reason),
+                        None => state,
+                    })
+                }
+            };
+            let case = Case {name: url.url, file: file(url.locations), duration_ms: url.duration_ms.unwrap_or_default(), outcome};
+            suites.entry(suite(url.sections, url.locations)).or_default().push(case);
+        }
+        for failure in self.failures.iter().filter(|failure| !self.urls.iter().any(|url| url.url == failure.link)) {
+            let locations = &failure.failure.locations;
+            let case = Case {name: failure.link, file: file(locations), duration_ms: 0, outcome: Outcome::Failed(failure.failure)};
+            suites.entry(suite(&failure.failure.sections, locations)).or_default().push(case);
+        }
+        let count = |cases: &[Case], failed: bool| cases.iter().filter(|case| match case.outcome {
+            Outcome::Failed(_) => failed,
+            Outcome::Skipped(_) => !failed,
+            Outcome::Passed => false,
+        }).count();
+        let all: Vec<_> = suites.values().flatten().collect();
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml += &format!(
+            "<testsuites name=\"links\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{}\">\n",
+            all.len(),
+            all.iter().filter(|case| matches!(case.outcome, Outcome::Failed(_))).count(),
+            all.iter().filter(|case| matches!(case.outcome, Outcome::Skipped(_))).count(),
+            seconds(self.summary.duration_ms),
+        );
+        for (title, cases) in &suites {
+            let time = seconds(cases.iter().map(|case| case.duration_ms).sum());
+            xml += &format!(
+                "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{}\">\n",
+                escape(title), cases.len(), count(cases, true), count(cases, false), time,
+            );
+            for case in cases {
+                xml += &format!("    <testcase name=\"{}\" classname=\"{}\" time=\"{}\"", escape(case.name), escape(case.file), seconds(case.duration_ms));
+                match &case.outcome {
+                    Outcome::Passed => xml += "/>\n",
+                    Outcome::Failed(failure) => {
+                        xml += &format!(
+                            ">\n      <failure type=\"{}\" message=\"{}\">{}</failure>\n    </testcase>\n",
+                            name(failure.kind), escape(&failure.message), escape(&failure.to_string()),
+                        );
+                    }
+                    Outcome::Skipped(message) => xml += &format!(">\n      <skipped message=\"{}\"/>\n    </testcase>\n", escape(message)),
+                }
+            }
+            xml += "  </testsuite>\n";
+        }
+        xml += "</testsuites>\n";
+        xml
+    }
+
+    /// A row for each url, working ones too unless it's only the failures, and for each failure
+    /// of a link which isn't fetched
+    fn csv(&self, failures_only: bool) -> Result<String, Error> {
+        let mut writer = csv::Writer::from_writer(vec![]);
+        for url in &self.urls {
+            let failure = self.failures.iter().find(|failure| failure.link == url.url).map(|failure| failure.failure);
+            if failures_only && failure.is_none() {
+                continue;
+            }
+            let (file, line) = first_location(url.locations);
+            writer.serialize(Row {
+                url: url.url,
+                status: name(url.state),
+                http_status: failure.and_then(|failure| failure.status).or_else(|| url.details.map(|details| details.status)),
+                error_kind: failure.map(|failure| name(failure.kind)),
+                redirect_target: url.redirects.last().map(String::as_str),
+                section: url.sections.join("; "),
+                file,
+                line,
+                duration_ms: url.duration_ms,
+                attempts: url.attempts.or_else(|| failure.map(|failure| failure.attempts)),
+            })?;
+        }
+        for failure in self.failures.iter().filter(|failure| !self.urls.iter().any(|url| url.url == failure.link)) {
+            let (file, line) = first_location(&failure.failure.locations);
+            writer.serialize(Row {
+                url: failure.link,
+                status: name(State::Failed),
+                http_status: failure.failure.status,
+                error_kind: Some(name(failure.failure.kind)),
+                redirect_target: None,
+                section: failure.failure.sections.join("; "),
+                file,
+                line,
+                duration_ms: None,
+                attempts: Some(failure.failure.attempts),
+            })?;
+        }
+        Ok(String::from_utf8(writer.into_inner().map_err(|e| e.into_error())?)?)
+    }
+}
+
+/// A row of `--output-format csv`. Scripts go by the order of the columns, so new ones go at the end.
+#[derive(Debug, Serialize)]
+struct Row<'a> {
+    url: &'a str,
+    status: String,
+    http_status: Option<u16>,
+    error_kind: Option<String>,
+    redirect_target: Option<&'a str>,
+    /// All the sections it's in, separated by `; `
+    section: String,
+    file: &'a str,
+    line: Option<usize>,
+    duration_ms: Option<u64>,
+    attempts: Option<u32>,
+}
+
+/// A test case of the JUnit report
+struct Case<'a> {
+    name: &'a str,
+    file: &'a str,
+    duration_ms: u64,
+    outcome: Outcome<'a>,
+}
+
+enum Outcome<'a> {
+    Passed,
+    Failed(&'a Failure),
+    /// With why it wasn't checked, or why it doesn't count
+    Skipped(String),
+}
+
+/// The test suite of a link, and its group in the html report: the first section it's in, or
+/// its file
+pub fn suite<'a>(sections: &'a [String], locations: &'a [String]) -> &'a str {
+    sections.first().map_or_else(|| file(locations), String::as_str)
+}
+
+/// The file of the first of a link's locations, which are path:line:column
+fn file(locations: &[String]) -> &str {
+    first_location(locations).0
+}
+
+/// The file and line of the first of a link's locations
+fn first_location(locations: &[String]) -> (&str, Option<usize>) {
+    match locations.first().and_then(|location| split_location(location)) {
+        Some((file, line, _)) => (file, Some(line)),
+        None => ("", None),
+    }
+}
+
+/// What a unit variant is called in the JSON, like `no_such_heading`
+pub fn name(variant: impl Serialize) -> String {
+    serde_json::to_value(variant).ok().and_then(|name| name.as_str().map(str::to_string)).unwrap_or_default()
+}
+
+/// Milliseconds as the seconds JUnit wants
+fn seconds(ms: u64) -> String {
+    format!("{}.{:03}", ms / 1000, ms % 1000)
+}
+
+/// Text which can go in XML or HTML, in an attribute too
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// How a url did, going by where the results have it, with why if they say
+fn state<'a>(results: &'a Results, url: &str) -> (State, Option<&'a str>) {
+    if results.failed.contains_key(url) {
+        (State::Failed, None)
+    } else if results.unchecked.contains(url) {
+        (State::Unchecked, None)
+    } else if let Some(reason) = results.unavailable.get(url) {
+        (State::Unavailable, Some(reason))
+    } else if let Some(reason) = results.unverifiable.get(url) {
+        (State::Unverifiable, Some(reason))
+    } else if let Some(login) = results.access_restricted.get(url) {
+        (State::LoginRequired, Some(login))
+    } else if let Some(reason) = results.suspect.get(url) {
+        (State::Suspect, Some(reason))
+    } else if results.working.contains(url) {
+        (State::Working, None)
+    } else {
+        (State::Unchecked, None)
+    }
+}
+
+/// What a run over a small readme knows, for the tests of the report and the formats it's
+/// written in
+#[cfg(test)]
+pub(crate) struct Fixture {
+    pub(crate) results: Results,
+    pub(crate) locations: BTreeMap<String, Vec<String>>,
+    pub(crate) link_sections: BTreeMap<String, Vec<String>>,
+    pub(crate) lints: Vec<String>,
+    pub(crate) ignored: BTreeMap<String, Vec<String>>,
+    pub(crate) durations: BTreeMap<String, time::Duration>,
+    pub(crate) attempts: BTreeMap<String, u32>,
+}
+
+#[cfg(test)]
+impl Fixture {
+    pub(crate) fn new() -> Fixture {
+        let mut results = Results::new();
+        results.working.insert("https://a.example/".to_string());
+        results.last_checked.insert("https://a.example/".to_string(), 1600000000);
+        results.redirected.insert("https://a.example/".to_string(), vec!["https://a.example/home".to_string()]);
+        results.suggestions.insert("https://a.example/".to_string(), "https://a.example/home".to_string());
+        let mut failure = Failure::new(crate::failures::Kind::Http, "[404] https://b.example/".to_string());
+        failure.status = Some(404);
+        failure.locations = vec!["README.md:3:1".to_string()];
+        failure.attempts = 1;
+        failure.checked_at = 1600000000;
+        results.failed.insert("https://b.example/".to_string(), failure);
+        let mut failure = Failure::new(crate::failures::Kind::NoSuchHeading, "[no such heading] #usage".to_string());
+        failure.suggestion = Some("#usages".to_string());
+        failure.locations = vec!["README.md:7:3".to_string()];
+        failure.checked_at = 1600000000;
+        results.failed.insert("README.md#usage".to_string(), failure);
+        results.unverifiable.insert("https://c.example/".to_string(), "Cloudflare".to_string());
+        let mut locations = BTreeMap::new();
+        locations.insert("https://a.example/".to_string(), vec!["README.md:1:1".to_string(), "README.md:2:1".to_string()]);
+        locations.insert("https://b.example/".to_string(), vec!["README.md:3:1".to_string()]);
+        locations.insert("https://c.example/".to_string(), vec!["README.md:4:1".to_string()]);
+        let mut link_sections = BTreeMap::new();
+        link_sections.insert("https://b.example/".to_string(), vec!["Applications > Audio".to_string()]);
+        Fixture {
+            results,
+            locations,
+            link_sections,
+            lints: vec!["README.md:4: no description".to_string()],
+            ignored: BTreeMap::new(),
+            durations: BTreeMap::new(),
+            attempts: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn report(&self) -> Report<'_> {
+        let run = Run {
+            files: vec!["README.md".to_string()],
+            locations: &self.locations,
+            link_sections: &self.link_sections,
+            lints: &self.lints,
+            ignored: &self.ignored,
+            durations: &self.durations,
+            attempts: &self.attempts,
+            permanent: 1,
+            downloaded: 2048,
+            duration: time::Duration::from_millis(1500),
+            deadline_reached: false,
+        };
+        Report::new(&self.results, &run)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_schema() {
+        let json = serde_json::to_string_pretty(&Fixture::new().report()).unwrap();
+        assert_eq!(json, r##"{
+  "summary": {
+    "files": [
+      "README.md"
+    ],
+    "urls": 3,
+    "links": 4,
+    "working": 1,
+    "failed": 2,
+    "permanent": 1,
+    "unavailable": 0,
+    "unverifiable": 1,
+    "login_required": 0,
+    "suspect": 0,
+    "unchecked": 0,
+    "ignored_links": 0,
+    "downloaded_bytes": 2048,
+    "duration_ms": 1500,
+    "deadline_reached": false
+  },
+  "failures": [
+    {
+      "link": "README.md#usage",
+      "kind": "no_such_heading",
+      "message": "[no such heading] #usage",
+      "locations": [
+        "README.md:7:3"
+      ],
+      "suggestion": "#usages",
+      "attempts": 0,
+      "checked_at": 1600000000
+    },
+    {
+      "link": "https://b.example/",
+      "kind": "http",
+      "status": 404,
+      "message": "[404] https://b.example/",
+      "locations": [
+        "README.md:3:1"
+      ],
+      "attempts": 1,
+      "checked_at": 1600000000
+    }
+  ],
+  "suggestions": [
+    {
+      "link": "https://a.example/",
+      "replacement": "https://a.example/home",
+      "kind": "better_url"
+    },
+    {
+      "link": "README.md#usage",
+      "replacement": "#usages",
+      "kind": "fix"
+    }
+  ],
+  "urls": [
+    {
+      "url": "https://a.example/",
+      "state": "working",
+      "locations": [
+        "README.md:1:1",
+        "README.md:2:1"
+      ],
+      "redirects": [
+        "https://a.example/home"
+      ],
+      "last_checked": 1600000000
+    },
+    {
+      "url": "https://b.example/",
+      "state": "failed",
+      "locations": [
+        "README.md:3:1"
+      ],
+      "sections": [
+        "Applications > Audio"
+      ]
+    },
+    {
+      "url": "https://c.example/",
+      "state": "unverifiable",
+      "reason": "Cloudflare",
+      "locations": [
+        "README.md:4:1"
+      ]
+    }
+  ],
+  "lint": [
+    "README.md:4: no description"
+  ]
+}"##);
+    }
+
+    #[test]
+    fn junit() {
+        let mut fixture = Fixture::new();
+        fixture.ignored.insert("https://d.example/?a=1&b=2".to_string(), vec!["README.md:5:1".to_string()]);
+        fixture.durations.insert("https://a.example/".to_string(), time::Duration::from_millis(250));
+        fixture.durations.insert("https://b.example/".to_string(), time::Duration::from_millis(1020));
+        assert_eq!(fixture.report().junit(), r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites name="links" tests="5" failures="2" skipped="2" time="1.500">
+  <testsuite name="Applications &gt; Audio" tests="1" failures="1" skipped="0" time="1.020">
+    <testcase name="https://b.example/" classname="README.md" time="1.020">
+      <failure type="http" message="[404] https://b.example/">[404] https://b.example/ (README.md:3:1)</failure>
+    </testcase>
+  </testsuite>
+  <testsuite name="README.md" tests="4" failures="1" skipped="2" time="0.250">
+    <testcase name="https://a.example/" classname="README.md" time="0.250"/>
+    <testcase name="https://c.example/" classname="README.md" time="0.000">
+      <skipped message="unverifiable, Cloudflare"/>
+    </testcase>
+    <testcase name="https://d.example/?a=1&amp;b=2" classname="README.md" time="0.000">
+      <skipped message="ignored"/>
+    </testcase>
+    <testcase name="README.md#usage" classname="README.md" time="0.000">
+      <failure type="no_such_heading" message="[no such heading] #usage">[no such heading] #usage (README.md:7:3), did you mean #usages?</failure>
+    </testcase>
+  </testsuite>
+</testsuites>
+"#);
+    }
+
+    #[test]
+    fn csv() {
+        let mut fixture = Fixture::new();
+        let quoted = "https://d.example/?q=\"a,b\"".to_string();
+        fixture.results.working.insert(quoted.clone());
+        fixture.locations.insert(quoted, vec!["README.md:5:3".to_string()]);
+        fixture.durations.insert("https://b.example/".to_string(), time::Duration::from_millis(1020));
+        let report = fixture.report();
+        assert_eq!(report.csv(false).unwrap(), "\
+url,status,http_status,error_kind,redirect_target,section,file,line,duration_ms,attempts
+https://a.example/,working,,,https://a.example/home,,README.md,1,,
+https://b.example/,failed,404,http,,Applications > Audio,README.md,3,1020,1
+https://c.example/,unverifiable,,,,,README.md,4,,
+\"https://d.example/?q=\"\"a,b\"\"\",working,,,,,README.md,5,,
+README.md#usage,failed,,no_such_heading,,,README.md,7,,0
+");
+        assert_eq!(report.csv(true).unwrap().lines().count(), 3);
+    }
+}
@@ -0,0 +1,147 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time;
+use failure::{Error, format_err};
+use reqwest::StatusCode;
+
+/// How often we try a url before giving up on it, and how long we wait in between
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Retry {
+    /// Including the first one, so 1 means no retries
+    pub attempts: u32,
+    /// Wait before the first retry, doubled for every one after that
+    #[serde(default)]
+    pub backoff_ms: u64,
+}
+
+impl Retry {
+    const fn new(attempts: u32, backoff_ms: u64) -> Retry {
+        Retry { attempts, backoff_ms }
+    }
+
+    /// How long to wait after the given (1-based) failed attempt
+    pub fn backoff(&self, attempt: u32) -> time::Duration {
+        time::Duration::from_millis(self.backoff_ms.saturating_mul(1 << attempt.saturating_sub(1).min(16)))
+    }
+}
+
+/// Connection errors, named the way they can be configured
+pub const ERROR_KINDS: &[&str] = &["dns", "timeout", "connection_refused", "connection_reset", "tls", "other"];
+
+/// Keyed by status code ("404"), status class ("5xx") or error kind, most specific first
+const DEFAULTS: &[(&str, Retry)] = &[
+    // Gone for good, or for anyone without an account
+    ("401", Retry::new(1, 0)),
+    ("404", Retry::new(1, 0)),
+    ("410", Retry::new(1, 0)),
+    // Without a Retry-After, wait 2s, 4s, 8s, ...
+    ("429", Retry::new(6, 2000)),
+    // Once with `Accept: */*`, once without an Accept header
+    ("406", Retry::new(3, 0)),
+    ("4xx", Retry::new(2, 0)),
+    // Cloudflare can't reach the origin, which is usually over quickly
+    ("520", Retry::new(5, 2000)),
+    ("521", Retry::new(5, 2000)),
+    ("522", Retry::new(5, 2000)),
+    ("523", Retry::new(5, 2000)),
+    ("524", Retry::new(5, 2000)),
+    ("5xx", Retry::new(5, 1000)),
+    ("dns", Retry::new(1, 0)),
+    ("tls", Retry::new(1, 0)),
+    ("timeout", Retry::new(5, 0)),
+    ("connection_refused", Retry::new(3, 0)),
+    ("connection_reset", Retry::new(5, 0)),
+    ("other", Retry::new(5, 0)),
+];
+
+/// What we do when the server didn't answer what we hoped for, which depends on how it failed
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    retries: BTreeMap<String, Retry>,
+}
+
+impl RetryPolicy {
+    /// The defaults, with whatever the config overrides
+    pub fn new(overrides: &BTreeMap<String, Retry>) -> Result<RetryPolicy, Error> {
+        let mut retries: BTreeMap<String, Retry> = DEFAULTS.iter().map(|(key, retry)| (key.to_string(), *retry)).collect();
+        for (key, retry) in overrides {
+            if !valid_key(key) {
+                return Err(format_err!("Can't retry on {}, expected a status like 404 or 5xx, or one of {}",
+                                       key, ERROR_KINDS.join(", ")));
+            }
+            if retry.attempts == 0 {
+                return Err(format_err!("Retries for {} need at least 1 attempt", key));
+            }
+            retries.insert(key.to_ascii_lowercase(), *retry);
+        }
+        Ok(RetryPolicy { retries })
+    }
+
+    pub fn for_status(&self, status: StatusCode) -> Retry {
+        let code = status.as_u16();
+        self.retries.get(&code.to_string())
+            .or_else(|| self.retries.get(&format!("{}xx", code / 100)))
+            .or_else(|| self.retries.get("other"))
+            .copied()
+            .unwrap_or(Retry::new(1, 0))
+    }
+
+    pub fn for_error(&self, kind: &str) -> Retry {
+        self.retries.get(kind).or_else(|| self.retries.get("other")).copied().unwrap_or(Retry::new(1, 0))
+    }
+}
+
+fn valid_key(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    let bytes = key.as_bytes();
+    ERROR_KINDS.contains(&key.as_str()) || (bytes.len() == 3 && (b'1'..=b'5').contains(&bytes[0]) &&
+        (bytes[1..].iter().all(u8::is_ascii_digit) || &bytes[1..] == b"xx"))
+}
+
+impl fmt::Display for RetryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let entries: Vec<_> = self.retries.iter()
+            .map(|(key, retry)| format!("{}: {}x/{}ms", key, retry.attempts, retry.backoff_ms))
+            .collect();
+        write!(f, "{}", entries.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults() {
+        let policy = RetryPolicy::new(&BTreeMap::new()).unwrap();
+        assert_eq!(policy.for_status(StatusCode::NOT_FOUND).attempts, 1);
+        assert_eq!(policy.for_status(StatusCode::NOT_ACCEPTABLE).attempts, 3);
+        assert_eq!(policy.for_status(StatusCode::INTERNAL_SERVER_ERROR).attempts, 5);
+        let rate_limited = policy.for_status(StatusCode::TOO_MANY_REQUESTS);
+        assert!(rate_limited.attempts > 1);
+        assert_eq!(rate_limited.backoff(1), time::Duration::from_secs(2));
+        assert_eq!(rate_limited.backoff(3), time::Duration::from_secs(8));
+        assert_eq!(policy.for_status(StatusCode::from_u16(521).unwrap()).backoff_ms, 2000);
+        assert_eq!(policy.for_error("dns").attempts, 1);
+        assert_eq!(policy.for_error("something else"), policy.for_error("other"));
+    }
+
+    #[test]
+    fn overrides() {
+        let overrides = serde_yaml::from_str(r#"
+"5xx": {attempts: 2}
+"404": {attempts: 3, backoff_ms: 100}
+"#).unwrap();
+        let policy = RetryPolicy::new(&overrides).unwrap();
+        assert_eq!(policy.for_status(StatusCode::BAD_GATEWAY), Retry::new(2, 0));
+        assert_eq!(policy.for_status(StatusCode::NOT_FOUND), Retry::new(3, 100));
+        // More specific defaults still win over an overridden class
+        assert_eq!(policy.for_status(StatusCode::from_u16(520).unwrap()), Retry::new(5, 2000));
+
+        for invalid in &["\"4x4\": {attempts: 2}", "\"600\": {attempts: 2}", "eof: {attempts: 2}", "\"500\": {attempts: 0}"] {
+            assert!(RetryPolicy::new(&serde_yaml::from_str(invalid).unwrap()).is_err(), "{}", invalid);
+        }
+    }
+}
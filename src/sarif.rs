@@ -0,0 +1,255 @@
+use serde::{Deserialize, Serialize};
+use crate::PARKED;
+use crate::failures::{split_location, Kind};
+use crate::report::{Report, ReportedFailure, State, Suggestion, SuggestionKind, UrlReport};
+
+const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+/// How serious a result is, in SARIF's words
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error,
+    Warning,
+    Note,
+    None,
+}
+
+/// How `--output-format sarif` rates what it reports
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Levels {
+    /// Broken links
+    pub failure: Level,
+    /// Links which work, but look like they lead somewhere they shouldn't, like a parked domain
+    pub suspect: Level,
+    /// Links which work, but have a better url
+    pub suggestion: Level,
+}
+
+impl Default for Levels {
+    fn default() -> Levels {
+        Levels {failure: Level::Error, suspect: Level::Warning, suggestion: Level::Note}
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Log {
+    #[serde(rename = "$schema")]
+    schema: &'static str,
+    version: &'static str,
+    runs: Vec<Run>,
+}
+
+#[derive(Debug, Serialize)]
+struct Run {
+    tool: Tool,
+    results: Vec<SarifResult>,
+}
+
+#[derive(Debug, Serialize)]
+struct Tool {
+    driver: Driver,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Driver {
+    name: &'static str,
+    version: &'static str,
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Rule {
+    id: &'static str,
+    short_description: Message,
+}
+
+#[derive(Debug, Serialize)]
+struct Message {
+    text: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SarifResult {
+    rule_id: &'static str,
+    rule_index: usize,
+    level: Level,
+    message: Message,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    locations: Vec<Location>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Location {
+    physical_location: PhysicalLocation,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PhysicalLocation {
+    artifact_location: ArtifactLocation,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    region: Option<Region>,
+}
+
+#[derive(Debug, Serialize)]
+struct ArtifactLocation {
+    uri: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Region {
+    start_line: usize,
+    start_column: usize,
+}
+
+/// The rule a failure breaks, and what the rule is about
+fn failure_rule(failure: &ReportedFailure) -> (&'static str, &'static str) {
+    match failure.failure.kind {
+        Kind::Http if matches!(failure.failure.status, Some(404) | Some(410)) => ("dead-link", "The page isn't there anymore"),
+        Kind::Http => ("http-error", "The server answers with an error status"),
+        Kind::RedirectLoop => ("redirect-loop", "The redirects go in a circle"),
+        Kind::TooManyRedirects => ("too-many-redirects", "There are too many redirects to follow"),
+        Kind::Dns => ("dns", "The host doesn't exist"),
+        Kind::Timeout => ("timeout", "The server doesn't answer in time"),
+        Kind::ConnectionRefused => ("connection-refused", "The server refuses the connection"),
+        Kind::ConnectionReset => ("connection-reset", "The server drops the connection"),
+        Kind::Tls => ("tls", "The certificate or the TLS handshake is broken"),
+        Kind::RateLimited => ("rate-limited", "The server keeps telling us to slow down"),
+        Kind::HostUnreachable => ("host-unreachable", "Too many requests to the host failed already"),
+        Kind::Unverifiable => ("unverifiable", "Bot protection keeps us from checking the page"),
+        Kind::LoginRequired => ("login-required", "The page needs a login"),
+        Kind::NotAnImage => ("not-an-image", "An image which isn't an image"),
+        Kind::MissingAnchor => ("missing-anchor", "The page has no section for the fragment"),
+        Kind::MangledUrl => ("mangled-url", "The markdown cuts the url short"),
+        Kind::BrokenBadge => ("broken-badge", "The badge says something is broken"),
+        Kind::NoSuchHeading => ("no-such-heading", "The document has no heading for the fragment"),
+        Kind::NoSuchFile => ("no-such-file", "The file isn't in the repository"),
+        Kind::UndefinedReference => ("undefined-reference", "The reference link has no definition"),
+        Kind::Duplicate => ("duplicate", "Several entries link to the same project"),
+        Kind::InvalidLink => ("invalid-link", "The link is written wrong"),
+        Kind::Other => ("broken-link", "The link is broken"),
+    }
+}
+
+fn suspect_rule(url: &UrlReport) -> (&'static str, &'static str) {
+    match url.reason {
+        Some(reason) if reason.starts_with(PARKED) => ("suspect-parked-domain", "The domain is parked, and leads to ads or worse"),
+        _ => ("suspect", "The page answers, but looks like it isn't there anymore"),
+    }
+}
+
+fn suggestion_rule(suggestion: &Suggestion, report: &Report) -> (&'static str, &'static str) {
+    let redirects = report.urls.iter().any(|url| url.url == suggestion.link && !url.redirects.is_empty());
+    match suggestion.kind {
+        SuggestionKind::MovedToHttps => ("moved-to-https", "Only the https url connects anymore"),
+        _ if redirects => ("redirect", "The link redirects, and should go where it ends up"),
+        _ => ("https-available", "The page works over https too"),
+    }
+}
+
+/// The locations of a link, which are path:line:column
+fn locations(locations: &[String]) -> Vec<Location> {
+    locations.iter().map(|location| {
+        let (uri, region) = match split_location(location) {
+            Some((path, start_line, start_column)) => (path, Some(Region {start_line, start_column})),
+            None => (location.as_str(), None),
+        };
+        Location {physical_location: PhysicalLocation {artifact_location: ArtifactLocation {uri: uri.to_string()}, region}}
+    }).collect()
+}
+
+/// The report as a SARIF 2.1.0 log, for GitHub code scanning: the failures and suspect links,
+/// and the links with a better url, each as a result of the rule for what's wrong with it
+pub fn render(report: &Report, levels: &Levels) -> String {
+    let mut rules: Vec<Rule> = vec![];
+    let mut results = vec![];
+    let mut add = |(id, description): (&'static str, &'static str), level, text, found: &[String]| {
+        let rule_index = rules.iter().position(|rule| rule.id == id).unwrap_or_else(|| {
+            rules.push(Rule {id, short_description: Message {text: description.to_string()}});
+            rules.len() - 1
+        });
+        results.push(SarifResult {rule_id: id, rule_index, level, message: Message {text}, locations: locations(found)});
+    };
+    for failure in &report.failures {
+        let mut text = failure.failure.message.clone();
+        if let Some(suggestion) = &failure.failure.suggestion {
+            text += &format!(", did you mean {}?", suggestion);
+        }
+        if let Some(snapshot) = failure.archived {
+            text += &format!(" (archived copy available from {}: {})", snapshot.date(), snapshot.url);
+        }
+        add(failure_rule(failure), levels.failure, text, &failure.failure.locations);
+    }
+    for url in report.urls.iter().filter(|url| url.state == State::Suspect) {
+        let text = format!("suspect, {}: {}", url.reason.unwrap_or_default(), url.url);
+        add(suspect_rule(url), levels.suspect, text, url.locations);
+    }
+    let better = report.suggestions.iter().filter(|suggestion| matches!(suggestion.kind, SuggestionKind::BetterUrl | SuggestionKind::MovedToHttps));
+    for suggestion in better {
+        let found = report.urls.iter().find(|url| url.url == suggestion.link).map_or(&[][..], |url| url.locations);
+        let text = format!("{} should be {}", suggestion.link, suggestion.replacement);
+        add(suggestion_rule(suggestion, report), levels.suggestion, text, found);
+    }
+    let log = Log {
+        schema: SCHEMA,
+        version: "2.1.0",
+        runs: vec![Run {
+            tool: Tool {driver: Driver {name: env!("CARGO_PKG_NAME"), version: env!("CARGO_PKG_VERSION"), rules}},
+            results,
+        }],
+    };
+    serde_json::to_string_pretty(&log).unwrap() + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::Fixture;
+
+    /// Checks the log against the SARIF 2.1.0 schema, and that each result's rule index is the
+    /// rule it names, which the schema can't tell
+    fn validate(log: &serde_json::Value) {
+        let schema = serde_json::from_str(include_str!("../testdata/sarif-schema-2.1.0.json")).unwrap();
+        let schema = jsonschema::JSONSchema::compile(&schema).unwrap();
+        if let Err(errors) = schema.validate(log) {
+            let errors: Vec<_> = errors.map(|error| format!("{}: {}", error.instance_path, error)).collect();
+            panic!("Not valid SARIF:\n{}", errors.join("\n"));
+        }
+        for run in log["runs"].as_array().unwrap() {
+            let rules = run["tool"]["driver"]["rules"].as_array().unwrap();
+            for result in run["results"].as_array().unwrap() {
+                assert_eq!(rules[result["ruleIndex"].as_u64().unwrap() as usize]["id"], result["ruleId"]);
+            }
+        }
+    }
+
+    #[test]
+    fn sarif_log() {
+        let mut fixture = Fixture::new();
+        fixture.results.suspect.insert("https://parked.example/".to_string(), format!("{} (Sedo)", PARKED));
+        fixture.locations.insert("https://parked.example/".to_string(), vec!["README.md:5:3".to_string()]);
+        let levels = Levels {suspect: Level::Error, ..Levels::default()};
+        let log: serde_json::Value = serde_json::from_str(&render(&fixture.report(), &levels)).unwrap();
+        validate(&log);
+        let results: Vec<_> = log["runs"][0]["results"].as_array().unwrap().iter()
+            .map(|result| (result["ruleId"].as_str().unwrap(), result["level"].as_str().unwrap(), result["message"]["text"].as_str().unwrap()))
+            .collect();
+        assert_eq!(results, vec![
+            ("no-such-heading", "error", "[no such heading] #usage, did you mean #usages?"),
+            ("dead-link", "error", "[404] https://b.example/"),
+            ("suspect-parked-domain", "error", "suspect, parked domain (Sedo): https://parked.example/"),
+            ("redirect", "note", "https://a.example/ should be https://a.example/home"),
+        ]);
+        let location = &log["runs"][0]["results"][0]["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "README.md");
+        assert_eq!((location["region"]["startLine"].as_u64(), location["region"]["startColumn"].as_u64()), (Some(7), Some(3)));
+        assert_eq!(log["runs"][0]["results"][3]["locations"].as_array().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,147 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+/// What's wrong with a `mailto:` link, if anything. This only looks at how it's written, whether
+/// anyone reads the mailbox is between the reader and the mail server.
+pub fn check_mailto(url: &str) -> Result<(), String> {
+    let target = &url["mailto:".len()..];
+    if target.starts_with("//") {
+        return Err("the address comes right after mailto:, without //".to_string());
+    }
+    let (addresses, query) = match target.find('?') {
+        Some(question) => (&target[..question], Some(&target[question + 1..])),
+        None => (target, None),
+    };
+    let mut count = 0;
+    for address in addresses.split(',').filter(|address| !address.is_empty()) {
+        check_address(address)?;
+        count += 1;
+    }
+    for field in query.into_iter().flat_map(|query| query.split('&')).filter(|field| !field.is_empty()) {
+        let (name, value) = match field.find('=') {
+            Some(equals) => (&field[..equals], &field[equals + 1..]),
+            None => return Err(format!("\"{}\" in the query has no value", field)),
+        };
+        if name.is_empty() {
+            return Err(format!("\"{}\" in the query has no name", field));
+        }
+        if ["to", "cc", "bcc"].contains(&name.to_lowercase().as_str()) {
+            for address in value.split(',').filter(|address| !address.is_empty()) {
+                check_address(address)?;
+                count += usize::from(name.eq_ignore_ascii_case("to"));
+            }
+        }
+    }
+    if count == 0 {
+        return Err("no address".to_string());
+    }
+    Ok(())
+}
+
+/// Checks an address the way it's written in a `mailto:` link, so percent-encoded
+fn check_address(address: &str) -> Result<(), String> {
+    lazy_static! {
+        static ref ADDRESS_REGEX: Regex = Regex::new(r"^[A-Za-z0-9.!#$%&'*+/=?^_`{|}~-]+@[A-Za-z0-9](?:[A-Za-z0-9-]*[A-Za-z0-9])?(?:\.[A-Za-z0-9](?:[A-Za-z0-9-]*[A-Za-z0-9])?)+$").unwrap();
+    }
+    let decoded = percent_encoding::percent_decode_str(address).decode_utf8_lossy();
+    if decoded.contains(char::is_whitespace) {
+        return Err(format!("\"{}\" has spaces in it", decoded));
+    }
+    if !decoded.contains('@') {
+        return Err(format!("\"{}\" has no @", decoded));
+    }
+    if !ADDRESS_REGEX.is_match(&decoded) {
+        return Err(format!("\"{}\" isn't an email address", decoded));
+    }
+    Ok(())
+}
+
+/// What's wrong with a `tel:` link, if anything: it has to be a number with at most some
+/// visual separators, and a `+` and country code unless it says where it's local to
+pub fn check_tel(url: &str) -> Result<(), String> {
+    lazy_static! {
+        static ref NUMBER_REGEX: Regex = Regex::new(r"^\+?[0-9().-]*[0-9][0-9().-]*$").unwrap();
+    }
+    let mut parts = url["tel:".len()..].split(';');
+    let number = parts.next().unwrap_or_default();
+    if number.contains(|c: char| c.is_whitespace()) || number.contains("%20") {
+        return Err(format!("\"{}\" has spaces in it, use - to separate the digits", number));
+    }
+    if !NUMBER_REGEX.is_match(number) {
+        return Err(format!("\"{}\" isn't a phone number", number));
+    }
+    if !number.starts_with('+') && !parts.any(|parameter| parameter.starts_with("phone-context=")) {
+        return Err(format!("\"{}\" needs a + and the country code", number));
+    }
+    Ok(())
+}
+
+/// What's wrong with a `data:` url. They put a whole file into the readme, which only small
+/// images (like inlined SVG badges) can get away with, and only with `max_image_size` set.
+pub fn check_data(url: &str, image: bool, max_image_size: Option<usize>) -> Result<(), String> {
+    let media_type = url["data:".len()..].split([';', ',']).next().unwrap_or_default();
+    match max_image_size {
+        Some(max) if image && media_type.starts_with("image/") => {
+            if url.len() > max {
+                return Err(format!("{} bytes inline, more than the {} allowed, link to the file instead", url.len(), max));
+            }
+            Ok(())
+        }
+        _ => Err("data: urls put the whole file into the readme, link to the file instead".to_string()),
+    }
+}
+
+/// The start of a long url, for urls like `data:` ones which are too long to show in full
+pub fn abbreviate(url: &str) -> String {
+    match url.char_indices().nth(60) {
+        Some((end, _)) => format!("{}...", &url[..end]),
+        None => url.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mailto_links() {
+        for url in &["mailto:someone@example.com", "mailto:a@example.com,b.c+d@mail.example.org",
+                     "mailto:someone@example.com?subject=Hello%20there&body=Hi", "mailto:?to=someone@example.com",
+                     "mailto:some%2Bone@example.com"] {
+            assert_eq!(check_mailto(url), Ok(()), "{}", url);
+        }
+        let error = |url| check_mailto(url).unwrap_err();
+        assert_eq!(error("mailto://someone@example.com"), "the address comes right after mailto:, without //");
+        assert_eq!(error("mailto:someone.example.com"), "\"someone.example.com\" has no @");
+        assert_eq!(error("mailto:some one@example.com"), "\"some one@example.com\" has spaces in it");
+        assert_eq!(error("mailto:someone%20@example.com"), "\"someone @example.com\" has spaces in it");
+        assert_eq!(error("mailto:someone@localhost"), "\"someone@localhost\" isn't an email address");
+        assert_eq!(error("mailto:?subject=Hello"), "no address");
+        assert_eq!(error("mailto:someone@example.com?subject"), "\"subject\" in the query has no value");
+        assert_eq!(error("mailto:a@example.com?cc=b@"), "\"b@\" isn't an email address");
+    }
+
+    #[test]
+    fn tel_links() {
+        for url in &["tel:+1-201-555-0123", "tel:+49(0)30.1234567", "tel:7042;phone-context=example.com"] {
+            assert_eq!(check_tel(url), Ok(()), "{}", url);
+        }
+        let error = |url| check_tel(url).unwrap_err();
+        assert_eq!(error("tel:+1 201 555 0123"), "\"+1 201 555 0123\" has spaces in it, use - to separate the digits");
+        assert_eq!(error("tel:555-0123"), "\"555-0123\" needs a + and the country code");
+        assert_eq!(error("tel:call-me"), "\"call-me\" isn't a phone number");
+        assert_eq!(error("tel:"), "\"\" isn't a phone number");
+    }
+
+    #[test]
+    fn data_urls() {
+        let badge = "data:image/svg+xml;base64,PHN2ZyB4bWxucz0iaHR0cDovL3d3dy53My5vcmcvMjAwMC9zdmciLz4=";
+        assert_eq!(check_data(badge, true, Some(1024)), Ok(()));
+        assert_eq!(check_data(badge, true, None), Err("data: urls put the whole file into the readme, link to the file instead".to_string()));
+        assert_eq!(check_data(badge, false, Some(1024)), Err("data: urls put the whole file into the readme, link to the file instead".to_string()));
+        assert!(check_data("data:text/html,<h1>hi</h1>", true, Some(1024)).is_err());
+        assert_eq!(check_data(badge, true, Some(20)), Err(format!("{} bytes inline, more than the 20 allowed, link to the file instead", badge.len())));
+        assert_eq!(abbreviate(badge), format!("{}...", &badge[..60]));
+        assert_eq!(abbreviate("data:,short"), "data:,short");
+    }
+}
@@ -0,0 +1,122 @@
+use crate::anchors::Heading;
+use crate::local::wildcard_matches;
+
+/// The headings of a document, to tell which section something is in. Each one starts a section
+/// that lasts until the next heading of the same or a higher level.
+#[derive(Debug, Default)]
+pub struct Outline<'a> {
+    headings: &'a [Heading],
+}
+
+impl<'a> Outline<'a> {
+    pub fn new(headings: &'a [Heading]) -> Outline<'a> {
+        Outline {headings}
+    }
+
+    /// The titles of the sections the offset is in, outermost first
+    pub fn path(&self, offset: usize) -> Vec<&str> {
+        let mut path: Vec<&Heading> = vec![];
+        for heading in self.headings.iter().take_while(|heading| heading.offset <= offset) {
+            while path.last().is_some_and(|last| last.level >= heading.level) {
+                path.pop();
+            }
+            path.push(heading);
+        }
+        path.into_iter().map(|heading| heading.title.as_str()).collect()
+    }
+
+    pub fn titles(&self) -> impl Iterator<Item = &str> {
+        self.headings.iter().map(|heading| heading.title.as_str())
+    }
+}
+
+/// Which sections `--section` and `--skip-section` leave to check. A section counts along with
+/// everything under it, and the names can have `*` and `?` like file names.
+#[derive(Debug, Default)]
+pub struct Scope {
+    sections: Vec<String>,
+    skipped: Vec<String>,
+}
+
+impl Scope {
+    pub fn new(sections: &[String], skipped: &[String]) -> Scope {
+        // Nobody remembers how a heading was capitalized
+        let lowercase = |names: &[String]| names.iter().map(|name| name.to_lowercase()).collect();
+        Scope {sections: lowercase(sections), skipped: lowercase(skipped)}
+    }
+
+    /// Whether the whole document gets checked
+    pub fn is_everything(&self) -> bool {
+        self.sections.is_empty() && self.skipped.is_empty()
+    }
+
+    /// Whether to check what's in the sections with these titles, outermost first
+    pub fn includes(&self, path: &[&str]) -> bool {
+        let any = |patterns: &[String]| path.iter().any(|title| matches(patterns, title));
+        (self.sections.is_empty() || any(&self.sections)) && !any(&self.skipped)
+    }
+
+    /// Whether `--section` names this heading
+    pub fn selects(&self, title: &str) -> bool {
+        matches(&self.sections, title)
+    }
+
+    /// Whether `--skip-section` names this heading
+    pub fn skips(&self, title: &str) -> bool {
+        matches(&self.skipped, title)
+    }
+}
+
+fn matches(patterns: &[String], title: &str) -> bool {
+    let title = title.to_lowercase();
+    patterns.iter().any(|pattern| wildcard_matches(pattern, &title))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::anchors::Anchors;
+
+    const SOURCE: &str = "\
+[intro](https://intro.example)
+# Awesome Rust
+## Applications
+### Web programming
+[a](https://a.example)
+#### `actix` things
+[b](https://b.example)
+### Games
+[c](https://c.example)
+## Libraries
+[d](https://d.example)
+";
+
+    #[test]
+    fn section_paths() {
+        let anchors = Anchors::from_markdown(SOURCE);
+        let outline = Outline::new(anchors.headings());
+        let path = |link| outline.path(SOURCE.find(link).unwrap());
+        assert_eq!(path("[intro]"), Vec::<&str>::new());
+        assert_eq!(path("[a]"), vec!["Awesome Rust", "Applications", "Web programming"]);
+        assert_eq!(path("[b]"), vec!["Awesome Rust", "Applications", "Web programming", "actix things"]);
+        assert_eq!(path("[c]"), vec!["Awesome Rust", "Applications", "Games"]);
+        assert_eq!(path("[d]"), vec!["Awesome Rust", "Libraries"]);
+    }
+
+    #[test]
+    fn scopes() {
+        let anchors = Anchors::from_markdown(SOURCE);
+        let outline = Outline::new(anchors.headings());
+        let checked = |scope: Scope| -> Vec<&str> {
+            ["[intro]", "[a]", "[b]", "[c]", "[d]"].iter().cloned()
+                .filter(|link| scope.includes(&outline.path(SOURCE.find(link).unwrap())))
+                .collect()
+        };
+        let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+        assert_eq!(checked(Scope::default()), vec!["[intro]", "[a]", "[b]", "[c]", "[d]"]);
+        assert_eq!(checked(Scope::new(&names(&["web programming"]), &[])), vec!["[a]", "[b]"]);
+        assert_eq!(checked(Scope::new(&names(&["Web*", "Lib?aries"]), &[])), vec!["[a]", "[b]", "[d]"]);
+        assert_eq!(checked(Scope::new(&names(&["Applications"]), &names(&["*things"]))), vec!["[a]", "[c]"]);
+        assert_eq!(checked(Scope::new(&[], &names(&["Awesome Rust"]))), vec!["[intro]"]);
+    }
+}
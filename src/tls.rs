@@ -0,0 +1,70 @@
+use rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use rustls::{Certificate, ClientConfig, Error, OwnedTrustAnchor, RootCertStore, ServerName};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time;
+
+/// When each host's certificate expires, as far as we have connected to it
+pub type Expiries = Arc<Mutex<BTreeMap<String, time::SystemTime>>>;
+
+/// Verifies certificates like reqwest would, but notes down when they expire
+struct ExpiryVerifier {
+    inner: WebPkiVerifier,
+    accept_invalid_certs: bool,
+    expiries: Expiries,
+}
+
+impl ServerCertVerifier for ExpiryVerifier {
+    fn verify_server_cert(&self, end_entity: &Certificate, intermediates: &[Certificate], server_name: &ServerName,
+                          scts: &mut dyn Iterator<Item = &[u8]>, ocsp_response: &[u8], now: time::SystemTime) -> Result<ServerCertVerified, Error> {
+        let host = match server_name {
+            ServerName::DnsName(name) => name.as_ref().to_string(),
+            ServerName::IpAddress(ip) => ip.to_string(),
+            _ => String::new(),
+        };
+        if let Some(not_after) = not_after(&end_entity.0) {
+            self.expiries.lock().unwrap().insert(host, not_after);
+        }
+        match self.inner.verify_server_cert(end_entity, intermediates, server_name, scts, ocsp_response, now) {
+            Err(_) if self.accept_invalid_certs => Ok(ServerCertVerified::assertion()),
+            res => res,
+        }
+    }
+}
+
+/// The same setup reqwest uses for rustls, with our verifier plugged in
+pub fn config(accept_invalid_certs: bool, expiries: Expiries) -> ClientConfig {
+    let mut roots = RootCertStore::empty();
+    roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(anchor.subject, anchor.spki, anchor.name_constraints)
+    }));
+    let mut config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots.clone())
+        .with_no_client_auth();
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    config.dangerous().set_certificate_verifier(Arc::new(ExpiryVerifier {
+        inner: WebPkiVerifier::new(roots, None),
+        accept_invalid_certs,
+        expiries,
+    }));
+    config
+}
+
+/// When a DER encoded X.509 certificate expires. webpki checks it, but doesn't tell us.
+fn not_after(der: &[u8]) -> Option<time::SystemTime> {
+    let (_, certificate) = x509_parser::parse_x509_certificate(der).ok()?;
+    Some(certificate.validity().not_after.to_datetime().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn certificate_expiry() {
+        let certs = rustls_pemfile::certs(&mut &include_bytes!("../testdata/localhost.crt")[..]).unwrap();
+        let expiry = not_after(&certs[0]).unwrap();
+        assert_eq!(httpdate::fmt_http_date(expiry), "Fri, 20 Sep 2126 18:57:37 GMT");
+    }
+}
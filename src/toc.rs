@@ -0,0 +1,284 @@
+use pulldown_cmark::{Event, Parser, Tag};
+use crate::anchors::{Anchors, Heading};
+
+/// An entry of a table of contents
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub offset: usize,
+    /// How deep it's nested, 0 for the outermost entries
+    pub depth: usize,
+    pub title: String,
+    /// Without the `#`, and decoded
+    pub fragment: String,
+}
+
+/// The table of contents of a document: the list between `<!-- toc -->` and `<!-- tocstop -->`
+/// if there's one, otherwise the first list of nothing but links to headings
+#[derive(Debug, Clone, PartialEq)]
+pub struct Toc {
+    pub entries: Vec<Entry>,
+    /// Where the list ends, as the document's headings after that are the ones it lists
+    pub end: usize,
+    /// How the list is written, to write a new one the same way: what comes before the
+    /// outermost entries' bullets, the bullet and how far each level is indented
+    indent: String,
+    bullet: char,
+    step: usize,
+}
+
+/// Where we are in an entry, relative to the link it starts with
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Position {
+    Before,
+    In,
+    After,
+}
+
+/// Something wrong with a table of contents, and where
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    pub offset: usize,
+    pub message: String,
+}
+
+impl Toc {
+    pub fn find(source: &str) -> Option<Toc> {
+        let marker = source.find("<!-- toc -->");
+        let mut depth = 0;
+        let mut entries: Vec<Entry> = vec![];
+        // The entries we're in, innermost last, with whether we're before, in or after the link
+        // they start with
+        let mut open: Vec<(usize, Position)> = vec![];
+        let mut only_fragments = true;
+        for (event, range) in Parser::new(source).into_offset_iter() {
+            match event {
+                Event::Start(Tag::List(_)) => depth += 1,
+                Event::End(Tag::List(_)) => {
+                    depth -= 1;
+                    if depth > 0 {
+                        continue;
+                    }
+                    let found = match marker {
+                        Some(marker) => entries.first().is_some_and(|first| first.offset > marker),
+                        None => only_fragments && entries.len() > 1,
+                    };
+                    if found {
+                        return Some(Toc::new(source, entries, range.end));
+                    }
+                    entries.clear();
+                    only_fragments = true;
+                }
+                Event::Start(Tag::Item) => {
+                    open.push((entries.len(), Position::Before));
+                    entries.push(Entry {offset: range.start, depth: depth - 1, title: String::new(), fragment: String::new()});
+                }
+                Event::End(Tag::Item) => {
+                    only_fragments &= matches!(open.pop(), Some((_, Position::After)));
+                }
+                Event::Start(Tag::Link(_, url, _)) => {
+                    if let Some((index, position @ Position::Before)) = open.last_mut() {
+                        match url.strip_prefix('#') {
+                            Some(fragment) => {
+                                entries[*index].fragment = percent_encoding::percent_decode_str(fragment).decode_utf8_lossy().to_string();
+                                *position = Position::In;
+                            }
+                            None => {
+                                only_fragments = false;
+                                *position = Position::After;
+                            }
+                        }
+                    }
+                }
+                Event::End(Tag::Link(..)) => {
+                    if let Some((_, position @ Position::In)) = open.last_mut() {
+                        *position = Position::After;
+                    }
+                }
+                Event::Text(text) | Event::Code(text) => match open.last() {
+                    Some((index, Position::In)) => entries[*index].title.push_str(&text),
+                    Some((_, Position::Before)) => only_fragments &= text.trim().is_empty(),
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+        None
+    }
+
+    fn new(source: &str, entries: Vec<Entry>, end: usize) -> Toc {
+        // Items of a nested list start at their bullet, but the outermost ones at the start of the line
+        let indentation = |offset: usize| {
+            let line = &source[source[..offset].rfind('\n').map_or(0, |newline| newline + 1)..];
+            &line[..line.len() - line.trim_start_matches(' ').len()]
+        };
+        let first = &entries[0];
+        let indent = indentation(first.offset).to_string();
+        let bullet = source[first.offset + indent.len()..].chars().next().unwrap_or('-');
+        let step = entries.iter().find(|entry| entry.depth == first.depth + 1)
+            .map(|entry| indentation(entry.offset).len().saturating_sub(indent.len()))
+            .filter(|step| *step > 0)
+            .unwrap_or(2);
+        Toc {entries, end, indent, bullet, step}
+    }
+
+    /// The headings after the table of contents which it should list: the highest level ones
+    /// there, and the levels below as deep as it goes
+    fn expected<'a>(&self, headings: &'a [Heading]) -> (Vec<&'a Heading>, u32) {
+        let after: Vec<_> = headings.iter().filter(|heading| heading.offset >= self.end).collect();
+        let top = after.iter().map(|heading| heading.level).min().unwrap_or(1);
+        let deepest = self.entries.iter().map(|entry| entry.depth).max().unwrap_or_default() as u32;
+        (after.into_iter().filter(|heading| heading.level <= top + deepest).collect(), top)
+    }
+
+    /// Entries for headings which aren't there, or which are in the wrong place, and headings
+    /// without an entry
+    pub fn check(&self, headings: &[Heading]) -> Vec<Problem> {
+        let (expected, top) = self.expected(headings);
+        let mut anchors = Anchors::default();
+        for heading in &expected {
+            anchors.add(&heading.anchor);
+        }
+        let mut problems = vec![];
+        // Headings with an entry, and ones a broken entry looks like it's meant for
+        let mut listed = vec![];
+        let mut suggested = vec![];
+        // The heading of the entry furthest into the document so far
+        let mut furthest: Option<(usize, &Entry)> = None;
+        for entry in &self.entries {
+            let found = expected.iter().position(|heading| heading.anchor == entry.fragment || heading.anchor == entry.fragment.to_lowercase());
+            let index = match found {
+                Some(index) => index,
+                None => {
+                    let suggestion = match anchors.nearest(&entry.fragment) {
+                        Some(nearest) => {
+                            suggested.push(nearest.to_string());
+                            format!(", did you mean #{}?", nearest)
+                        }
+                        None => String::new(),
+                    };
+                    problems.push(Problem {offset: entry.offset, message: format!("#{} has no heading{}", entry.fragment, suggestion)});
+                    continue;
+                }
+            };
+            listed.push(index);
+            let depth = (expected[index].level - top) as usize;
+            if entry.depth != depth {
+                let message = format!("{} is indented like an h{}, but its heading is an h{}", entry.title, top as usize + entry.depth, expected[index].level);
+                problems.push(Problem {offset: entry.offset, message});
+            }
+            match furthest {
+                Some((furthest, before)) if index < furthest => {
+                    problems.push(Problem {offset: entry.offset, message: format!("{} is out of order, its heading comes before {}", entry.title, before.title)});
+                }
+                _ => furthest = Some((index, entry)),
+            }
+        }
+        for (index, heading) in expected.iter().enumerate().filter(|(index, _)| !listed.contains(index)) {
+            if suggested.contains(&heading.anchor) {
+                continue;
+            }
+            // Right after the entry for the heading before it, if it has one
+            let previous = (0..index).rev().find_map(|previous| {
+                let anchor = &expected[previous].anchor;
+                self.entries.iter().find(|entry| entry.fragment.to_lowercase() == *anchor)
+            });
+            let line = self.line(heading, top);
+            let message = match previous {
+                Some(previous) => format!("{} is missing, add `{}` after {}", heading.title, line.trim_start(), previous.title),
+                None => format!("{} is missing, add `{}` at the start", heading.title, line.trim_start()),
+            };
+            problems.push(Problem {offset: previous.unwrap_or(&self.entries[0]).offset, message});
+        }
+        problems.sort_by_key(|problem| problem.offset);
+        problems
+    }
+
+    /// The entry for a heading, written like the others
+    fn line(&self, heading: &Heading, top: u32) -> String {
+        let depth = (heading.level - top) as usize;
+        format!("{}{}{} [{}](#{})", self.indent, " ".repeat(self.step * depth), self.bullet, heading.title, heading.anchor)
+    }
+
+    /// The table of contents as it should be, to paste over the old one
+    pub fn render(&self, headings: &[Heading]) -> String {
+        let (expected, top) = self.expected(headings);
+        expected.iter().map(|heading| self.line(heading, top) + "\n").collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lines::Lines;
+
+    fn headings(source: &str) -> Vec<Heading> {
+        Anchors::from_markdown(source).headings().to_vec()
+    }
+
+    const README: &str = "\
+# Awesome Rust
+
+## Table of contents
+
+  - [Applications](#applications)
+    - [Audio](#audio)
+    - [Games](#games)
+  - [Libraries](#libraries)
+    - [Audio](#audio-1)
+      - [Codecs](#codecs)
+
+## Applications
+
+* [ncspot](https://github.com/hrkfdn/ncspot) — Cross-platform ncurses Spotify client.
+
+### Audio
+
+### Games
+
+## Libraries
+
+### Audio
+
+#### Codecs
+";
+
+    #[test]
+    fn finds_the_table_of_contents() {
+        let toc = Toc::find(README).unwrap();
+        let entries: Vec<_> = toc.entries.iter().map(|entry| (entry.depth, entry.title.as_str(), entry.fragment.as_str())).collect();
+        assert_eq!(entries, vec![
+            (0, "Applications", "applications"),
+            (1, "Audio", "audio"),
+            (1, "Games", "games"),
+            (0, "Libraries", "libraries"),
+            (1, "Audio", "audio-1"),
+            (2, "Codecs", "codecs"),
+        ]);
+        assert_eq!(toc.check(&headings(README)), vec![]);
+        assert_eq!(toc.render(&headings(README)), README[README.find("  - [App").unwrap()..README.find("\n\n## Applications").unwrap() + 1]);
+        assert_eq!(Toc::find("* [serde](https://serde.rs)\n* [Audio](#audio)\n"), None);
+        // With markers, it's the list after them, whatever it looks like
+        let marked = Toc::find("- [Not it](#no)\n- [Not it](#no)\n\n<!-- toc -->\n* [Intro](#intro)\n<!-- tocstop -->\n\n# Intro\n").unwrap();
+        assert_eq!(marked.entries.len(), 1);
+    }
+
+    #[test]
+    fn drifted_table_of_contents() {
+        let source = README
+            .replace("    - [Games](#games)\n", "")
+            .replace("  - [Applications](#applications)\n    - [Audio](#audio)\n", "  - [Audio](#audio)\n  - [Applications](#applications)\n")
+            .replace("#codecs", "#codec")
+            .replace("## Libraries", "## Libraries\n\n### Text");
+        let toc = Toc::find(&source).unwrap();
+        let problems: Vec<_> = toc.check(&headings(&source)).into_iter()
+            .map(|problem| (Lines::new(&source).line(problem.offset), problem.message))
+            .collect();
+        assert_eq!(problems, vec![
+            (5, "Audio is indented like an h2, but its heading is an h3".to_string()),
+            (5, "Games is missing, add `- [Games](#games)` after Audio".to_string()),
+            (6, "Applications is out of order, its heading comes before Audio".to_string()),
+            (7, "Text is missing, add `- [Text](#text)` after Libraries".to_string()),
+            (9, "#codec has no heading, did you mean #codecs?".to_string()),
+        ]);
+    }
+}
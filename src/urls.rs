@@ -0,0 +1,338 @@
+use lazy_static::lazy_static;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use regex::Regex;
+
+/// Punctuation which ends a sentence rather than the url in it
+const TRAILING: &[char] = &['.', ',', ';', ':', '!', '?', '\'', '"', '*', '_'];
+
+/// Cuts off whatever the surrounding text glued onto the end of a url: punctuation, and closing
+/// parentheses without an opening one in the url, as in "(see https://example.com)". Wikipedia
+/// style urls keep theirs.
+pub fn trim_trailing(url: &str) -> &str {
+    let mut url = url;
+    loop {
+        let trimmed = url.trim_end_matches(TRAILING);
+        let trimmed = match trimmed.strip_suffix(')') {
+            Some(rest) if trimmed.matches('(').count() < trimmed.matches(')').count() => rest,
+            _ => trimmed,
+        };
+        if trimmed == url {
+            return url;
+        }
+        url = trimmed;
+    }
+}
+
+/// The http(s) urls written out in a piece of text, with their byte offset in it. Only urls
+/// with a scheme count, as "example.com" in a sentence is as likely to be about the name.
+pub fn in_text(text: &str) -> Vec<(usize, &str)> {
+    lazy_static! {
+        static ref URL_REGEX: Regex = Regex::new(r#"\bhttps?://[^\s<>"`]+"#).unwrap();
+    }
+    URL_REGEX.find_iter(text)
+        .map(|found| (found.start(), trim_trailing(found.as_str())))
+        // Nothing but the scheme is left of "see http://."
+        .filter(|(_, url)| !url.ends_with("://"))
+        .collect()
+}
+
+/// The lines of a plain list of urls (`--format urls`), with their byte offset and (1-based)
+/// line number. Blank lines and lines starting with `#` are left out, and lines which aren't an
+/// http(s) url are an error with the line.
+pub fn in_list(source: &str) -> Vec<(usize, usize, Result<&str, &str>)> {
+    let mut offset = 0;
+    let mut listed = vec![];
+    for (number, line) in source.split_inclusive('\n').enumerate() {
+        let start = offset + line.len() - line.trim_start().len();
+        offset += line.len();
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let url = if line.starts_with("http") { Ok(line) } else { Err(line) };
+        listed.push((start, number + 1, url));
+    }
+    listed
+}
+
+/// Whether the url is on a host reserved for examples (RFC 2606) or on this machine, so it's a
+/// placeholder, as in "git clone https://example.com/yourname/yourrepo"
+pub fn placeholder(url: &str) -> bool {
+    let host = match url::Url::parse(url).ok().and_then(|url| url.host_str().map(str::to_lowercase)) {
+        Some(host) => host,
+        None => return false,
+    };
+    let host = host.trim_end_matches('.');
+    ["example.com", "example.org", "example.net", "example", "test", "invalid", "localhost"].iter()
+        .any(|reserved| host == *reserved || host.ends_with(&format!(".{}", reserved)))
+        || ["127.0.0.1", "0.0.0.0", "[::1]"].contains(&host)
+}
+
+/// The url the way we keep track of it: the scheme and host in lowercase, without the default
+/// port, and with `/` for an empty path. The rest stays the way it's written, since servers are
+/// free to tell `%7E` from `~`, or `?a=1&b=2` from `?b=2&a=1`.
+pub fn normalize(url: &str) -> String {
+    let (scheme, rest) = match url.find("://") {
+        Some(end) if url[..end].eq_ignore_ascii_case("http") || url[..end].eq_ignore_ascii_case("https") => (url[..end].to_lowercase(), &url[end + 3..]),
+        _ => return url.to_string(),
+    };
+    let authority_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let (authority, rest) = rest.split_at(authority_end);
+    // Only the host is case insensitive, not a user name in front of it
+    let (userinfo, host) = match authority.rfind('@') {
+        Some(at) => authority.split_at(at + 1),
+        None => ("", authority),
+    };
+    let mut host = host.to_lowercase();
+    let default_port = if scheme == "http" { ":80" } else { ":443" };
+    if host.ends_with(default_port) {
+        host.truncate(host.len() - default_port.len());
+    } else if host.ends_with(':') {
+        host.pop();
+    }
+    let slash = if rest.starts_with('/') { "" } else { "/" };
+    format!("{}://{}{}{}{}", scheme, userinfo, host, slash, rest)
+}
+
+/// Where YouTube tells whether an embedded video is still there, for the url of an embed like
+/// `https://www.youtube.com/embed/<id>`. The embed itself loads fine either way.
+pub fn youtube_oembed(url: &str) -> Option<String> {
+    lazy_static! {
+        static ref EMBED_REGEX: Regex = Regex::new(r"^https?://(?:www\.)?youtube(?:-nocookie)?\.com/embed/([A-Za-z0-9_-]+)").unwrap();
+    }
+    let id = &EMBED_REGEX.captures(url)?[1];
+    Some(format!("https://www.youtube.com/oembed?format=json&url=https://www.youtube.com/watch?v={}", id))
+}
+
+/// What gets encoded when turning an IRI into a URI, besides everything that isn't ASCII
+const URI_ENCODED: &AsciiSet = &CONTROLS.add(b' ');
+
+/// The url as a URI: an internationalized host in punycode, as in `xn--bcher-kva.example` for
+/// `bücher.example`, and the non-ASCII characters after it percent-encoded as UTF-8, as in
+/// `/wiki/%C3%89tude` for `/wiki/Étude`. What's encoded already stays as it is.
+pub fn to_uri(url: &str) -> String {
+    let (prefix, authority, rest) = match split_authority(url) {
+        Some(split) => split,
+        None => return utf8_percent_encode(url, URI_ENCODED).to_string(),
+    };
+    // The host is between any user name and any port
+    let host_start = authority.rfind('@').map_or(0, |at| at + 1);
+    let host_end = authority.rfind(':').filter(|colon| *colon > host_start && !authority.ends_with(']')).unwrap_or(authority.len());
+    let host = &authority[host_start..host_end];
+    let host = match idna::domain_to_ascii(host) {
+        Ok(ascii) if !host.is_ascii() => ascii,
+        _ => host.to_string(),
+    };
+    format!("{}{}{}{}{}", prefix, &authority[..host_start], host, &authority[host_end..], utf8_percent_encode(rest, URI_ENCODED))
+}
+
+/// Splits a url with a host into what's before the host, the host with anything around it up
+/// to the path, and the rest
+fn split_authority(url: &str) -> Option<(&str, &str, &str)> {
+    let start = url.find("://")? + 3;
+    let end = start + url[start..].find(['/', '?', '#']).unwrap_or(url.len() - start);
+    Some((&url[..start], &url[start..end], &url[end..]))
+}
+
+/// The scripts which have letters that look like Latin ones
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Script {
+    Latin,
+    Greek,
+    Cyrillic,
+}
+
+impl Script {
+    fn of(c: char) -> Option<Script> {
+        match c {
+            'a'..='z' | 'A'..='Z' | '\u{c0}'..='\u{24f}' => Some(Script::Latin),
+            '\u{370}'..='\u{3ff}' => Some(Script::Greek),
+            '\u{400}'..='\u{52f}' => Some(Script::Cyrillic),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Script::Latin => "Latin",
+            Script::Greek => "Greek",
+            Script::Cyrillic => "Cyrillic",
+        }
+    }
+}
+
+/// Why the host of the url might be pretending to be another one, like `аpple.com` with a
+/// Cyrillic `а`: a part of the name mixes letters of scripts which have look-alikes of each
+/// other. Hosts written in punycode count with what they show up as in a browser.
+pub fn homograph(url: &str) -> Option<String> {
+    let (_, authority, _) = split_authority(url)?;
+    let host = authority.rsplit('@').next().unwrap_or_default();
+    let (host, _) = idna::domain_to_unicode(host);
+    host.split('.').find_map(|label| {
+        let mut scripts: Vec<Script> = vec![];
+        for script in label.chars().filter_map(Script::of) {
+            if !scripts.contains(&script) {
+                scripts.push(script);
+            }
+        }
+        if scripts.len() < 2 {
+            return None;
+        }
+        let names: Vec<_> = scripts.iter().map(|script| script.name()).collect();
+        Some(format!("{} mixes {} letters", label, names.join(" and ")))
+    })
+}
+
+/// Spaces which html attributes pick up at the end of a url, along with the real whitespace
+const TRAILING_SPACES: &[&str] = &["&nbsp;", "%C2%A0", "%c2%a0", "%20"];
+
+/// What the author probably meant, for a url that the text around it got into: one with a
+/// backslash that was meant to escape something, punctuation or spaces at the end, or with
+/// parentheses that don't match up because the closing one ended the link (or one too many
+/// stayed in it). Percent-encoded parentheses don't count, that's the way to write them which
+/// always works.
+pub fn repaired(url: &str) -> Option<String> {
+    let mut repaired = url.replace('\\', "");
+    loop {
+        let trimmed = repaired.trim_matches(char::is_whitespace);
+        let trimmed = trim_trailing(TRAILING_SPACES.iter().fold(trimmed, |trimmed, space| trimmed.strip_suffix(space).unwrap_or(trimmed)));
+        if trimmed == repaired {
+            break;
+        }
+        repaired = trimmed.to_string();
+    }
+    let opening = repaired.matches('(').count();
+    let closing = repaired.matches(')').count();
+    if opening > closing {
+        repaired.push_str(&")".repeat(opening - closing));
+    }
+    Some(repaired).filter(|repaired| repaired != url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bare_urls() {
+        let text = "See https://example.com/docs. Or (http://example.org/a), \
+                    https://en.wikipedia.org/wiki/Rust_(programming_language)! and \"https://quoted.example\" \
+                    but not example.net, ftp://example.com or https://.";
+        let urls: Vec<_> = in_text(text).into_iter().map(|(_, url)| url).collect();
+        assert_eq!(urls, vec![
+            "https://example.com/docs",
+            "http://example.org/a",
+            "https://en.wikipedia.org/wiki/Rust_(programming_language)",
+            "https://quoted.example",
+        ]);
+        assert_eq!(in_text(text)[0].0, 4);
+    }
+
+    #[test]
+    fn url_lists() {
+        let source = "# Checked every night\nhttps://example.com/\n\n   \n  http://example.org/a  \r\nexample.net\n#https://commented.example/\nhttps://last.example";
+        assert_eq!(in_list(source), vec![
+            (source.find("https://example.com/").unwrap(), 2, Ok("https://example.com/")),
+            (source.find("http://example.org/a").unwrap(), 5, Ok("http://example.org/a")),
+            (source.find("example.net").unwrap(), 6, Err("example.net")),
+            (source.find("https://last.example").unwrap(), 8, Ok("https://last.example")),
+        ]);
+        assert!(in_list("").is_empty());
+    }
+
+    #[test]
+    fn placeholders() {
+        for url in &["https://example.com/yourname/yourrepo", "http://www.example.org", "http://localhost:8080/",
+                     "http://127.0.0.1:3000", "http://[::1]/", "https://my-app.test/", "https://EXAMPLE.COM."] {
+            assert!(placeholder(url), "{}", url);
+        }
+        for url in &["https://github.com/rust-lang/rust", "https://example.community/", "https://notexample.com/"] {
+            assert!(!placeholder(url), "{}", url);
+        }
+    }
+
+    #[test]
+    fn normalized() {
+        let cases = [
+            ("https://example.com", "https://example.com/"),
+            ("HTTPS://EXAMPLE.COM/Path", "https://example.com/Path"),
+            ("http://example.com:80/a", "http://example.com/a"),
+            ("https://example.com:443?q", "https://example.com/?q"),
+            ("https://example.com:8443/", "https://example.com:8443/"),
+            ("http://[::1]:80/", "http://[::1]/"),
+            ("https://User@Example.com#Top", "https://User@example.com/#Top"),
+            ("https://example.com/%7e?b=2&a=1", "https://example.com/%7e?b=2&a=1"),
+            ("mailto:someone@example.com", "mailto:someone@example.com"),
+            ("README.md#intro", "README.md#intro"),
+        ];
+        for (url, normalized) in &cases {
+            assert_eq!(normalize(url), *normalized, "{}", url);
+        }
+    }
+
+    #[test]
+    fn repaired_urls() {
+        let cases = [
+            ("https://en.wikipedia.org/wiki/Rust_(programming_language", Some("https://en.wikipedia.org/wiki/Rust_(programming_language)")),
+            ("https://en.wikipedia.org/wiki/Rust_(programming_language))", Some("https://en.wikipedia.org/wiki/Rust_(programming_language)")),
+            ("https://en.wikipedia.org/wiki/Rust_\\(programming_language\\)", Some("https://en.wikipedia.org/wiki/Rust_(programming_language)")),
+            ("https://en.wikipedia.org/wiki/Fish_(Unix_shell)", None),
+            ("https://en.wikipedia.org/wiki/Rust_%28programming_language%29", None),
+            ("https://en.wikipedia.org/wiki/Rust_%28programming_language", None),
+            ("https://example.com/a)b", None),
+            ("https://example.com/docs.", Some("https://example.com/docs")),
+            ("https://example.com/docs;", Some("https://example.com/docs")),
+            ("https://example.com/a,b,", Some("https://example.com/a,b")),
+            ("https://example.com/docs\u{a0}", Some("https://example.com/docs")),
+            ("https://example.com/docs&nbsp;", Some("https://example.com/docs")),
+            (" https://example.com/docs%20\n", Some("https://example.com/docs")),
+            ("https://en.wikipedia.org/wiki/Rust_(programming_language).", Some("https://en.wikipedia.org/wiki/Rust_(programming_language)")),
+        ];
+        for (url, repaired_url) in &cases {
+            assert_eq!(repaired(url).as_deref(), *repaired_url, "{}", url);
+        }
+        let url = "https://en.wikipedia.org/wiki/Rust_%28programming_language%29";
+        assert_eq!(normalize(url), url);
+        assert_eq!(in_text(&format!("({})", url))[0].1, url);
+    }
+
+    #[test]
+    fn uris() {
+        let cases = [
+            ("https://example.com/wiki/Étude", "https://example.com/wiki/%C3%89tude"),
+            ("https://zh.wikipedia.org/wiki/编程语言", "https://zh.wikipedia.org/wiki/%E7%BC%96%E7%A8%8B%E8%AF%AD%E8%A8%80"),
+            ("https://example.com/docs?q=café#résumé", "https://example.com/docs?q=caf%C3%A9#r%C3%A9sum%C3%A9"),
+            ("https://example.com/#日本語のドキュメント", "https://example.com/#%E6%97%A5%E6%9C%AC%E8%AA%9E%E3%81%AE%E3%83%89%E3%82%AD%E3%83%A5%E3%83%A1%E3%83%B3%E3%83%88"),
+            ("https://example.com/%C3%89tude%20a b", "https://example.com/%C3%89tude%20a%20b"),
+            ("https://example.com/plain?a=1&b=%2F", "https://example.com/plain?a=1&b=%2F"),
+            ("https://example.com", "https://example.com"),
+            ("https://程序员.example/路径", "https://xn--jsrp1vc5w.example/%E8%B7%AF%E5%BE%84"),
+            ("https://user@bücher.example:8080/", "https://user@xn--bcher-kva.example:8080/"),
+            ("https://xn--bcher-kva.example/", "https://xn--bcher-kva.example/"),
+            ("http://[::1]:8080/é", "http://[::1]:8080/%C3%A9"),
+        ];
+        for (url, uri) in &cases {
+            assert_eq!(to_uri(url), *uri, "{}", url);
+        }
+        assert_eq!(idna::domain_to_unicode("xn--jsrp1vc5w.example").0, "程序员.example");
+    }
+
+    #[test]
+    fn homographs() {
+        assert_eq!(homograph("https://www.\u{430}pple.com/"), Some("\u{430}pple mixes Cyrillic and Latin letters".to_string()));
+        // The same, the way it would be written in punycode
+        assert_eq!(homograph(&to_uri("https://www.\u{430}pple.com/")), homograph("https://www.\u{430}pple.com/"));
+        assert_eq!(homograph("https://ρaypal.example"), Some("ρaypal mixes Greek and Latin letters".to_string()));
+        for url in &["https://www.apple.com/", "https://程序员.example", "https://bücher.example", "https://яндекс.рф/", "mailto:a@b"] {
+            assert_eq!(homograph(url), None, "{}", url);
+        }
+    }
+
+    #[test]
+    fn youtube_embeds() {
+        assert_eq!(youtube_oembed("https://www.youtube.com/embed/dQw4w9WgXcQ?autoplay=1").as_deref(),
+                   Some("https://www.youtube.com/oembed?format=json&url=https://www.youtube.com/watch?v=dQw4w9WgXcQ"));
+        assert!(youtube_oembed("https://www.youtube-nocookie.com/embed/dQw4w9WgXcQ").is_some());
+        assert_eq!(youtube_oembed("https://www.youtube.com/watch?v=dQw4w9WgXcQ"), None);
+    }
+}
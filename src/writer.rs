@@ -0,0 +1,495 @@
+use std::collections::BTreeMap;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time;
+use failure::Error;
+use reqwest::StatusCode;
+use tokio::sync::mpsc;
+use crate::failures::{self, Failure};
+use crate::progress::Bar;
+use crate::{Accept, Checked, Checker, CheckerError, Results, TimeoutPhase, host_of, redirect_chain, trivial_redirect};
+
+/// Write the results at most this often, so a run doesn't rewrite the whole file for every url
+const FLUSH_INTERVAL: time::Duration = time::Duration::from_secs(5);
+
+/// ... unless this many checks finished since the last time
+const FLUSH_EVERY: usize = 100;
+
+/// How often to look for a signal when no check finishes
+const TICK: time::Duration = time::Duration::from_millis(500);
+
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+
+/// Whether to color the line for each check
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Color {
+    /// When the lines go to a terminal
+    Auto,
+    Always,
+    Never,
+}
+
+impl std::str::FromStr for Color {
+    type Err = String;
+
+    fn from_str(color: &str) -> Result<Color, String> {
+        match color {
+            "auto" => Ok(Color::Auto),
+            "always" => Ok(Color::Always),
+            "never" => Ok(Color::Never),
+            _ => Err(format!("unknown color mode {}", color)),
+        }
+    }
+}
+
+impl Color {
+    fn enabled(self, stream: impl IsTerminal) -> bool {
+        match self {
+            Color::Auto => stream.is_terminal(),
+            Color::Always => true,
+            Color::Never => false,
+        }
+    }
+}
+
+/// How a check went
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mark {
+    Working,
+    Failed,
+    /// Neither, with the word for what it is instead
+    Doubtful(&'static str),
+}
+
+/// Resolves when we're told to stop, on Ctrl-C or when CI kills the job, with the exit code a
+/// shell gives a process the signal killed
+async fn stopped() -> i32 {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate()).expect("Couldn't listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => 130,
+            _ = terminate.recv() => 143,
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        130
+    }
+}
+
+/// Once we listened for a signal, it doesn't stop the process by itself anymore, so this does
+/// what it did before
+fn stop_on_signals() {
+    tokio::spawn(async {
+        std::process::exit(stopped().await);
+    });
+}
+
+/// The results, with what the checks of this run found so far
+pub struct Recorder {
+    pub results: Results,
+    /// How many of the failures won't go away by trying again
+    pub permanent: usize,
+    /// The urls which work, in the order they were checked
+    pub newly_working: Vec<String>,
+    checker: Arc<Checker>,
+    /// Where each url is in the files, and the sections it's in, for the failures
+    locations: BTreeMap<String, Vec<String>>,
+    link_sections: BTreeMap<String, Vec<String>>,
+    bar: Option<Bar>,
+}
+
+/// Owns the results while the checks run, on a task of its own, and writes them to disk every
+/// few seconds, at the end and when we're interrupted. Signals only stop the run after that.
+pub struct Writer {
+    sender: mpsc::UnboundedSender<(String, Result<Checked, CheckerError>)>,
+    task: tokio::task::JoinHandle<Result<Recorder, Error>>,
+    path: Option<PathBuf>,
+}
+
+impl Writer {
+    pub fn spawn(results: Results, checker: Arc<Checker>, locations: BTreeMap<String, Vec<String>>,
+                 link_sections: BTreeMap<String, Vec<String>>, path: Option<PathBuf>, bar: Option<Bar>) -> Writer {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let mut recorder = Recorder {results, permanent: 0, newly_working: vec![], checker, locations, link_sections, bar};
+        let task_path = path.clone();
+        let task = tokio::spawn(async move {
+            let path = task_path;
+            let mut pending = 0;
+            let mut flushed = time::Instant::now();
+            let stop = stopped();
+            tokio::pin!(stop);
+            recorder.draw_bar();
+            loop {
+                tokio::select! {
+                    code = &mut stop => {
+                        recorder.results.save(path.as_deref())?;
+                        if let Some(bar) = &mut recorder.bar {
+                            bar.clear();
+                        }
+                        eprintln!();
+                        eprintln!("Interrupted, results saved");
+                        std::process::exit(code);
+                    }
+                    next = async_std::future::timeout(TICK, receiver.recv()) => match next {
+                        Ok(Some((url, res))) => {
+                            recorder.record(url, res);
+                            pending += 1;
+                        }
+                        // Nothing more to come
+                        Ok(None) => break,
+                        // Keeps the ETA going while the checks in flight take their time
+                        Err(_) => recorder.draw_bar(),
+                    },
+                }
+                if pending >= FLUSH_EVERY || (pending > 0 && flushed.elapsed() >= FLUSH_INTERVAL) {
+                    recorder.results.save(path.as_deref())?;
+                    pending = 0;
+                    flushed = time::Instant::now();
+                }
+            }
+            recorder.results.save(path.as_deref())?;
+            if let Some(bar) = &mut recorder.bar {
+                bar.clear();
+            }
+            stop_on_signals();
+            Ok(recorder)
+        });
+        Writer {sender, task, path}
+    }
+
+    /// Hands over the outcome of a check
+    pub fn send(&self, url: String, res: Result<Checked, CheckerError>) {
+        // The writer only stops early if saving failed, which finish() reports
+        let _ = self.sender.send((url, res));
+    }
+
+    /// Waits for everything sent so far to be written, along with the urls we didn't get to
+    /// before the deadline, which the next run checks first
+    pub async fn finish(self, unchecked: Vec<String>) -> Result<Recorder, Error> {
+        drop(self.sender);
+        let mut recorder = self.task.await??;
+        if !unchecked.is_empty() {
+            recorder.results.unchecked.extend(unchecked);
+            recorder.results.save(self.path.as_deref())?;
+        }
+        Ok(recorder)
+    }
+}
+
+impl Recorder {
+    /// Puts the outcome of a check into the results
+    fn record(&mut self, url: String, res: Result<Checked, CheckerError>) {
+        match res {
+            Ok(Checked {suspect: Some(reason), ..}) => {
+                self.progress(Mark::Doubtful("suspect"), &format!("{} ({})", url, reason));
+                // Or the next run gets a 304 for the validators of when it worked, and takes it
+                // for working again without looking at the page
+                self.results.forget_checks(&url);
+                self.results.suspect.insert(url, reason);
+            }
+            Ok(checked) => {
+                self.progress(Mark::Working, &format!("{} ({})", url, checked.success.status));
+                let mut warnings = vec![];
+                if let Some(ref reason) = checked.fallbacks.insecure {
+                    warnings.push(format!("invalid certificate tolerated: {}", reason));
+                }
+                // The certificate that matters is the one of the page we ended up on
+                if let Some(expiry) = self.checker.expires_soon(checked.chain.last().unwrap_or(&url)) {
+                    warnings.push(format!("certificate expires {}", httpdate::fmt_http_date(expiry)));
+                }
+                if checked.archived {
+                    warnings.push("repository is archived".to_string());
+                }
+                if let Some(status) = checked.accepted {
+                    warnings.push(format!("accepted status {}", status.as_u16()));
+                }
+                if warnings.is_empty() {
+                    self.results.warnings.remove(&url);
+                } else {
+                    self.results.warnings.insert(url.clone(), warnings);
+                }
+                let trivial = checked.chain.last().filter(|target| trivial_redirect(&url, target)).cloned();
+                if checked.chain.is_empty() || trivial.is_some() {
+                    self.results.redirected.remove(&url);
+                } else {
+                    self.results.redirected.insert(url.clone(), checked.chain);
+                }
+                if checked.fallbacks.get {
+                    self.results.needs_get.insert(host_of(&url));
+                }
+                if checked.fallbacks.browser {
+                    self.results.needs_browser.insert(url.clone());
+                } else {
+                    self.results.needs_browser.remove(&url);
+                }
+                if checked.fallbacks.accept == Accept::Html {
+                    self.results.needs_accept.remove(&url);
+                } else {
+                    self.results.needs_accept.insert(url.clone(), checked.fallbacks.accept.to_string());
+                }
+                match checked.badge {
+                    Some(badge) => self.results.badges.insert(url.clone(), badge),
+                    None => self.results.badges.remove(&url),
+                };
+                match checked.upgrade.or(trivial) {
+                    Some(upgrade) => self.results.suggestions.insert(url.clone(), upgrade),
+                    None => self.results.suggestions.remove(&url),
+                };
+                match checked.moved {
+                    Some(moved) => self.results.moved_to_https.insert(url.clone(), moved),
+                    None => self.results.moved_to_https.remove(&url),
+                };
+                match checked.missing_anchor {
+                    Some(anchor) => self.results.missing_anchors.insert(url.clone(), anchor),
+                    None => self.results.missing_anchors.remove(&url),
+                };
+                match checked.stale_workflow {
+                    Some(repository) => self.results.stale_workflows.insert(url.clone(), repository),
+                    None => self.results.stale_workflows.remove(&url),
+                };
+                if checked.validators.is_empty() {
+                    self.results.validators.remove(&url);
+                } else {
+                    self.results.validators.insert(url.clone(), checked.validators);
+                }
+                if self.checker.opt.verbose_results {
+                    self.results.working_details.insert(url.clone(), checked.success);
+                }
+                self.newly_working.push(url.clone());
+                let now = time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap_or_default().as_secs();
+                self.results.last_checked.insert(url.clone(), now);
+                self.results.working.insert(url);
+            },
+            Err(CheckerError::TemporarilyUnavailable {retry_after}) => {
+                let message = format!("retry after {}s", retry_after.as_secs());
+                self.progress(Mark::Doubtful("unavailable"), &format!("{} ({})", url, message));
+                self.results.unavailable.insert(url, message);
+            }
+            Err(CheckerError::Unverifiable {reason}) if !self.checker.opt.strict => {
+                self.progress(Mark::Doubtful("unverifiable"), &format!("{} ({})", url, reason));
+                self.results.unverifiable.insert(url, reason);
+            }
+            Err(CheckerError::AccessRestricted {login}) if !self.checker.opt.strict => {
+                self.progress(Mark::Doubtful("login required"), &format!("{} -> {}", url, login));
+                self.results.access_restricted.insert(url, login);
+            }
+            Err(err) => {
+                // It worked the last time, but not anymore
+                self.results.forget_checks(&url);
+                if err.is_permanent(&self.checker.retries) {
+                    self.permanent += 1;
+                }
+                let mut chain = vec![];
+                let mut status = None;
+                let (kind, message) = match err {
+                    CheckerError::HttpError {status: code, chain: hops} => {
+                        let message = match code {
+                            StatusCode::GONE => format!("[410 gone] {}", redirect_chain(&url, &hops)),
+                            _ => format!("[{}] {}", code.as_u16(), redirect_chain(&url, &hops)),
+                        };
+                        status = Some(code.as_u16());
+                        chain = hops;
+                        (failures::Kind::Http, message)
+                    }
+                    CheckerError::RedirectLoop {chain: hops} => {
+                        let message = format!("[redirect loop] {}", redirect_chain(&url, &hops));
+                        chain = hops;
+                        (failures::Kind::RedirectLoop, message)
+                    }
+                    CheckerError::DnsError {host} => {
+                        (failures::Kind::Dns, format!("[DNS] no such host {}: {}", host, url))
+                    }
+                    CheckerError::Timeout {phase} => {
+                        let timeout = match phase {
+                            TimeoutPhase::Connect => time::Duration::from_secs(self.checker.opt.connect_timeout),
+                            TimeoutPhase::Total => self.checker.timeout(&url),
+                        };
+                        (failures::Kind::Timeout, format!("[{} timeout after {}s] {}", phase, timeout.as_secs(), url))
+                    }
+                    CheckerError::ConnectionRefused => {
+                        (failures::Kind::ConnectionRefused, format!("[connection refused] {}", url))
+                    }
+                    CheckerError::ConnectionReset => {
+                        (failures::Kind::ConnectionReset, format!("[connection reset] {}", url))
+                    }
+                    CheckerError::Unverifiable {reason} => {
+                        (failures::Kind::Unverifiable, format!("[unverifiable, {}] {}", reason, url))
+                    }
+                    CheckerError::AccessRestricted {login} => {
+                        (failures::Kind::LoginRequired, format!("[login required] {} -> {}", url, login))
+                    }
+                    CheckerError::WrongContentType {expected, got} => {
+                        (failures::Kind::NotAnImage, format!("[not an image, expected {} but got {}] {}", expected, got, url))
+                    }
+                    CheckerError::MissingAnchor {anchor} => {
+                        (failures::Kind::MissingAnchor, format!("[no anchor #{}] {}", anchor, url))
+                    }
+                    CheckerError::MangledUrl {repaired} => {
+                        // Encoded, the parentheses can't end the link
+                        let escaped = repaired.replace('(', "%28").replace(')', "%29");
+                        status = Some(404);
+                        (failures::Kind::MangledUrl, format!("[404, mangled url] {} should be {}", url, escaped))
+                    }
+                    CheckerError::BrokenBadge {text} => {
+                        (failures::Kind::BrokenBadge, format!("[badge says \"{}\"] {}", text, url))
+                    }
+                    CheckerError::TlsError {reason} => {
+                        (failures::Kind::Tls, format!("[TLS] {}: {}", reason, url))
+                    }
+                    CheckerError::RateLimited => {
+                        status = Some(429);
+                        (failures::Kind::RateLimited, format!("[rate limited] {}", url))
+                    }
+                    CheckerError::CircuitOpen {host} => {
+                        (failures::Kind::HostUnreachable, format!("[skipped, {} unreachable] {}", host, url))
+                    }
+                    CheckerError::TooManyRedirects {chain: hops} => {
+                        let message = format!("[too many redirects] {}", redirect_chain(&url, &hops));
+                        chain = hops;
+                        (failures::Kind::TooManyRedirects, message)
+                    }
+                    _ => {
+                        (failures::Kind::Other, format!("{:?}", err))
+                    }
+                };
+                let mut failure = Failure::new(kind, message);
+                failure.status = status;
+                failure.attempts = self.checker.attempts(std::iter::once(&url).chain(&chain));
+                failure.redirect_chain = chain;
+                failure.locations = self.locations.get(&url).cloned().unwrap_or_default();
+                failure.sections = self.link_sections.get(&url).cloned().unwrap_or_default();
+                // As it happens, so a long run shows what's wrong before it's over
+                self.progress(Mark::Failed, &failure.message);
+                self.results.failed.insert(url, failure);
+            }
+        }
+    }
+
+    /// Shows how a check went, on stderr when stdout is for a report in another format
+    fn progress(&mut self, mark: Mark, text: &str) {
+        let stdout = self.checker.opt.text_on_stdout();
+        let line = if stdout { self.line(mark, text, io::stdout()) } else { self.line(mark, text, io::stderr()) };
+        // Above the progress bar, which goes back under it
+        if let Some(bar) = &mut self.bar {
+            bar.clear();
+            bar.finished(mark == Mark::Failed, time::Instant::now());
+        }
+        if stdout {
+            println!("{}", line);
+        } else {
+            eprintln!("{}", line);
+        }
+        self.draw_bar();
+    }
+
+    /// In color with a glyph on a terminal, as a plain record with a word instead of the glyph
+    /// otherwise
+    fn line(&self, mark: Mark, text: &str, stream: impl IsTerminal) -> String {
+        if self.checker.opt.color.enabled(stream) {
+            let (color, glyph) = match mark {
+                Mark::Working => (GREEN, "\u{2714}"),
+                Mark::Failed => (RED, "\u{2718}"),
+                Mark::Doubtful(_) => (YELLOW, "?"),
+            };
+            format!("{}{}{} {}", color, glyph, RESET, text)
+        } else {
+            let word = match mark {
+                Mark::Working => "ok",
+                Mark::Failed => "failed",
+                Mark::Doubtful(word) => word,
+            };
+            format!("{} {}", word, text)
+        }
+    }
+
+    fn draw_bar(&mut self) {
+        if let Some(bar) = &mut self.bar {
+            bar.draw(self.checker.in_flight.load(Ordering::Relaxed));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::StructOpt;
+    use crate::Opt;
+
+    fn recorder(args: &[&str]) -> Recorder {
+        let args = std::iter::once("awesome-rust").chain(args.iter().cloned());
+        let checker = Arc::new(Checker::new(Opt::from_iter(args)).unwrap());
+        Recorder {results: Results::new(), permanent: 0, newly_working: vec![], checker, locations: BTreeMap::new(), link_sections: BTreeMap::new(), bar: None}
+    }
+
+    #[tokio::test]
+    async fn results_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("results.yaml");
+        let mut locations = BTreeMap::new();
+        locations.insert("https://b.example/".to_string(), vec!["README.md:3:1".to_string()]);
+        let writer = Writer::spawn(Results::new(), recorder(&[]).checker, locations, BTreeMap::new(), Some(path.clone()), None);
+        writer.send("https://a.example/".to_string(), Ok(Checked::default()));
+        writer.send("https://b.example/".to_string(), Err(CheckerError::DnsError {host: "b.example".to_string()}));
+        let recorder = writer.finish(vec!["https://c.example/".to_string()]).await.unwrap();
+        assert_eq!(recorder.permanent, 1);
+        assert_eq!(recorder.newly_working, vec!["https://a.example/"]);
+        let saved = Results::load(Some(&path), false).unwrap();
+        assert!(saved.working.contains("https://a.example/"));
+        assert_eq!(saved.failed["https://b.example/"].to_string(), "[DNS] no such host b.example: https://b.example/ (README.md:3:1)");
+        assert!(saved.unchecked.contains("https://c.example/"));
+    }
+
+    #[test]
+    fn suspect_urls_stop_working() {
+        let mut recorder = recorder(&["--color", "never"]);
+        let url = "https://parked.example/".to_string();
+        let validators = crate::Validators {etag: Some("\"1\"".to_string()), last_modified: None};
+        recorder.record(url.clone(), Ok(Checked {validators, ..Checked::default()}));
+        assert!(recorder.results.working.contains(&url) && recorder.results.validators.contains_key(&url));
+        recorder.record(url.clone(), Ok(Checked {suspect: Some("parked domain (Sedo)".to_string()), ..Checked::default()}));
+        assert!(!recorder.results.working.contains(&url));
+        assert!(!recorder.results.validators.contains_key(&url));
+        assert!(!recorder.results.last_checked.contains_key(&url));
+        assert_eq!(recorder.results.suspect[&url], "parked domain (Sedo)");
+    }
+
+    #[test]
+    fn failures_drop_what_worked() {
+        let mut recorder = recorder(&["--color", "never"]);
+        let url = "http://moved.example/".to_string();
+        let chain = vec!["https://moved.example/new".to_string()];
+        recorder.record(url.clone(), Ok(Checked {chain, upgrade: Some("https://moved.example/".to_string()), ..Checked::default()}));
+        assert!(recorder.results.redirected.contains_key(&url) && recorder.results.suggestions.contains_key(&url));
+        recorder.record(url.clone(), Err(CheckerError::ConnectionRefused));
+        assert!(!recorder.results.working.contains(&url));
+        // Or the reports still say it works, but redirects, and suggest to replace it
+        assert!(!recorder.results.redirected.contains_key(&url));
+        assert!(!recorder.results.suggestions.contains_key(&url));
+        assert_eq!(recorder.results.failed[&url].to_string(), "[connection refused] http://moved.example/");
+    }
+
+    #[test]
+    fn progress_lines() {
+        // Whatever the tests run in, these don't look at it
+        let plain = recorder(&["--color", "never"]);
+        assert_eq!(plain.checker.opt.color, Color::Never);
+        assert_eq!(plain.line(Mark::Working, "https://a.example/ (200 OK)", io::stdout()), "ok https://a.example/ (200 OK)");
+        assert_eq!(plain.line(Mark::Failed, "[404] https://b.example/", io::stdout()), "failed [404] https://b.example/");
+        assert_eq!(plain.line(Mark::Doubtful("login required"), "https://c.example/ -> https://c.example/login", io::stdout()), "login required https://c.example/ -> https://c.example/login");
+        let colored = recorder(&["--color", "always"]);
+        assert_eq!(colored.line(Mark::Working, "https://a.example/ (200 OK)", io::stdout()), "\x1b[32m\u{2714}\x1b[0m https://a.example/ (200 OK)");
+        assert_eq!(colored.line(Mark::Failed, "[404] https://b.example/", io::stdout()), "\x1b[31m\u{2718}\x1b[0m [404] https://b.example/");
+        assert_eq!(colored.line(Mark::Doubtful("suspect"), "https://d.example/ (parked)", io::stdout()), "\x1b[33m?\x1b[0m https://d.example/ (parked)");
+        assert_eq!(recorder(&[]).checker.opt.color, Color::Auto);
+        assert!("sometimes".parse::<Color>().is_err());
+    }
+}